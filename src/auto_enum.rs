@@ -0,0 +1,559 @@
+//! Support for `#[cain::auto_enum]`, which lets a function that returns `impl Trait`
+//! return values of different types.
+//!
+//! The body of the function is transformed like a `cain!` block, so that the branch points
+//! before the tail expression fork it. Every value that the function returns, from the
+//! arms of its tail expression and from `return` expressions, is then wrapped in its own
+//! variant of a local enum, which implements the traits of the return type by delegating
+//! to the value in the variant. For `Result<impl Trait, E>`, only the `Ok` values are
+//! wrapped.
+//!
+//! A procedural macro cannot see the methods of a trait, so only a fixed set of traits from
+//! the standard library can be implemented. Auto traits and lifetime bounds hold for the
+//! enum whenever they hold for all of its values.
+//!
+//! The "enum" strategy of `cain!` uses the same enum for the values of branching `let`
+//! statements, with the traits of the dispatch type.
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote, ToTokens};
+use syn::visit_mut::VisitMut;
+
+use crate::dispatch::unwrap_block;
+use crate::macros;
+use crate::options::Options;
+use crate::util::{drain_filter, is_diverging, is_diverging_macro};
+use crate::warnings::{self, Warning};
+
+const ENUM: &str = "__CainEnum";
+const VARIANT: &str = "__cain_variant";
+
+/// The traits that the enum can implement.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Trait {
+    Iterator,
+    DoubleEndedIterator,
+    ExactSizeIterator,
+    FusedIterator,
+    Future,
+    Debug,
+    Display,
+    Error,
+}
+
+impl Trait {
+    fn from_ident(ident: &syn::Ident) -> Option<Trait> {
+        Some(match &*ident.to_string() {
+            "Iterator" => Trait::Iterator,
+            "DoubleEndedIterator" => Trait::DoubleEndedIterator,
+            "ExactSizeIterator" => Trait::ExactSizeIterator,
+            "FusedIterator" => Trait::FusedIterator,
+            "Future" => Trait::Future,
+            "Debug" => Trait::Debug,
+            "Display" => Trait::Display,
+            "Error" => Trait::Error,
+            _ => return None,
+        })
+    }
+
+    /// The supertraits that the enum has to implement as well.
+    fn supertraits(self) -> &'static [Trait] {
+        match self {
+            Trait::DoubleEndedIterator | Trait::ExactSizeIterator | Trait::FusedIterator => {
+                &[Trait::Iterator]
+            }
+            Trait::Error => &[Trait::Debug, Trait::Display],
+            _ => &[],
+        }
+    }
+}
+
+/// Whether the function returns `impl Trait` or `Result<impl Trait, E>`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Shape {
+    Plain,
+    Result,
+}
+
+pub fn auto_enum(args: TokenStream, item: syn::ItemFn) -> syn::Result<TokenStream> {
+    if !args.is_empty() {
+        return Err(syn::Error::new_spanned(
+            args,
+            "#[cain::auto_enum] does not take any arguments",
+        ));
+    }
+
+    let (mut item, warnings) = expand(item, &Options::load_defaults()?)?;
+    let body = warnings::emit(warnings, item.block.into_token_stream());
+    item.block = Box::new(syn::parse2(body)?);
+    Ok(item.into_token_stream())
+}
+
+/// Transform a function, and return it together with the warnings of its body.
+pub fn expand(
+    mut item: syn::ItemFn,
+    defaults: &Options,
+) -> syn::Result<(syn::ItemFn, Vec<Warning>)> {
+    let (shape, bounds) = return_bounds(&item.sig.output)?;
+    let traits = traits(bounds, "#[cain::auto_enum]", "the returned values")?;
+
+    // `syn` keeps the inner attributes of the body with the other attributes of the function
+    let options = drain_filter(&mut item.attrs, |attr| {
+        matches!(attr.style, syn::AttrStyle::Inner(_)) && attr.path.is_ident("cain")
+    });
+    let stmts = &item.block.stmts;
+    let (output, mut warnings) = macros::expand(quote! { #(#options)* #(#stmts)* }, defaults)?;
+    let mut body: syn::Block = syn::parse2(output)?;
+
+    // a function without branch points may still return values of different types
+    warnings.retain(|warning| warning.message != warnings::NO_BRANCH_POINTS);
+
+    let mut values = 0;
+    wrap_returned_values(&mut body.clone(), shape, &mut |expr| {
+        values += 1;
+        expr
+    });
+
+    // a single value does not need an enum
+    if values > 1 {
+        let mut variants = 0;
+        wrap_returned_values(&mut body, shape, &mut |expr| {
+            let variant = format_ident!("V{}", variants);
+            variants += 1;
+            let name = format_ident!("{}", ENUM);
+            syn::parse_quote! { #name::#variant(#expr) }
+        });
+
+        let items = enum_items(variants, &traits);
+        body.stmts.splice(0..0, items);
+    }
+
+    item.block = Box::new(body);
+    Ok((item, warnings))
+}
+
+/// Returns the traits in `bounds` that the enum implements, together with their
+/// supertraits.
+fn traits<'a>(
+    bounds: impl IntoIterator<Item = &'a syn::TypeParamBound>,
+    macro_name: &str,
+    values: &str,
+) -> syn::Result<Vec<Trait>> {
+    let mut traits = Vec::new();
+    for bound in bounds {
+        let trait_bound = match bound {
+            syn::TypeParamBound::Trait(trait_bound) => trait_bound,
+            syn::TypeParamBound::Lifetime(_) => continue,
+        };
+        let ident = match trait_bound.path.segments.last() {
+            Some(segment) => &segment.ident,
+            None => continue,
+        };
+
+        if let Some(tr) = Trait::from_ident(ident) {
+            traits.push(tr);
+            traits.extend(tr.supertraits());
+        } else if !is_auto_trait(ident) {
+            return Err(syn::Error::new_spanned(
+                trait_bound,
+                format!(
+                    "{} cannot implement `{}` for {}, only `Iterator`, `DoubleEndedIterator`, `ExactSizeIterator`, `FusedIterator`, `Future`, `Debug`, `Display` and `Error` are supported",
+                    macro_name, ident, values
+                ),
+            ));
+        }
+    }
+    traits.sort();
+    traits.dedup();
+
+    Ok(traits)
+}
+
+/// Wrap an arm value of a branching `let` statement with the "enum" strategy, so that
+/// [`declare_variants`] can put it in its own variant.
+pub fn wrap_variant(expr: syn::Expr) -> syn::Expr {
+    let variant = format_ident!("{}", VARIANT);
+    syn::parse_quote! { #variant(#expr) }
+}
+
+/// Give every value that was wrapped by [`wrap_variant`] a variant of its own, and declare
+/// the enum, which implements the traits of the dispatch type, at the start of `expr`.
+pub fn declare_variants(dispatch: &syn::TypeReference, expr: syn::Expr) -> syn::Result<syn::Expr> {
+    let bounds = match &*dispatch.elem {
+        syn::Type::TraitObject(trait_object) => &trait_object.bounds,
+        _ => unreachable!(),
+    };
+    let traits = traits(
+        bounds,
+        "cain!",
+        "the values of branching `let` statements with the \"enum\" strategy",
+    )?;
+
+    let mut values = 0;
+    VariantVisitor {
+        wrap: &mut |value| {
+            values += 1;
+            value
+        },
+    }
+    .visit_expr_mut(&mut expr.clone());
+
+    let mut expr = expr;
+    if values <= 1 {
+        // a single value does not need an enum
+        VariantVisitor {
+            wrap: &mut |value| value,
+        }
+        .visit_expr_mut(&mut expr);
+        return Ok(expr);
+    }
+
+    let mut variants = 0;
+    VariantVisitor {
+        wrap: &mut |value| {
+            let variant = format_ident!("V{}", variants);
+            variants += 1;
+            let name = format_ident!("{}", ENUM);
+            syn::parse_quote! { #name::#variant(#value) }
+        },
+    }
+    .visit_expr_mut(&mut expr);
+
+    let items = enum_items(variants, &traits);
+    Ok(syn::parse_quote! {
+        {
+            #(#items)*
+            #expr
+        }
+    })
+}
+
+/// Replaces the values that were wrapped by [`wrap_variant`]. Diverging values are left
+/// alone, since their variant would not get a type.
+struct VariantVisitor<'a, F> {
+    wrap: &'a mut F,
+}
+
+impl<'a, F: FnMut(syn::Expr) -> syn::Expr> VisitMut for VariantVisitor<'a, F> {
+    fn visit_expr_mut(&mut self, i: &mut syn::Expr) {
+        let value = match i {
+            syn::Expr::Call(call)
+                if call.args.len() == 1
+                    && matches!(&*call.func, syn::Expr::Path(expr_path) if expr_path.path.is_ident(VARIANT)) =>
+            {
+                let mut value = call.args[0].clone();
+                unwrap_block(&mut value);
+                value
+            }
+            _ => return syn::visit_mut::visit_expr_mut(self, i),
+        };
+
+        *i = if is_diverging(&value) {
+            value
+        } else {
+            (self.wrap)(value)
+        };
+    }
+}
+
+/// Wrap the values that the body of a function returns, from its tail expression and from
+/// `return` expressions.
+fn wrap_returned_values(
+    body: &mut syn::Block,
+    shape: Shape,
+    wrap: &mut impl FnMut(syn::Expr) -> syn::Expr,
+) {
+    if let Some(syn::Stmt::Expr(tail)) = body.stmts.last_mut() {
+        wrap_values(tail, shape, wrap);
+    }
+    let mut visitor = ReturnVisitor { shape, wrap };
+    visitor.visit_block_mut(body);
+}
+
+/// Returns the bounds of the `impl Trait` type that a function returns.
+fn return_bounds(
+    output: &syn::ReturnType,
+) -> syn::Result<(
+    Shape,
+    &syn::punctuated::Punctuated<syn::TypeParamBound, syn::Token![+]>,
+)> {
+    let ty = match output {
+        syn::ReturnType::Type(_, ty) => &**ty,
+        syn::ReturnType::Default => {
+            return Err(syn::Error::new(
+                proc_macro2::Span::call_site(),
+                "#[cain::auto_enum] expects a function that returns `impl Trait` or `Result<impl Trait, E>`",
+            ))
+        }
+    };
+
+    if let syn::Type::ImplTrait(impl_trait) = ty {
+        return Ok((Shape::Plain, &impl_trait.bounds));
+    }
+
+    let result = match ty {
+        syn::Type::Path(type_path) if type_path.qself.is_none() => type_path.path.segments.last(),
+        _ => None,
+    };
+    if let Some(segment) = result.filter(|segment| segment.ident == "Result") {
+        if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
+            if let Some(syn::GenericArgument::Type(syn::Type::ImplTrait(impl_trait))) =
+                args.args.first()
+            {
+                return Ok((Shape::Result, &impl_trait.bounds));
+            }
+        }
+    }
+
+    Err(syn::Error::new_spanned(
+        ty,
+        "#[cain::auto_enum] expects a function that returns `impl Trait` or `Result<impl Trait, E>`",
+    ))
+}
+
+fn is_auto_trait(ident: &syn::Ident) -> bool {
+    [
+        "Send",
+        "Sync",
+        "Unpin",
+        "UnwindSafe",
+        "RefUnwindSafe",
+        "Sized",
+    ]
+    .iter()
+    .any(|name| ident == name)
+}
+
+/// Wrap the values that `expr` evaluates to, following it into the arms of `match` and `if`
+/// expressions and into the tail expressions of blocks. Diverging values are left alone.
+fn wrap_values(expr: &mut syn::Expr, shape: Shape, wrap: &mut impl FnMut(syn::Expr) -> syn::Expr) {
+    match expr {
+        syn::Expr::Match(match_expr) => {
+            for arm in &mut match_expr.arms {
+                wrap_values(&mut arm.body, shape, wrap);
+            }
+        }
+
+        syn::Expr::If(if_expr) => {
+            // without an `else` branch, the value is `()`
+            if let Some((_, else_branch)) = &mut if_expr.else_branch {
+                wrap_block_value(&mut if_expr.then_branch, shape, wrap);
+                wrap_values(else_branch, shape, wrap);
+            }
+        }
+
+        syn::Expr::Block(expr_block) if expr_block.label.is_none() => {
+            wrap_block_value(&mut expr_block.block, shape, wrap);
+        }
+
+        syn::Expr::Paren(syn::ExprParen { expr, .. })
+        | syn::Expr::Group(syn::ExprGroup { expr, .. }) => wrap_values(expr, shape, wrap),
+
+        // the values of `return` expressions are wrapped where they are found
+        syn::Expr::Return(_) | syn::Expr::Break(_) | syn::Expr::Continue(_) => (),
+        syn::Expr::Macro(expr_macro) if is_diverging_macro(&expr_macro.mac) => (),
+
+        syn::Expr::Call(call) if shape == Shape::Result => match result_variant(&call.func) {
+            Some("Ok") if call.args.len() == 1 => {
+                wrap_values(&mut call.args[0], Shape::Plain, wrap);
+            }
+            Some("Err") => (),
+            _ => map_ok(expr, wrap),
+        },
+        _ if shape == Shape::Result => map_ok(expr, wrap),
+
+        _ => {
+            let value = std::mem::replace(expr, syn::Expr::Verbatim(TokenStream::new()));
+            *expr = wrap(value);
+        }
+    }
+}
+
+fn wrap_block_value(
+    block: &mut syn::Block,
+    shape: Shape,
+    wrap: &mut impl FnMut(syn::Expr) -> syn::Expr,
+) {
+    if let Some(syn::Stmt::Expr(tail)) = block.stmts.last_mut() {
+        wrap_values(tail, shape, wrap);
+    }
+}
+
+/// Wrap the `Ok` value of a `Result` that is not constructed in place.
+fn map_ok(expr: &mut syn::Expr, wrap: &mut impl FnMut(syn::Expr) -> syn::Expr) {
+    let value = std::mem::replace(expr, syn::Expr::Verbatim(TokenStream::new()));
+    let ok = format_ident!("__cain_ok");
+    let wrapped = wrap(syn::parse_quote! { #ok });
+    *expr = syn::parse_quote! { (#value).map(|#ok| #wrapped) };
+}
+
+/// Returns `Ok` or `Err` if `func` is the constructor of that variant of `Result`.
+fn result_variant(func: &syn::Expr) -> Option<&'static str> {
+    let path = match func {
+        syn::Expr::Path(expr_path) if expr_path.qself.is_none() => &expr_path.path,
+        _ => return None,
+    };
+    match path.segments.last() {
+        Some(segment) if segment.ident == "Ok" => Some("Ok"),
+        Some(segment) if segment.ident == "Err" => Some("Err"),
+        _ => None,
+    }
+}
+
+/// Wraps the values of `return` expressions, outside of closures, `async` blocks and items,
+/// which return from something else.
+struct ReturnVisitor<'a, F> {
+    shape: Shape,
+    wrap: &'a mut F,
+}
+
+impl<'a, F: FnMut(syn::Expr) -> syn::Expr> VisitMut for ReturnVisitor<'a, F> {
+    fn visit_expr_return_mut(&mut self, i: &mut syn::ExprReturn) {
+        if let Some(expr) = &mut i.expr {
+            syn::visit_mut::visit_expr_mut(self, expr);
+            wrap_values(expr, self.shape, self.wrap);
+        }
+    }
+
+    fn visit_expr_closure_mut(&mut self, _: &mut syn::ExprClosure) {}
+    fn visit_expr_async_mut(&mut self, _: &mut syn::ExprAsync) {}
+    fn visit_item_mut(&mut self, _: &mut syn::Item) {}
+}
+
+/// Returns the definition of the enum with `variants` variants, and its implementations of
+/// `traits`.
+fn enum_items(variants: usize, traits: &[Trait]) -> Vec<syn::Stmt> {
+    let name = format_ident!("{}", ENUM);
+    let params = (0..variants)
+        .map(|index| format_ident!("T{}", index))
+        .collect::<Vec<_>>();
+    let variants = (0..variants)
+        .map(|index| format_ident!("V{}", index))
+        .collect::<Vec<_>>();
+    let first = &params[0];
+    let others = &params[1..];
+
+    let definition = quote! {
+        enum #name<#(#params),*> {
+            #(#variants(#params)),*
+        }
+    };
+
+    // the bounds that make every value an iterator over the same items
+    let iterator_bounds = quote! {
+        #first: ::core::iter::Iterator,
+        #(#others: ::core::iter::Iterator<Item = <#first as ::core::iter::Iterator>::Item>,)*
+    };
+
+    let impls = traits.iter().map(|tr| match tr {
+        Trait::Iterator => quote! {
+            impl<#(#params),*> ::core::iter::Iterator for #name<#(#params),*>
+            where
+                #iterator_bounds
+            {
+                type Item = <#first as ::core::iter::Iterator>::Item;
+
+                fn next(&mut self) -> ::core::option::Option<Self::Item> {
+                    match self {
+                        #(Self::#variants(value) => value.next(),)*
+                    }
+                }
+
+                fn size_hint(&self) -> (usize, ::core::option::Option<usize>) {
+                    match self {
+                        #(Self::#variants(value) => value.size_hint(),)*
+                    }
+                }
+            }
+        },
+
+        Trait::DoubleEndedIterator => quote! {
+            impl<#(#params),*> ::core::iter::DoubleEndedIterator for #name<#(#params),*>
+            where
+                #iterator_bounds
+                #(#params: ::core::iter::DoubleEndedIterator,)*
+            {
+                fn next_back(&mut self) -> ::core::option::Option<Self::Item> {
+                    match self {
+                        #(Self::#variants(value) => value.next_back(),)*
+                    }
+                }
+            }
+        },
+
+        Trait::ExactSizeIterator => quote! {
+            impl<#(#params),*> ::core::iter::ExactSizeIterator for #name<#(#params),*>
+            where
+                #iterator_bounds
+                #(#params: ::core::iter::ExactSizeIterator,)*
+            {
+            }
+        },
+
+        Trait::FusedIterator => quote! {
+            impl<#(#params),*> ::core::iter::FusedIterator for #name<#(#params),*>
+            where
+                #iterator_bounds
+                #(#params: ::core::iter::FusedIterator,)*
+            {
+            }
+        },
+
+        Trait::Future => quote! {
+            impl<#(#params),*> ::core::future::Future for #name<#(#params),*>
+            where
+                #first: ::core::future::Future,
+                #(#others: ::core::future::Future<Output = <#first as ::core::future::Future>::Output>,)*
+            {
+                type Output = <#first as ::core::future::Future>::Output;
+
+                fn poll(
+                    self: ::core::pin::Pin<&mut Self>,
+                    cx: &mut ::core::task::Context<'_>,
+                ) -> ::core::task::Poll<Self::Output> {
+                    // SAFETY: the value is never moved out of the pinned enum
+                    unsafe {
+                        match self.get_unchecked_mut() {
+                            #(Self::#variants(value) => ::core::pin::Pin::new_unchecked(value).poll(cx),)*
+                        }
+                    }
+                }
+            }
+        },
+
+        Trait::Debug => quote! {
+            impl<#(#params: ::core::fmt::Debug),*> ::core::fmt::Debug for #name<#(#params),*> {
+                fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                    match self {
+                        #(Self::#variants(value) => ::core::fmt::Debug::fmt(value, f),)*
+                    }
+                }
+            }
+        },
+
+        Trait::Display => quote! {
+            impl<#(#params: ::core::fmt::Display),*> ::core::fmt::Display for #name<#(#params),*> {
+                fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                    match self {
+                        #(Self::#variants(value) => ::core::fmt::Display::fmt(value, f),)*
+                    }
+                }
+            }
+        },
+
+        Trait::Error => quote! {
+            impl<#(#params: ::std::error::Error),*> ::std::error::Error for #name<#(#params),*> {
+                fn source(&self) -> ::core::option::Option<&(dyn ::std::error::Error + 'static)> {
+                    match self {
+                        #(Self::#variants(value) => value.source(),)*
+                    }
+                }
+            }
+        },
+    });
+
+    std::iter::once(definition)
+        .chain(impls)
+        .map(|tokens| syn::Stmt::Item(syn::parse_quote! { #tokens }))
+        .collect()
+}
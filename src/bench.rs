@@ -0,0 +1,68 @@
+//! Benchmark of the expansion time of `cain!` blocks, by number of branch points and by
+//! block size.
+//!
+//! A procedural macro crate cannot export anything but its macros, so the benchmark calls
+//! [`expand`] directly from inside the crate, and is ignored by default. Run it with:
+//!
+//! ```text
+//! cargo test --release --lib bench -- --ignored --nocapture
+//! ```
+
+use std::time::{Duration, Instant};
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+
+use crate::macros::expand;
+use crate::options::Options;
+
+/// A block with `branches` branching `let` statements, each followed by `stmts` plain
+/// statements.
+fn input(strategy: &str, branches: usize, stmts: usize) -> TokenStream {
+    let lets = (0..branches).map(|n| {
+        let ident = format_ident!("v{}", n);
+        let plain = (0..stmts).map(|m| quote! { total += #ident.len() * #m; });
+        quote! {
+            let #ident = if flags[#n] { "yes" } else { "no" };
+            #(#plain)*
+        }
+    });
+
+    quote! {
+        #![cain(strategy = #strategy)]
+        let mut total = 0;
+        #(#lets)*
+        total
+    }
+}
+
+/// Returns the average time it takes to expand `input`.
+fn time(input: &TokenStream) -> Duration {
+    let mut runs = 0;
+    let start = Instant::now();
+    while runs == 0 || start.elapsed() < Duration::from_millis(200) {
+        expand(input.clone(), &Options::default()).unwrap();
+        runs += 1;
+    }
+    start.elapsed() / runs
+}
+
+#[test]
+#[ignore]
+fn expansion_time() {
+    println!();
+    println!(
+        "{:>9} {:>8} {:>6} {:>12}",
+        "strategy", "branches", "stmts", "time"
+    );
+
+    for strategy in ["duplicate", "macro"] {
+        for stmts in [1, 10, 100] {
+            for branches in [1, 2, 4, 6, 8] {
+                let input = input(strategy, branches, stmts);
+                let time = time(&input);
+                println!("{:>9} {:>8} {:>6} {:>12?}", strategy, branches, stmts, time);
+            }
+        }
+    }
+}
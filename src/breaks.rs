@@ -0,0 +1,349 @@
+use syn::visit::Visit;
+use syn::visit_mut::VisitMut;
+
+/// Returns `true` if `expr` is a `loop` or a labeled block, i.e. an expression whose
+/// value may come from `break` expressions.
+pub fn is_break_target(expr: &syn::Expr) -> bool {
+    match expr {
+        syn::Expr::Loop(_) => true,
+        syn::Expr::Block(expr_block) => expr_block.label.is_some(),
+        _ => false,
+    }
+}
+
+/// Replace the value of every exit point of a `loop` or labeled block with the result
+/// of `f`, and return the number of exit points that were found.
+///
+/// The exit points of a `loop` are all `break` expressions that target it. The exit
+/// points of a labeled block are all `break` expressions that target its label, and its
+/// tail expression. The value passed to `f` is `None` for exit points without a value.
+pub fn replace_break_values<F>(expr: &mut syn::Expr, f: F) -> usize
+where
+    F: FnMut(Option<syn::Expr>) -> syn::Expr,
+{
+    match expr {
+        syn::Expr::Loop(expr_loop) => {
+            let mut visitor = BreakVisitor::new(expr_loop.label.as_ref(), true, f);
+            visitor.visit_block_mut(&mut expr_loop.body);
+            visitor.exits.count
+        }
+
+        syn::Expr::Block(syn::ExprBlock {
+            label: Some(label),
+            block,
+            ..
+        }) => {
+            let mut visitor = BreakVisitor::new(Some(label), false, f);
+            visitor.visit_block_mut(block);
+
+            match block.stmts.last_mut() {
+                Some(syn::Stmt::Expr(tail)) => {
+                    let value = std::mem::replace(tail, syn::parse_quote! { () });
+                    *tail = visitor.replace(Some(value));
+                }
+
+                Some(syn::Stmt::Semi(
+                    syn::Expr::Break(_) | syn::Expr::Continue(_) | syn::Expr::Return(_),
+                    _,
+                )) => (),
+
+                _ => {
+                    let tail = visitor.replace(None);
+                    block.stmts.push(syn::Stmt::Expr(tail));
+                }
+            }
+
+            visitor.exits.count
+        }
+
+        _ => 0,
+    }
+}
+
+/// Returns the number of exit points that [`replace_break_values`] would find.
+pub fn count_break_values(expr: &syn::Expr) -> usize {
+    match expr {
+        syn::Expr::Loop(expr_loop) => {
+            let mut counter = BreakCounter {
+                exits: Exits::new(expr_loop.label.as_ref(), true),
+            };
+            counter.visit_block(&expr_loop.body);
+            counter.exits.count
+        }
+
+        syn::Expr::Block(syn::ExprBlock {
+            label: Some(label),
+            block,
+            ..
+        }) => {
+            let mut counter = BreakCounter {
+                exits: Exits::new(Some(label), false),
+            };
+            counter.visit_block(block);
+
+            // the tail expression, or the end of the block, is an exit point of its own
+            let falls_through = !matches!(
+                block.stmts.last(),
+                Some(syn::Stmt::Semi(
+                    syn::Expr::Break(_) | syn::Expr::Continue(_) | syn::Expr::Return(_),
+                    _,
+                ))
+            );
+            counter.exits.count + usize::from(falls_through)
+        }
+
+        _ => 0,
+    }
+}
+
+/// Returns an unlabeled `break` or `continue` expression in `stmts` that would bind to
+/// a different loop if the statements were moved into the body of a `loop`.
+pub fn find_unlabeled_control_flow(stmts: &[syn::Stmt]) -> Option<&syn::Expr> {
+    let mut visitor = ControlFlowVisitor { found: None };
+    for stmt in stmts {
+        visitor.visit_stmt(stmt);
+    }
+    visitor.found
+}
+
+/// Make every `break` expression in `body` that exits a `for` loop with the label `label`
+/// exit the loop labeled `target` instead.
+pub fn retarget_breaks(body: &mut syn::Block, label: Option<&syn::Label>, target: &syn::Lifetime) {
+    let mut visitor = RetargetVisitor {
+        label: label.map(|label| &label.name),
+        target,
+        loop_depth: 0,
+    };
+    visitor.visit_block_mut(body);
+}
+
+/// The `break` expressions that exit a `loop` or labeled block, while the nested loops
+/// and blocks are visited.
+struct Exits<'a> {
+    label: Option<&'a syn::Lifetime>,
+    unlabeled: bool,
+    loop_depth: usize,
+    count: usize,
+}
+
+impl<'a> Exits<'a> {
+    fn new(label: Option<&'a syn::Label>, unlabeled: bool) -> Exits<'a> {
+        Exits {
+            label: label.map(|label| &label.name),
+            unlabeled,
+            loop_depth: 0,
+            count: 0,
+        }
+    }
+
+    fn targets(&self, label: Option<&syn::Lifetime>) -> bool {
+        match label {
+            Some(label) => self.label == Some(label),
+            None => self.unlabeled && self.loop_depth == 0,
+        }
+    }
+
+    fn shadows(&self, label: Option<&syn::Label>) -> bool {
+        matches!((label, self.label), (Some(inner), Some(outer)) if inner.name == *outer)
+    }
+}
+
+struct BreakVisitor<'a, F> {
+    exits: Exits<'a>,
+    f: F,
+}
+
+impl<'a, F> BreakVisitor<'a, F>
+where
+    F: FnMut(Option<syn::Expr>) -> syn::Expr,
+{
+    fn new(label: Option<&'a syn::Label>, unlabeled: bool, f: F) -> BreakVisitor<'a, F> {
+        BreakVisitor {
+            exits: Exits::new(label, unlabeled),
+            f,
+        }
+    }
+
+    fn replace(&mut self, value: Option<syn::Expr>) -> syn::Expr {
+        self.exits.count += 1;
+        (self.f)(value)
+    }
+
+    fn visit_nested_loop(&mut self, label: Option<&syn::Label>, body: &mut syn::Block) {
+        if !self.exits.shadows(label) {
+            self.exits.loop_depth += 1;
+            self.visit_block_mut(body);
+            self.exits.loop_depth -= 1;
+        }
+    }
+}
+
+impl<'a, F> VisitMut for BreakVisitor<'a, F>
+where
+    F: FnMut(Option<syn::Expr>) -> syn::Expr,
+{
+    fn visit_expr_break_mut(&mut self, i: &mut syn::ExprBreak) {
+        syn::visit_mut::visit_expr_break_mut(self, i);
+
+        if self.exits.targets(i.label.as_ref()) {
+            let value = i.expr.take().map(|expr| *expr);
+            i.expr = Some(Box::new(self.replace(value)));
+        }
+    }
+
+    fn visit_expr_loop_mut(&mut self, i: &mut syn::ExprLoop) {
+        self.visit_nested_loop(i.label.as_ref(), &mut i.body);
+    }
+
+    fn visit_expr_while_mut(&mut self, i: &mut syn::ExprWhile) {
+        self.visit_expr_mut(&mut i.cond);
+        self.visit_nested_loop(i.label.as_ref(), &mut i.body);
+    }
+
+    fn visit_expr_for_loop_mut(&mut self, i: &mut syn::ExprForLoop) {
+        self.visit_expr_mut(&mut i.expr);
+        self.visit_nested_loop(i.label.as_ref(), &mut i.body);
+    }
+
+    fn visit_expr_block_mut(&mut self, i: &mut syn::ExprBlock) {
+        if !self.exits.shadows(i.label.as_ref()) {
+            self.visit_block_mut(&mut i.block);
+        }
+    }
+
+    // `break` cannot leave closures, async blocks or items
+    fn visit_expr_closure_mut(&mut self, _: &mut syn::ExprClosure) {}
+    fn visit_expr_async_mut(&mut self, _: &mut syn::ExprAsync) {}
+    fn visit_item_mut(&mut self, _: &mut syn::Item) {}
+}
+
+/// Counts the exit points like [`BreakVisitor`], without replacing their values.
+struct BreakCounter<'a> {
+    exits: Exits<'a>,
+}
+
+impl<'a> BreakCounter<'a> {
+    fn visit_nested_loop(&mut self, label: Option<&syn::Label>, body: &syn::Block) {
+        if !self.exits.shadows(label) {
+            self.exits.loop_depth += 1;
+            self.visit_block(body);
+            self.exits.loop_depth -= 1;
+        }
+    }
+}
+
+impl<'a, 'ast> Visit<'ast> for BreakCounter<'a> {
+    fn visit_expr_break(&mut self, i: &'ast syn::ExprBreak) {
+        syn::visit::visit_expr_break(self, i);
+
+        if self.exits.targets(i.label.as_ref()) {
+            self.exits.count += 1;
+        }
+    }
+
+    fn visit_expr_loop(&mut self, i: &'ast syn::ExprLoop) {
+        self.visit_nested_loop(i.label.as_ref(), &i.body);
+    }
+
+    fn visit_expr_while(&mut self, i: &'ast syn::ExprWhile) {
+        self.visit_expr(&i.cond);
+        self.visit_nested_loop(i.label.as_ref(), &i.body);
+    }
+
+    fn visit_expr_for_loop(&mut self, i: &'ast syn::ExprForLoop) {
+        self.visit_expr(&i.expr);
+        self.visit_nested_loop(i.label.as_ref(), &i.body);
+    }
+
+    fn visit_expr_block(&mut self, i: &'ast syn::ExprBlock) {
+        if !self.exits.shadows(i.label.as_ref()) {
+            self.visit_block(&i.block);
+        }
+    }
+
+    fn visit_expr_closure(&mut self, _: &'ast syn::ExprClosure) {}
+    fn visit_expr_async(&mut self, _: &'ast syn::ExprAsync) {}
+    fn visit_item(&mut self, _: &'ast syn::Item) {}
+}
+
+struct RetargetVisitor<'a> {
+    label: Option<&'a syn::Lifetime>,
+    target: &'a syn::Lifetime,
+    loop_depth: usize,
+}
+
+impl<'a> RetargetVisitor<'a> {
+    fn shadows(&self, label: Option<&syn::Label>) -> bool {
+        matches!((label, self.label), (Some(inner), Some(outer)) if inner.name == *outer)
+    }
+
+    fn visit_nested_loop(&mut self, label: Option<&syn::Label>, body: &mut syn::Block) {
+        if !self.shadows(label) {
+            self.loop_depth += 1;
+            self.visit_block_mut(body);
+            self.loop_depth -= 1;
+        }
+    }
+}
+
+impl<'a> VisitMut for RetargetVisitor<'a> {
+    fn visit_expr_break_mut(&mut self, i: &mut syn::ExprBreak) {
+        syn::visit_mut::visit_expr_break_mut(self, i);
+
+        let targets = match &i.label {
+            Some(label) => self.label == Some(label),
+            None => self.loop_depth == 0,
+        };
+        if targets {
+            i.label = Some(self.target.clone());
+        }
+    }
+
+    fn visit_expr_loop_mut(&mut self, i: &mut syn::ExprLoop) {
+        self.visit_nested_loop(i.label.as_ref(), &mut i.body);
+    }
+
+    fn visit_expr_while_mut(&mut self, i: &mut syn::ExprWhile) {
+        self.visit_expr_mut(&mut i.cond);
+        self.visit_nested_loop(i.label.as_ref(), &mut i.body);
+    }
+
+    fn visit_expr_for_loop_mut(&mut self, i: &mut syn::ExprForLoop) {
+        self.visit_expr_mut(&mut i.expr);
+        self.visit_nested_loop(i.label.as_ref(), &mut i.body);
+    }
+
+    fn visit_expr_block_mut(&mut self, i: &mut syn::ExprBlock) {
+        if !self.shadows(i.label.as_ref()) {
+            self.visit_block_mut(&mut i.block);
+        }
+    }
+
+    fn visit_expr_closure_mut(&mut self, _: &mut syn::ExprClosure) {}
+    fn visit_expr_async_mut(&mut self, _: &mut syn::ExprAsync) {}
+    fn visit_item_mut(&mut self, _: &mut syn::Item) {}
+}
+
+struct ControlFlowVisitor<'ast> {
+    found: Option<&'ast syn::Expr>,
+}
+
+impl<'ast> Visit<'ast> for ControlFlowVisitor<'ast> {
+    fn visit_expr(&mut self, i: &'ast syn::Expr) {
+        match i {
+            syn::Expr::Break(syn::ExprBreak { label: None, .. })
+            | syn::Expr::Continue(syn::ExprContinue { label: None, .. }) => {
+                self.found.get_or_insert(i);
+            }
+
+            // unlabeled `break` and `continue` in nested loops bind to those loops
+            syn::Expr::Loop(_) | syn::Expr::While(_) | syn::Expr::ForLoop(_) => (),
+
+            syn::Expr::Closure(_) | syn::Expr::Async(_) => (),
+
+            _ => syn::visit::visit_expr(self, i),
+        }
+    }
+
+    fn visit_item(&mut self, _: &'ast syn::Item) {}
+}
@@ -1,6 +1,8 @@
-use crate::macros::cain;
+use crate::macros::expand;
+use crate::options::Options;
 
 use pretty_assertions::assert_eq;
+use quote::ToTokens;
 
 macro_rules! test_cain_macro {
     (
@@ -15,7 +17,7 @@ macro_rules! test_cain_macro {
                     let input = ::quote::quote!{ $($input)* };
                     let expected_output = ::quote::quote!{ { $($output)* } };
 
-                    let actual_output = cain(input).unwrap();
+                    let (actual_output, _) = expand(input, &Options::default()).unwrap();
 
                     assert_eq!(expected_output.to_string(), actual_output.to_string());
                 })
@@ -24,6 +26,27 @@ macro_rules! test_cain_macro {
     };
 }
 
+macro_rules! test_cain_macro_error {
+    (
+        $(
+            $test_name:ident: { $($input:tt)* } => $message:literal
+        ),+ $(,)?
+    ) => {
+        $(
+            #[test]
+            fn $test_name() {
+                crate::util::in_test(|| {
+                    let input = ::quote::quote!{ $($input)* };
+
+                    let actual_error = expand(input, &Options::default()).unwrap_err();
+
+                    assert_eq!($message, actual_error.to_string());
+                })
+            }
+        )*
+    };
+}
+
 test_cain_macro! {
     empty: {} => {},
 
@@ -68,13 +91,7 @@ test_cain_macro! {
         },
 
     if_let_ident: { 1 + if let x = z { a + x } }
-        => {
-            if let __cain_ident__0 = z {
-                if { #[allow(unused_variables, unreachable_patterns)] { matches!(&__cain_ident__0, x) } } {
-                    1 + { if let (x,) = (__cain_ident__0,) { { a + x } } else { unreachable!() } }
-                }
-            }
-        },
+        => { if let x = z { 1 + { a + x } } },
 
     match_simple: { 1 + match x { 1 => a, _ => b } }
         => { match x { 1 => 1 + a, _ => 1 + b } },
@@ -94,48 +111,66 @@ test_cain_macro! {
         },
 
     match_ident_pat: { 1 + match x { r => a + r } }
-        => {
-            match x {
-                __cain_ident__0
-                    if { #[allow(unused_variables, unreachable_patterns)] { matches!(&__cain_ident__0, r) } }
-                    => 1 + if let (r,) = (__cain_ident__0,) { a + r } else { unreachable!() },
-                #[allow(unreachable_patterns, unused_variables)] r => unreachable!()
-            }
-        },
+        => { match x { r => 1 + a + r } },
 
     match_ident_pat_2: { 1 + match x { Ok(r) => a + r, Err(r) => b + r } }
+        => { match x { Ok(r) => 1 + a + r, Err(r) => 1 + b + r } },
+
+    match_ident_pat_3: { 1 + match x { (r, s) => r + s } }
+        => { match x { (r, s) => 1 + r + s } },
+
+    match_ident_pat_4: { 1 + match x { r @ s => r + s } }
+        => { match x { r @ s => 1 + r + s } },
+
+    match_ident_shadowed: { let b = match x { Some(r) => a + r, None => a }; f(b, r) }
         => {
             match x {
-                Ok(__cain_ident__0)
+                Some(__cain_ident__0)
                     if { #[allow(unused_variables, unreachable_patterns)] { matches!(&__cain_ident__0, r) } }
-                    => 1 + if let (r,) = (__cain_ident__0,) { a + r } else { unreachable!() },
-                #[allow(unreachable_patterns, unused_variables)] Ok(r) => unreachable!(),
-                Err(__cain_ident__1)
-                    if { #[allow(unused_variables, unreachable_patterns)] { matches!(&__cain_ident__1, r) } }
-                    => 1 + if let (r,) = (__cain_ident__1,) { b + r } else { unreachable!() },
-                #[allow(unreachable_patterns, unused_variables)] Err(r) => unreachable!()
+                    => {
+                        let b = if let (r,) = (__cain_ident__0,) { a + r } else { unreachable!() };
+                        f(b, r)
+                    },
+                #[allow(unreachable_patterns, unused_variables)] Some(r) => unreachable!(),
+                None => {
+                    let b = a;
+                    f(b, r)
+                }
             }
         },
 
-    match_ident_pat_3: { 1 + match x { (r, s) => r + s } }
+    match_ident_format_capture: { let b = match x { Some(r) => r, None => 0 }; println!("{r:?} {}", b) }
         => {
             match x {
-                (__cain_ident__0, __cain_ident__1)
+                Some(__cain_ident__0)
                     if { #[allow(unused_variables, unreachable_patterns)] { matches!(&__cain_ident__0, r) } }
-                        && { #[allow(unused_variables, unreachable_patterns)] { matches!(&__cain_ident__1, s) } }
-                    => 1 + if let (r, s,) = (__cain_ident__0, __cain_ident__1,) { r + s } else { unreachable!() },
-                #[allow(unreachable_patterns, unused_variables)] (r, s) => unreachable!()
+                    => {
+                        let b = if let (r,) = (__cain_ident__0,) { r } else { unreachable!() };
+                        println!("{r:?} {}", b)
+                    },
+                #[allow(unreachable_patterns, unused_variables)] Some(r) => unreachable!(),
+                None => {
+                    let b = 0;
+                    println!("{r:?} {}", b)
+                }
             }
         },
 
-    match_ident_pat_4: { 1 + match x { r @ s => r + s } }
+    if_let_shadowed: { let b = if let Some(x) = z { x } else { y }; f(b, x) }
         => {
-            match x {
-                __cain_ident__0 @ __cain_ident__1
-                    if { #[allow(unused_variables, unreachable_patterns)] { matches!(&__cain_ident__0, r) } }
-                        && { #[allow(unused_variables, unreachable_patterns)] { matches!(&__cain_ident__1, s) } }
-                    => 1 + if let (r, s,) = (__cain_ident__0, __cain_ident__1,) { r + s } else { unreachable!() },
-                #[allow(unreachable_patterns, unused_variables)] r @ s => unreachable!()
+            if let Some(__cain_ident__0) = z {
+                if { #[allow(unused_variables, unreachable_patterns)] { matches!(&__cain_ident__0, x) } } {
+                    {
+                        let b = { if let (x,) = (__cain_ident__0,) { x } else { unreachable!() } };
+                        f(b, x)
+                    }
+                } else {
+                    let b = { y };
+                    f(b, x)
+                }
+            } else {
+                let b = { y };
+                f(b, x)
             }
         },
 
@@ -437,4 +472,2370 @@ test_cain_macro! {
             }
         }
     },
+
+    loop_break_let: {
+        let z = loop {
+            if a { break 1 }
+            if b { break "s" }
+        };
+        f(z)
+    } => {
+        loop {
+            if a { break { let z = 1; { f(z) } } }
+            if b { break { let z = "s"; { f(z) } } }
+        }
+    },
+
+    loop_break_nested_loop: {
+        let z = 'outer: loop {
+            loop {
+                if a { break 'outer 1 }
+                break;
+            }
+            break "s";
+        };
+        f(z)
+    } => {
+        'outer: loop {
+            loop {
+                if a { break 'outer { let z = 1; { f(z) } } }
+                break;
+            }
+            break { let z = "s"; { f(z) } };
+        }
+    },
+
+    loop_break_match: {
+        let z = loop {
+            break match x {
+                1 => 1,
+                _ => "s"
+            };
+        };
+        f(z)
+    } => {
+        loop {
+            break {
+                match x {
+                    1 => {
+                        let z = 1;
+                        { f(z) }
+                    },
+                    _ => {
+                        let z = "s";
+                        { f(z) }
+                    }
+                }
+            };
+        }
+    },
+
+    labeled_block_let: {
+        let z = 'a: {
+            if a { break 'a 1; }
+            "s"
+        };
+        f(z)
+    } => {
+        'a: {
+            if a { break 'a { let z = 1; { f(z) } }; }
+            { let z = "s"; { f(z) } }
+        }
+    },
+
+    let_else_plain: {
+        let Some(z) = x else { return };
+        f(z)
+    } => {
+        let Some(z) = x else { return };
+        f(z)
+    },
+
+    let_else_match: {
+        let Some(z) = match x {
+            1 => Some(1),
+            _ => Some("s")
+        } else {
+            return
+        };
+        f(z)
+    } => {
+        match x {
+            1 => {
+                let Some(z) = Some(1) else { return };
+                f(z)
+            },
+            _ => {
+                let Some(z) = Some("s") else { return };
+                f(z)
+            }
+        }
+    },
+
+    let_else_parens: {
+        let Some(z) = (match x {
+            1 => Some(1),
+            _ => { Some("s") }
+        }) else {
+            return
+        };
+        f(z)
+    } => {
+        match x {
+            1 => {
+                let Some(z) = Some(1) else { return };
+                f(z)
+            },
+            _ => {
+                let Some(z) = ({ Some("s") }) else { return };
+                f(z)
+            }
+        }
+    },
+
+    let_else_after_options: {
+        #![cain(max_paths = 4)]
+        let Some(z) = (match x { 1 => Some(a), _ => None }) else { return };
+        f(z)
+    } => {
+        match x {
+            1 => {
+                let Some(z) = Some(a) else { return };
+                f(z)
+            },
+            _ => {
+                let Some(z) = None else { return };
+                f(z)
+            }
+        }
+    },
+
+    let_else_nested: {
+        let f = || {
+            let Ok(z) = x else {
+                let Some(e) = y else { panic!() };
+                return e
+            };
+            z
+        };
+    } => {
+        let f = | | {
+            let Ok(z) = x else {
+                let Some(e) = y else { panic!() };
+                return e
+            };
+            z
+        };
+    },
+
+    let_else_block_untouched: {
+        let Some(z) = x else {
+            let code = match y {
+                1 => 1,
+                _ => "2"
+            };
+            return fail(code)
+        };
+        f(z)
+    } => {
+        let Some(z) = x else {
+            let code = match y {
+                1 => 1,
+                _ => "2"
+            };
+            return fail(code)
+        };
+        f(z)
+    },
+
+    let_else_if_else: {
+        let z = if x { a } else { b };
+        f(z)
+    } => {
+        if x {
+            {
+                let z = { a };
+                f(z)
+            }
+        } else {
+            let z = { b };
+            f(z)
+        }
+    },
+
+    impl_trait_let: {
+        let w: impl Write + Send = match x {
+            1 => a,
+            _ => b
+        };
+        f(w)
+    } => {
+        match x {
+            1 => {
+                let w = {
+                    #[allow(non_snake_case)]
+                    fn __cain_arm_1_must_implement_Write_and_Send<__CainValue: Write + Send>(value: __CainValue) -> __CainValue { value }
+                    __cain_arm_1_must_implement_Write_and_Send(a)
+                };
+                f(w)
+            },
+            _ => {
+                let w = {
+                    #[allow(non_snake_case)]
+                    fn __cain_arm_2_must_implement_Write_and_Send<__CainValue: Write + Send>(value: __CainValue) -> __CainValue { value }
+                    __cain_arm_2_must_implement_Write_and_Send(b)
+                };
+                f(w)
+            }
+        }
+    },
+
+    impl_trait_nested_arms: {
+        let w: impl Write = match x {
+            1 => a,
+            2 => match y {
+                1 => b,
+                _ => c
+            },
+            _ => return
+        };
+        f(w)
+    } => {
+        match x {
+            1 => {
+                let w = {
+                    #[allow(non_snake_case)]
+                    fn __cain_arm_1_must_implement_Write<__CainValue: Write>(value: __CainValue) -> __CainValue { value }
+                    __cain_arm_1_must_implement_Write(a)
+                };
+                f(w)
+            },
+            2 => match y {
+                1 => {
+                    let w = {
+                        #[allow(non_snake_case)]
+                        fn __cain_arm_2_1_must_implement_Write<__CainValue: Write>(value: __CainValue) -> __CainValue { value }
+                        __cain_arm_2_1_must_implement_Write(b)
+                    };
+                    f(w)
+                },
+                _ => {
+                    let w = {
+                        #[allow(non_snake_case)]
+                        fn __cain_arm_2_2_must_implement_Write<__CainValue: Write>(value: __CainValue) -> __CainValue { value }
+                        __cain_arm_2_2_must_implement_Write(c)
+                    };
+                    f(w)
+                }
+            },
+            _ => return
+        }
+    },
+
+    dyn_match: {
+        dyn Display;
+        let z = match x {
+            1 => 123,
+            _ => "abc"
+        };
+        println!("{}", z);
+    } => {
+        let __cain_ident__0;
+        let __cain_ident__1;
+        let z: &dyn Display = match x {
+            1 => {
+                __cain_ident__0 = 123;
+                &__cain_ident__0
+            },
+            _ => {
+                __cain_ident__1 = "abc";
+                &__cain_ident__1
+            }
+        };
+        println!("{}", z);
+    },
+
+    dyn_match_diverging_arm: {
+        dyn Display;
+        let z = match x {
+            1 => 123,
+            2 => "abc",
+            _ => return String::new()
+        };
+        z.to_string()
+    } => {
+        let __cain_ident__0;
+        let __cain_ident__1;
+        let z: &dyn Display = match x {
+            1 => {
+                __cain_ident__0 = 123;
+                &__cain_ident__0
+            },
+            2 => {
+                __cain_ident__1 = "abc";
+                &__cain_ident__1
+            },
+            _ => return String::new()
+        };
+        z.to_string()
+    },
+
+    dyn_mut_loop: {
+        &mut dyn Write;
+        let mut w = loop {
+            if a { break f(); }
+            break g();
+        };
+        w.flush()
+    } => {
+        let mut __cain_ident__0;
+        let mut __cain_ident__1;
+        let mut w: &mut dyn Write = loop {
+            if a {
+                break {
+                    __cain_ident__0 = f();
+                    &mut __cain_ident__0
+                };
+            }
+            break {
+                __cain_ident__1 = g();
+                &mut __cain_ident__1
+            };
+        };
+        w.flush()
+    },
+
+    dyn_no_branch: {
+        dyn Display;
+        let (a, b) = f();
+        let _ = match x {
+            1 => 123,
+            _ => "abc"
+        };
+        g(a, b)
+    } => {
+        let (a, b) = f();
+        let _ = match x {
+            1 => 123,
+            _ => "abc"
+        };
+        g(a, b)
+    },
+
+    dyn_max_paths: {
+        dyn Display, max_paths = 2;
+        let a = match x {
+            1 => 123,
+            _ => "abc"
+        };
+        let b = match y {
+            1 => 456,
+            _ => "def"
+        };
+        f(a, b)
+    } => {
+        match x {
+            1 => {
+                let a = 123;
+                let __cain_ident__0;
+                let __cain_ident__1;
+                let b: &dyn Display = match y {
+                    1 => {
+                        __cain_ident__0 = 456;
+                        &__cain_ident__0
+                    },
+                    _ => {
+                        __cain_ident__1 = "def";
+                        &__cain_ident__1
+                    }
+                };
+                f(a, b)
+            },
+            _ => {
+                let a = "abc";
+                let __cain_ident__0;
+                let __cain_ident__1;
+                let b: &dyn Display = match y {
+                    1 => {
+                        __cain_ident__0 = 456;
+                        &__cain_ident__0
+                    },
+                    _ => {
+                        __cain_ident__1 = "def";
+                        &__cain_ident__1
+                    }
+                };
+                f(a, b)
+            }
+        }
+    },
+
+    options_enum_strategy: {
+        #![cain(max_paths = 64, strategy = "enum", transparent_macros(log, trace))]
+        #![cain(dispatch = "dyn Display")]
+        let a = match x {
+            1 => 123,
+            2 => return None,
+            _ => "abc"
+        };
+        f(a)
+    } => {
+        let a = {
+            enum __CainEnum<T0, T1> {
+                V0(T0),
+                V1(T1)
+            }
+            impl<T0: ::core::fmt::Display, T1: ::core::fmt::Display> ::core::fmt::Display for __CainEnum<T0, T1> {
+                fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                    match self {
+                        Self::V0(value) => ::core::fmt::Display::fmt(value, f),
+                        Self::V1(value) => ::core::fmt::Display::fmt(value, f),
+                    }
+                }
+            }
+            match x {
+                1 => __CainEnum::V0(123),
+                2 => return None,
+                _ => __CainEnum::V1("abc")
+            }
+        };
+        f(a)
+    },
+
+    nested_arm_branches: {
+        let a = match x {
+            1 => match y {
+                1 => 123,
+                _ => 456
+            },
+            _ => if z { "abc" } else if w { 'd' } else { 7.0 }
+        };
+        f(a)
+    } => {
+        match x {
+            1 => match y {
+                1 => {
+                    let a = 123;
+                    f(a)
+                },
+                _ => {
+                    let a = 456;
+                    f(a)
+                }
+            },
+            _ => if z {
+                {
+                    let a = { "abc" };
+                    f(a)
+                }
+            } else if w {
+                {
+                    let a = { 'd' };
+                    f(a)
+                }
+            } else {
+                let a = { 7.0 };
+                f(a)
+            }
+        }
+    },
+
+    hybrid_nested_arms: {
+        #![cain(strategy = "hybrid", dispatch = "dyn Display", max_paths = 3)]
+        let a = match x {
+            1 => match y {
+                1 => 123,
+                _ => 456
+            },
+            _ => "abc"
+        };
+        f(a)
+    } => {
+        match x {
+            1 => match y {
+                1 => {
+                    let a = 123;
+                    f(a)
+                },
+                _ => {
+                    let a = 456;
+                    f(a)
+                }
+            },
+            _ => {
+                let a = "abc";
+                f(a)
+            }
+        }
+    },
+
+    hybrid_enum_fallback: {
+        #![cain(strategy = "hybrid", fallback = "enum", dispatch = "dyn Display", max_paths = 2)]
+        let a = match x {
+            1 => 123,
+            _ => "abc"
+        };
+        let b = match y {
+            1 => 456,
+            _ => "def"
+        };
+        f(a, b)
+    } => {
+        match x {
+            1 => {
+                let a = 123;
+                let b = {
+                    enum __CainEnum<T0, T1> {
+                        V0(T0),
+                        V1(T1)
+                    }
+                    impl<T0: ::core::fmt::Display, T1: ::core::fmt::Display> ::core::fmt::Display for __CainEnum<T0, T1> {
+                        fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                            match self {
+                                Self::V0(value) => ::core::fmt::Display::fmt(value, f),
+                                Self::V1(value) => ::core::fmt::Display::fmt(value, f),
+                            }
+                        }
+                    }
+                    match y {
+                        1 => __CainEnum::V0(456),
+                        _ => __CainEnum::V1("def")
+                    }
+                };
+                f(a, b)
+            },
+            _ => {
+                let a = "abc";
+                let b = {
+                    enum __CainEnum<T0, T1> {
+                        V0(T0),
+                        V1(T1)
+                    }
+                    impl<T0: ::core::fmt::Display, T1: ::core::fmt::Display> ::core::fmt::Display for __CainEnum<T0, T1> {
+                        fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                            match self {
+                                Self::V0(value) => ::core::fmt::Display::fmt(value, f),
+                                Self::V1(value) => ::core::fmt::Display::fmt(value, f),
+                            }
+                        }
+                    }
+                    match y {
+                        1 => __CainEnum::V0(456),
+                        _ => __CainEnum::V1("def")
+                    }
+                };
+                f(a, b)
+            }
+        }
+    },
+
+    options_transparent_macro: {
+        #![cain(transparent_macros(log))]
+        log!("{}", match x {
+            1 => 123,
+            _ => "abc"
+        });
+        f()
+    } => {
+        match x {
+            1 => log!("{}", 123),
+            _ => log!("{}", "abc")
+        };
+        f()
+    },
+
+    options_transparent_macro_let: {
+        #![cain(transparent_macros(format))]
+        let s = format!("{}", match x {
+            1 => 123,
+            _ => "abc"
+        });
+        f(s)
+    } => {
+        match x {
+            1 => {
+                let s = format!("{}", 123);
+                f(s)
+            },
+            _ => {
+                let s = format!("{}", "abc");
+                f(s)
+            }
+        }
+    },
+
+    options_opaque_macro: {
+        #![cain(max_paths = 4)]
+        trace!("{}", match x {
+            1 => 123,
+            _ => "abc"
+        });
+        f()
+    } => {
+        trace!("{}", match x {
+            1 => 123,
+            _ => "abc"
+        });
+        f()
+    },
+
+    path_match: {
+        let a = match x {
+            Some((a, ..)) => a,
+            _ => 0
+        };
+        println!("{}", cain::path!());
+        path!()
+    } => {
+        match x {
+            Some((a, ..)) => {
+                let a = a;
+                println!("{}", "x => Some ((a , ..))");
+                path!()
+            },
+            _ => {
+                let a = 0;
+                println!("{}", "x => _");
+                path!()
+            }
+        }
+    },
+
+    path_hook: {
+        #![cain(path_hook = "trace")]
+        let a = if !x.is_empty() && y > -1 { 1 } else { 2 };
+        f(a)
+    } => {
+        if !x.is_empty() && y > -1 {
+            {
+                trace("! x . is_empty () && y > - 1 => true");
+                {
+                    let a = { 1 };
+                    f(a)
+                }
+            }
+        } else {
+            trace("! x . is_empty () && y > - 1 => false");
+            {
+                let a = { 2 };
+                f(a)
+            }
+        }
+    },
+
+    narrow_repeated_match: {
+        let a = match x {
+            1 => "one",
+            2 => "two",
+            _ => "many"
+        };
+        let b = match x {
+            1 => 10,
+            _ => 20
+        };
+        f(a, b)
+    } => {
+        match x {
+            1 => {
+                let a = "one";
+                match x {
+                    1 => {
+                        let b = 10;
+                        f(a, b)
+                    },
+                    #[allow(unreachable_patterns)]
+                    _ => unreachable!()
+                }
+            },
+            2 => {
+                let a = "two";
+                match x {
+                    _ => {
+                        let b = 20;
+                        f(a, b)
+                    }
+                }
+            },
+            _ => {
+                let a = "many";
+                match x {
+                    _ => {
+                        let b = 20;
+                        f(a, b)
+                    }
+                }
+            }
+        }
+    },
+
+    narrow_keeps_path_patterns: {
+        let a = match x {
+            Limits::LOW => 1,
+            _ => 2
+        };
+        let b = match x {
+            Limits::ZERO => "zero",
+            _ => "other"
+        };
+        f(a, b)
+    } => {
+        match x {
+            Limits::LOW => {
+                let a = 1;
+                match x {
+                    Limits::ZERO => {
+                        let b = "zero";
+                        f(a, b)
+                    },
+                    _ => {
+                        let b = "other";
+                        f(a, b)
+                    }
+                }
+            },
+            _ => {
+                let a = 2;
+                match x {
+                    Limits::ZERO => {
+                        let b = "zero";
+                        f(a, b)
+                    },
+                    _ => {
+                        let b = "other";
+                        f(a, b)
+                    }
+                }
+            }
+        }
+    },
+
+    narrow_negative_zero: {
+        let a = match x {
+            0 => 1,
+            _ => 2
+        };
+        let b = match x {
+            -0 => "zero",
+            _ => "other"
+        };
+        f(a, b)
+    } => {
+        match x {
+            0 => {
+                let a = 1;
+                match x {
+                    -0 => {
+                        let b = "zero";
+                        f(a, b)
+                    },
+                    #[allow(unreachable_patterns)]
+                    _ => unreachable!()
+                }
+            },
+            _ => {
+                let a = 2;
+                match x {
+                    _ => {
+                        let b = "other";
+                        f(a, b)
+                    }
+                }
+            }
+        }
+    },
+
+    narrow_skips_statics: {
+        let a = match COUNTER {
+            1 => 1,
+            _ => 2
+        };
+        let b = match COUNTER {
+            1 => "one",
+            _ => "other"
+        };
+        f(a, b)
+    } => {
+        match COUNTER {
+            1 => {
+                let a = 1;
+                match COUNTER {
+                    1 => {
+                        let b = "one";
+                        f(a, b)
+                    },
+                    _ => {
+                        let b = "other";
+                        f(a, b)
+                    }
+                }
+            },
+            _ => {
+                let a = 2;
+                match COUNTER {
+                    1 => {
+                        let b = "one";
+                        f(a, b)
+                    },
+                    _ => {
+                        let b = "other";
+                        f(a, b)
+                    }
+                }
+            }
+        }
+    },
+
+    narrow_stops_at_assignment: {
+        let a = match x {
+            1 => 1,
+            _ => 2
+        };
+        x = g();
+        let b = match x {
+            1 => 10,
+            _ => 20
+        };
+        f(a, b)
+    } => {
+        match x {
+            1 => {
+                let a = 1;
+                x = g();
+                match x {
+                    1 => {
+                        let b = 10;
+                        f(a, b)
+                    },
+                    _ => {
+                        let b = 20;
+                        f(a, b)
+                    }
+                }
+            },
+            _ => {
+                let a = 2;
+                x = g();
+                match x {
+                    1 => {
+                        let b = 10;
+                        f(a, b)
+                    },
+                    _ => {
+                        let b = 20;
+                        f(a, b)
+                    }
+                }
+            }
+        }
+    },
+
+    panic_free_rebinding: {
+        #![cain(panic_free)]
+        let b = match x {
+            Some(mut r) => { r += 1; r }
+            None => a
+        };
+        let c = if let Ok(s) = y { s } else { b };
+        f(b, c, r, s)
+    } => {
+        match x {
+            Some(mut __cain_ident__1) => {
+                let b = {
+                    let (mut r,) = (__cain_ident__1,);
+                    r += 1;
+                    r
+                };
+                if let Ok(__cain_ident__0) = y {
+                    {
+                        let c = {
+                            let (s,) = (__cain_ident__0,);
+                            s
+                        };
+                        f(b, c, r, s)
+                    }
+                } else {
+                    let c = { b };
+                    f(b, c, r, s)
+                }
+            }
+            None => {
+                let b = a;
+                if let Ok(__cain_ident__0) = y {
+                    {
+                        let c = {
+                            let (s,) = (__cain_ident__0,);
+                            s
+                        };
+                        f(b, c, r, s)
+                    }
+                } else {
+                    let c = { b };
+                    f(b, c, r, s)
+                }
+            }
+        }
+    },
+
+    panic_free_narrowing: {
+        #![cain(panic_free)]
+        let a = match x {
+            1 => "one",
+            _ => "many"
+        };
+        let b = match x {
+            1 => 10,
+            _ => 20
+        };
+        f(a, b)
+    } => {
+        match x {
+            1 => {
+                let a = "one";
+                match x {
+                    1 => {
+                        let b = 10;
+                        f(a, b)
+                    },
+                    _ => {
+                        let b = 20;
+                        f(a, b)
+                    }
+                }
+            },
+            _ => {
+                let a = "many";
+                match x {
+                    1 => {
+                        let b = 10;
+                        f(a, b)
+                    },
+                    _ => {
+                        let b = 20;
+                        f(a, b)
+                    }
+                }
+            }
+        }
+    },
+
+    skip_match: {
+        let a = #[cain::skip] match x {
+            1 => 2,
+            _ => 3
+        };
+        f(a)
+    } => {
+        let a = match x {
+            1 => 2,
+            _ => 3
+        };
+        f(a)
+    },
+
+    skip_match_arms: {
+        #[cain::skip]
+        match x {
+            Some(v) => g(if y { v } else { 0 }),
+            None => h(),
+        }
+    } => {
+        match x {
+            Some(v) => if y { g({ v }) } else { g({ 0 }) },
+            None => h(),
+        }
+    },
+
+    skip_let: {
+        #[cain::skip]
+        let y = match n {
+            1 => 1,
+            _ => 2
+        };
+        f(y)
+    } => {
+        let y = match n {
+            1 => 1,
+            _ => 2
+        };
+        f(y)
+    },
+
+    skip_if_else_if: {
+        let a = #[cain::skip] if x {
+            1
+        } else if match y { Some(_) => true, None => false } {
+            2
+        } else {
+            3
+        };
+        f(a)
+    } => {
+        let a = if x {
+            1
+        } else if match y { Some(_) => true, None => false } {
+            2
+        } else {
+            3
+        };
+        f(a)
+    },
+
+    explicit_fork: {
+        #![cain(explicit)]
+        let a = if x { 1 } else { 2 };
+        let b = #[cain::fork] #[allow(unused_parens)] match y {
+            Some(_) => "some",
+            None => "none"
+        };
+        f(a, b)
+    } => {
+        let a = if x { 1 } else { 2 };
+        #[allow(unused_parens)]
+        match y {
+            Some(_) => {
+                let b = "some";
+                f(a, b)
+            },
+            None => {
+                let b = "none";
+                f(a, b)
+            }
+        }
+    },
+
+    fork_attributes: {
+        let a = #[allow(unreachable_patterns)] match x {
+            1 => "one",
+            _ => "many"
+        };
+        f(a)
+    } => {
+        #[allow(unreachable_patterns)]
+        match x {
+            1 => {
+                let a = "one";
+                f(a)
+            },
+            _ => {
+                let a = "many";
+                f(a)
+            }
+        }
+    },
+
+    shallow_nested: {
+        #![cain(shallow)]
+        let a = f(match x {
+            1 => 2,
+            _ => 3
+        });
+        let b = if y { 'a' } else { 'b' };
+        g(a, b)
+    } => {
+        let a = f(match x {
+            1 => 2,
+            _ => 3
+        });
+        if y {
+            {
+                let b = { 'a' };
+                g(a, b)
+            }
+        } else {
+            let b = { 'b' };
+            g(a, b)
+        }
+    },
+
+    shallow_tail: {
+        #![cain(shallow)]
+        h(if y { a } else { b });
+        match x {
+            1 => f(a),
+            _ => g(if y { a } else { b })
+        }
+    } => {
+        h(if y { a } else { b });
+        match x {
+            1 => f(a),
+            _ => g(if y { a } else { b })
+        }
+    },
+
+    shallow_else_if: {
+        #![cain(shallow)]
+        let v = if a { 1 } else if b { 'c' } else { match x { 1 => 2, _ => 3 } };
+        f(v)
+    } => {
+        if a {
+            {
+                let v = { 1 };
+                f(v)
+            }
+        } else if b {
+            {
+                let v = { 'c' };
+                f(v)
+            }
+        } else {
+            let v = { match x { 1 => 2, _ => 3 } };
+            f(v)
+        }
+    },
+
+    shallow_paren: {
+        #![cain(shallow)]
+        let a = (if y { 'a' } else { 'b' });
+        f(a)
+    } => {
+        if y {
+            {
+                let a = ({ 'a' });
+                f(a)
+            }
+        } else {
+            let a = ({ 'b' });
+            f(a)
+        }
+    },
+
+    shallow_fork_marker: {
+        #![cain(shallow)]
+        let a = f(#[cain::fork] match x {
+            1 => 2,
+            _ => "3"
+        });
+        g(a)
+    } => {
+        match x {
+            1 => {
+                let a = f(2);
+                g(a)
+            },
+            _ => {
+                let a = f("3");
+                g(a)
+            }
+        }
+    },
+
+    deferred_if: {
+        let conn;
+        if tls {
+            log("tls");
+            conn = tls_stream();
+        } else {
+            conn = tcp_stream();
+        }
+        f(conn)
+    } => {
+        if tls {
+            {
+                let conn = {
+                    log("tls");
+                    tls_stream()
+                };
+                f(conn)
+            }
+        } else {
+            let conn = { tcp_stream() };
+            f(conn)
+        }
+    },
+
+    deferred_match: {
+        let mut value: u8;
+        g();
+        match x {
+            Some(n) => value = n,
+            _ => {
+                value = if y { 1 } else { 2 };
+            }
+        }
+        f(value)
+    } => {
+        g();
+        match x {
+            Some(n) => {
+                let mut value: u8 = n;
+                f(value)
+            },
+            _ => {
+                let mut value: u8 = {
+                    if y { { 1 } } else { 2 }
+                };
+                f(value)
+            }
+        }
+    },
+
+    deferred_diverging_arm: {
+        let value;
+        match x {
+            Some(n) => value = n,
+            None => return
+        }
+        f(value)
+    } => {
+        let value;
+        match x {
+            Some(n) => value = n,
+            None => return
+        }
+        f(value)
+    },
+
+    deferred_assign: {
+        let conn;
+        g();
+        conn = match x {
+            1 => a(),
+            _ => b()
+        };
+        f(conn)
+    } => {
+        g();
+        match x {
+            1 => {
+                let conn = a();
+                f(conn)
+            },
+            _ => {
+                let conn = b();
+                f(conn)
+            }
+        }
+    },
+
+    deferred_without_branch_point: {
+        let x;
+        g();
+        x = h();
+        f(x)
+    } => {
+        let x;
+        g();
+        x = h();
+        f(x)
+    },
+
+    deferred_let_in_between: {
+        let log;
+        let guard = lock();
+        log = if verbose { open() } else { discard() };
+        f(log, guard)
+    } => {
+        let log;
+        let guard = lock();
+        if verbose {
+            log = { open() }
+        } else {
+            log = { discard() }
+        };
+        f(log, guard)
+    },
+
+    deferred_used_later: {
+        let x;
+        if y {
+            x = 1;
+            g(x);
+        } else {
+            x = 2;
+        }
+        f(x)
+    } => {
+        let x;
+        if y {
+            {
+                x = 1;
+                g(x);
+            }
+        } else {
+            x = 2;
+        }
+        f(x)
+    },
+
+    narrow_unknown_constants: {
+        let a = match x {
+            1 => 1,
+            _ => 2
+        };
+        let b = match x {
+            FOO => 10,
+            other => other
+        };
+        f(a, b)
+    } => {
+        match x {
+            1 => {
+                let a = 1;
+                match x {
+                    FOO => {
+                        let b = 10;
+                        f(a, b)
+                    },
+                    other => {
+                        let b = other;
+                        f(a, b)
+                    }
+                }
+            },
+            _ => {
+                let a = 2;
+                match x {
+                    FOO => {
+                        let b = 10;
+                        f(a, b)
+                    },
+                    other => {
+                        let b = other;
+                        f(a, b)
+                    }
+                }
+            }
+        }
+    },
+
+    condition_literal: { 1 + if true { a } else { b } + if x { c } else { d } }
+        => {
+            if x {
+                1 + a + { c }
+            } else {
+                1 + a + { d }
+            }
+        },
+
+    condition_const: {
+        const FAST: bool = false;
+        let value = if !FAST { slow() } else { fast() };
+        run(value)
+    } => {
+        const FAST: bool = false;
+        let value = {
+            let _ = !FAST;
+            slow()
+        };
+        run(value)
+    },
+
+    condition_cfg: {
+        let value = if cfg!(feature = "simd") && true { fast() } else { slow() };
+        run(value)
+    } => {
+        #[cfg(feature = "simd")]
+        {
+            let value = { fast() };
+            run(value)
+        }
+        #[cfg(not(feature = "simd"))]
+        {
+            let value = { slow() };
+            run(value)
+        }
+    },
+
+    condition_cfg_diverging: {
+        let value = if cfg!(feature = "simd") { return None } else { slow() };
+        run(value)
+    } => {
+        #[cfg(feature = "simd")]
+        { return None }
+        #[cfg(not(feature = "simd"))]
+        {
+            let value = { slow() };
+            run(value)
+        }
+    },
+
+    dispatch_macro_let: {
+        #![cain(dispatch_macros(shape_dispatch))]
+        let prefix = "shape";
+        let inner = shape_dispatch!(shape);
+        let (N, FAST) = cain::const_dispatch!(n in 1..=4, fast in bool,);
+        f::<N, FAST>(prefix, inner)
+    } => {
+        let prefix = "shape";
+        shape_dispatch!(shape, |inner| {
+            cain::const_dispatch!(n in 1..=4, fast in bool, |N, FAST| {
+                f::<N, FAST>(prefix, inner)
+            })
+        })
+    },
+
+    each_loop: {
+        for x in cain::each!(a, "b") {
+            loop {
+                break;
+            }
+            let y = match x.skip() {
+                true => continue,
+                false => f(x),
+            };
+            y.stop();
+            break;
+        }
+    } => {
+        '__cain_ident__0: loop {
+            for x in ::core::iter::once(a) {
+                loop {
+                    break;
+                }
+                match x.skip() {
+                    true => continue,
+                    false => {
+                        let y = f(x);
+                        y.stop();
+                        break '__cain_ident__0;
+                    },
+                }
+            }
+            for x in ::core::iter::once("b") {
+                loop {
+                    break;
+                }
+                match x.skip() {
+                    true => continue,
+                    false => {
+                        let y = f(x);
+                        y.stop();
+                        break '__cain_ident__0;
+                    },
+                }
+            }
+            break;
+        }
+    },
+
+    each_tuple_labeled: {
+        'items: for (i, x) in #[cain::each] (a, b) {
+            for _ in 0..i {
+                continue 'items;
+            }
+            break 'items;
+        }
+    } => {
+        '__cain_ident__0: loop {
+            'items: for (i, x) in ::core::iter::once(a) {
+                for _ in 0..i {
+                    continue 'items;
+                }
+                break '__cain_ident__0;
+            }
+            'items: for (i, x) in ::core::iter::once(b) {
+                for _ in 0..i {
+                    continue 'items;
+                }
+                break '__cain_ident__0;
+            }
+            break;
+        }
+    },
+
+    each_unqualified: {
+        for x in each!(a, b) {
+            f(x);
+        }
+    } => {
+        for x in each!(a, b) {
+            f(x);
+        }
+    },
+
+    nested_let: {
+        let a = cain! {
+            let b = match x {
+                1 => 1,
+                _ => "b",
+            };
+            f(b)
+        };
+        g(a)
+    } => {
+        match x {
+            1 => {
+                let b = 1;
+                let a = f(b);
+                g(a)
+            },
+            _ => {
+                let b = "b";
+                let a = f(b);
+                g(a)
+            },
+        }
+    },
+
+    nested_let_shadowing: {
+        let y = cain! {
+            let x = next();
+            if x > 0 { x } else { 0 }
+        };
+        f(x, y)
+    } => {
+        let y = {
+            let x = next();
+            if x > 0 {
+                {
+                    { x }
+                }
+            } else {
+                0
+            }
+        };
+        f(x, y)
+    },
+
+    nested_expr: {
+        let a = h(cain::cain! {
+            let b = if x { 1 } else { 2 };
+            f(b)
+        });
+        cain! {
+            let c = if y { a } else { 3 };
+            g(c);
+        }
+    } => {
+        let a = h({
+            if x {
+                {
+                    let b = { 1 };
+                    f(b)
+                }
+            } else {
+                let b = { 2 };
+                f(b)
+            }
+        });
+        {
+            if y {
+                {
+                    let c = { a };
+                    g(c);
+                }
+            } else {
+                let c = { 3 };
+                g(c);
+            }
+        }
+    },
+
+    shared_let: {
+        shared T: Display -> String;
+        let prefix: &str = "value";
+        let mut count: usize = 0;
+        let a = match x {
+            1 => 1,
+            _ => "a",
+        };
+        count += 1;
+        format!("{} {} {}", prefix, a, count)
+    } => {
+        let prefix: &str = "value";
+        #[allow(unused_mut)]
+        let mut count: usize = 0;
+        fn __cain_ident__0<T: Display>(
+            a: T,
+            #[allow(unused_mut, unused_variables)] prefix: &str,
+            #[allow(unused_mut, unused_variables)] mut count: usize
+        ) -> String {
+            count += 1;
+            format!("{} {} {}", prefix, a, count)
+        }
+        match x {
+            1 => __cain_ident__0(1, prefix, count),
+            _ => __cain_ident__0("a", prefix, count),
+        }
+    },
+
+    macro_let: {
+        #![cain(strategy = "macro")]
+        let prefix = "value";
+        let a = match x {
+            1 => 1,
+            _ => "a",
+        };
+        let b = if y { 'b' } else { 2 };
+        format!("{} {} {}", prefix, a, b)
+    } => {
+        let prefix = "value";
+        macro_rules! __cain_ident__1 {
+            ($__cain_ident__1_value:expr) => {
+                {
+                    let a = $__cain_ident__1_value;
+                    macro_rules! __cain_ident__0 {
+                        ($__cain_ident__0_value:expr) => {
+                            {
+                                let b = $__cain_ident__0_value;
+                                format!("{} {} {}", prefix, a, b)
+                            }
+                        };
+                    }
+                    if y { __cain_ident__0!('b') } else { __cain_ident__0!(2) }
+                }
+            };
+        }
+        match x {
+            1 => __cain_ident__1!(1),
+            _ => __cain_ident__1!("a"),
+        }
+    },
+
+    macro_paths: {
+        #![cain(strategy = "macro")]
+        let a = if x { 1 } else { "a" };
+        format!("{} {}", a, cain::path!())
+    } => {
+        if x {
+            {
+                let a = { 1 };
+                format!("{} {}", a, "x => true")
+            }
+        } else {
+            let a = { "a" };
+            format!("{} {}", a, "x => false")
+        }
+    },
+
+    dispatch_macro_derived_in_block: {
+        #[derive(Dispatch)]
+        enum Shape {
+            Circle(Circle),
+        }
+        let inner = shape_dispatch!(shape);
+        f(inner)
+    } => {
+        #[derive(Dispatch)]
+        enum Shape {
+            Circle(Circle),
+        }
+        shape_dispatch!(shape, |inner| { f(inner) })
+    },
+
+    dispatch_macro_unregistered: {
+        let inner = shape_dispatch!(shape);
+        let value = other_dispatch!(inner);
+        f(value)
+    } => {
+        let inner = shape_dispatch!(shape);
+        let value = other_dispatch!(inner);
+        f(value)
+    },
+
+    dispatch_macro_attributes: {
+        #![cain(dispatch_macros(shape_dispatch))]
+        #[allow(unused_mut)]
+        let mut inner = shape_dispatch!(shape);
+        f(inner)
+    } => {
+        shape_dispatch!(shape, |__cain_ident__0| {
+            #[allow(unused_mut)]
+            let mut inner = __cain_ident__0;
+            f(inner)
+        })
+    },
+
+    dispatch_macro_let_else: {
+        #![cain(dispatch_macros(shape_dispatch))]
+        let Some(inner) = shape_dispatch!(shape) else {
+            return;
+        };
+        f(inner)
+    } => {
+        shape_dispatch!(shape, |__cain_ident__0| {
+            let Some(inner) = __cain_ident__0 else {
+                return;
+            };
+            f(inner)
+        })
+    },
+
+    dispatch_macro_closed: {
+        let size = cain::const_dispatch!(n in 1..=4, |N| N);
+        f(size)
+    } => {
+        let size = cain::const_dispatch!(n in 1..=4, |N| N);
+        f(size)
+    },
+}
+
+test_cain_macro_error! {
+    dispatch_macro_type: {
+        #![cain(dispatch_macros(shape_dispatch))]
+        let inner: Circle = shape_dispatch!(shape);
+        f(inner)
+    } => "cain! does not support type annotations on the result of a dispatch macro",
+
+    panic_free_uppercase_binding: {
+        #![cain(panic_free)]
+        let b = match x {
+            Some(v) => v,
+            None => 0
+        };
+        f(b, None)
+    } => "cain! cannot tell whether `None` is a binding or a constant in `panic_free` mode, write constants and unit variants as paths, as in `Option::None`",
+
+    options_panic_free_value: {
+        #![cain(panic_free = 1)]
+    } => "expected a boolean, as in `panic_free` or `panic_free = false`",
+
+    marker_arguments: {
+        let a = #[cain::skip(all)] match x {
+            1 => 2,
+            _ => 3
+        };
+    } => "cain! markers do not take arguments",
+
+    marker_twice: {
+        let a = #[cain::skip] #[cain::fork] match x {
+            1 => 2,
+            _ => 3
+        };
+    } => "cain! expected at most one `#[cain::skip]` or `#[cain::fork]` marker",
+
+    marker_misplaced: {
+        let a = #[cain::fork] f(x);
+    } => "cain! markers can only be put on `match` and `if` expressions",
+
+    impl_trait_outer_lifetime: {
+        let words: impl Iterator<Item = &'a str> = match x {
+            1 => a.split(' '),
+            _ => a.lines()
+        };
+        f(words)
+    } => "cain! cannot check `impl Trait` bounds that name the lifetime `'a` of the enclosing function",
+
+    marker_misplaced_let: {
+        #[cain::fork]
+        let a = f(x);
+    } => "cain! markers on a `let` statement need a `match` or `if` initializer",
+
+    options_shallow_value: {
+        #![cain(shallow = 1)]
+    } => "expected a boolean, as in `shallow` or `shallow = false`",
+
+    options_explicit_value: {
+        #![cain(explicit = "yes")]
+    } => "expected a boolean, as in `explicit` or `explicit = false`",
+
+    each_tuple_attributes: {
+        for x in #[cain::each] #[allow(unused)] (a, b) {
+            f(x);
+        }
+    } => "cain! does not support other attributes on a `#[cain::each]` tuple",
+
+    nested_max_paths: {
+        #![cain(max_paths = 3)]
+        let a = match x {
+            1 => 1,
+            _ => 2
+        };
+        let b = cain! {
+            let c = if y { 1 } else { 2 };
+            c
+        };
+        f(a, b)
+    } => "cain! block forks into 4 paths here, which exceeds `max_paths = 3`",
+
+    shared_untyped_capture: {
+        shared T: Display;
+        let prefix = "value";
+        let a = match x {
+            1 => 1,
+            _ => "a",
+        };
+        println!("{} {}", prefix, a);
+    } => "cain! needs the type of `prefix` to pass it to a shared continuation, add a type annotation to its `let` statement",
+
+    shared_return: {
+        shared T: Display;
+        let a = match x {
+            1 => 1,
+            _ => "a",
+        };
+        if a.to_string().is_empty() {
+            return;
+        }
+        println!("{}", a);
+    } => "cain! cannot share a continuation that contains `return`",
+
+    shared_pattern: {
+        shared T: Display;
+        let (a, b) = match x {
+            1 => (1, 2),
+            _ => ("a", "b")
+        };
+    } => "cain! can only share the continuation of `let` statements that bind a single identifier",
+
+    shared_with_dispatch: {
+        #![cain(dispatch = "dyn Display")]
+        shared T: Display;
+    } => "a `shared` header cannot be combined with a dispatch type",
+
+    options_shared_without_header: {
+        #![cain(strategy = "shared")]
+    } => "the \"shared\" strategy requires a `shared T: Trait;` header",
+
+    loop_break_unlabeled_continue: {
+        let z = loop {
+            break 1;
+        };
+        continue;
+    } => "cain! cannot move unlabeled `break` or `continue` into a `loop`, add a label to the target loop",
+
+    dyn_pattern: {
+        dyn Display;
+        let (a, b) = match x {
+            1 => (1, 2),
+            _ => ("a", "b")
+        };
+    } => "cain! can only dispatch `let` statements that bind a single identifier",
+
+    dyn_bad_option: {
+        dyn Display, max_depth = 2;
+    } => "expected `max_paths`",
+
+    options_unknown_key: {
+        #![cain(max_depth = 2)]
+    } => "unknown cain! option, expected one of `strategy`, `dispatch`, `max_paths`, `fallback`, `transparent_macros`, `dispatch_macros`, `path_hook`, `panic_free`, `explicit`, `shallow`",
+
+    options_wrong_type: {
+        #![cain(max_paths = "many")]
+    } => "expected an integer, as in `max_paths = 16`",
+
+    options_unknown_strategy: {
+        #![cain(strategy = "static", dispatch = "dyn Display")]
+    } => "unknown strategy \"static\", expected one of \"duplicate\", \"dyn\", \"hybrid\", \"enum\", \"shared\", \"macro\"",
+
+    options_duplicate_key: {
+        #![cain(max_paths = 2, max_paths = 4)]
+    } => "duplicate cain! option `max_paths`",
+
+    options_duplicate_key_across_attributes: {
+        #![cain(max_paths = 2)]
+        #![cain(shallow, max_paths = 4)]
+    } => "duplicate cain! option `max_paths`",
+
+    options_duplicate_max_paths_in_header: {
+        #![cain(max_paths = 2)]
+        dyn Display, max_paths = 4;
+    } => "duplicate cain! option `max_paths`",
+
+    options_enum_unsupported_trait: {
+        #![cain(strategy = "enum", dispatch = "dyn Read")]
+        let a = match x {
+            1 => f(),
+            _ => g()
+        };
+        a.read()
+    } => "cain! cannot implement `Read` for the values of branching `let` statements with the \"enum\" strategy, only `Iterator`, `DoubleEndedIterator`, `ExactSizeIterator`, `FusedIterator`, `Future`, `Debug`, `Display` and `Error` are supported",
+
+    options_other_attribute: {
+        #![allow(unused)]
+    } => "cain! only supports `#![cain(...)]` inner attributes",
+
+    options_bad_fallback: {
+        #![cain(strategy = "hybrid", fallback = "shared", dispatch = "dyn Display", max_paths = 4)]
+    } => "the \"hybrid\" strategy can only fall back to the \"dyn\" or \"enum\" strategy",
+
+    options_dyn_without_dispatch: {
+        #![cain(strategy = "dyn")]
+    } => "this strategy requires a `dyn Trait;` header or a `dispatch = \"dyn Trait\"` option",
+
+    options_max_paths_exceeded: {
+        #![cain(max_paths = 3)]
+        let a = match x {
+            1 => 1,
+            _ => 2
+        };
+        let b = match y {
+            1 => 1,
+            _ => 2
+        };
+        f(a, b)
+    } => "cain! block forks into 4 paths here, which exceeds `max_paths = 3`",
+
+    loop_break_shadowing: {
+        let y = loop {
+            let x = next();
+            if x > 0 {
+                break x;
+            }
+        };
+        f(x, y)
+    } => "cain! cannot fork at the exit points of this loop, since `x` would shadow a variable that the rest of the block uses, rename it",
+}
+
+macro_rules! test_cain_macro_defaults {
+    (
+        $(
+            $test_name:ident: $manifest:literal, { $($input:tt)* } => $output:tt
+        ),+ $(,)?
+    ) => {
+        $(
+            #[test]
+            fn $test_name() {
+                crate::util::in_test(|| {
+                    let input = ::quote::quote!{ $($input)* };
+
+                    let actual_output = Options::from_manifest($manifest)
+                        .map_err(|message| message.to_string())
+                        .and_then(|defaults| {
+                            expand(input, &defaults).map_err(|err| err.to_string())
+                        })
+                        .map(|(output, _)| output.to_string());
+
+                    assert_eq!(test_cain_macro_defaults!(@expected $output), actual_output);
+                })
+            }
+        )*
+    };
+
+    (@expected { $($output:tt)* }) => {
+        Ok(::quote::quote!{ { $($output)* } }.to_string())
+    };
+
+    (@expected $message:literal) => {
+        Err($message.to_string())
+    };
+}
+
+test_cain_macro_defaults! {
+    defaults_dispatch: r#"
+        [package.metadata.cain]
+        dispatch = "dyn Display"
+    "#, {
+        let a = match x {
+            1 => 123,
+            _ => "abc"
+        };
+        f(a)
+    } => {
+        let __cain_ident__0;
+        let __cain_ident__1;
+        let a: &dyn Display = match x {
+            1 => {
+                __cain_ident__0 = 123;
+                &__cain_ident__0
+            },
+            _ => {
+                __cain_ident__1 = "abc";
+                &__cain_ident__1
+            }
+        };
+        f(a)
+    },
+
+    defaults_overridden: r#"
+        [package.metadata.cain]
+        strategy = "dyn"
+        dispatch = "dyn Display"
+    "#, {
+        #![cain(strategy = "duplicate")]
+        let a = match x {
+            1 => 123,
+            _ => "abc"
+        };
+        f(a)
+    } => {
+        match x {
+            1 => {
+                let a = 123;
+                f(a)
+            },
+            _ => {
+                let a = "abc";
+                f(a)
+            }
+        }
+    },
+
+    defaults_max_paths: r#"
+        [package.metadata.cain]
+        max-paths = 1
+    "#, {
+        let a = match x {
+            1 => 123,
+            _ => "abc"
+        };
+        f(a)
+    } => "cain! block forks into 2 paths here, which exceeds `max_paths = 1`",
+
+    defaults_transparent_macros: r#"
+        [package.metadata.cain]
+        transparent_macros = ["format"]
+    "#, {
+        #![cain(max_paths = 2)]
+        let s = format!("{}", match x {
+            1 => 123,
+            _ => "abc"
+        });
+        f(s)
+    } => {
+        match x {
+            1 => {
+                let s = format!("{}", 123);
+                f(s)
+            },
+            _ => {
+                let s = format!("{}", "abc");
+                f(s)
+            }
+        }
+    },
+
+    defaults_unknown_key: r#"
+        [package.metadata.cain]
+        max_depth = 2
+    "#, {} => "unknown option `max_depth`, expected one of `strategy`, `dispatch`, `max_paths`, `fallback`, `transparent_macros`, `dispatch_macros`, `path_hook`, `panic_free`, `explicit`, `shallow`",
+
+    defaults_wrong_type: r#"
+        [package.metadata.cain]
+        max_paths = "many"
+    "#, {} => "expected `max_paths` to be a non-negative integer",
+
+    defaults_invalid_strategy: r#"
+        [package.metadata.cain]
+        strategy = "hybrid"
+    "#, {} => "this strategy requires a `dyn Trait;` header or a `dispatch = \"dyn Trait\"` option",
+}
+
+macro_rules! test_cain_macro_warnings {
+    (
+        $(
+            $test_name:ident: { $($input:tt)* } => [ $($message:literal),* $(,)? ]
+        ),+ $(,)?
+    ) => {
+        $(
+            #[test]
+            fn $test_name() {
+                crate::util::in_test(|| {
+                    let input = ::quote::quote!{ $($input)* };
+
+                    let (_, warnings) = expand(input, &Options::default()).unwrap();
+                    let actual_messages = warnings
+                        .into_iter()
+                        .map(|warning| warning.message)
+                        .collect::<Vec<_>>();
+
+                    assert_eq!(vec![$($message),*] as Vec<&str>, actual_messages);
+                })
+            }
+        )*
+    };
+}
+
+test_cain_macro_warnings! {
+    warn_no_branch_points: {
+        let a = f();
+        g(a)
+    } => ["this `cain!` block has no branch points, and can be removed"],
+
+    warn_reordered_call: {
+        f(g(), match h() {
+            1 => a,
+            _ => b
+        })
+    } => ["this expression is now evaluated after a branch point that follows it, because `cain!` evaluates branch points first"],
+
+    no_warn_else_if: {
+        let a = if f() { 1 } else if g() { 2 } else { 3 };
+        a
+    } => [],
+
+    no_warn_const_condition: {
+        const FAST: bool = false;
+        let x = if FAST { 1u8 } else { "s" };
+        f(x)
+    } => [],
+
+    warn_nested_shadowing: {
+        let y = cain! {
+            let x = next();
+            if x > 0 { x } else { 0 }
+        };
+        f(x, y)
+    } => ["this binding shadows `x` in the rest of the `cain!` block, so the nested block cannot fork it"],
+
+    no_warn_pure_scrutinee: {
+        f(g(), match x {
+            1 => a,
+            _ => b
+        })
+    } => [],
+
+    no_warn_call_after_branch: {
+        f(match h() {
+            1 => a,
+            _ => b
+        }, g())
+    } => [],
+
+    no_warn_loop_rebound: {
+        let y = loop {
+            let x = next();
+            break x;
+        };
+        let x = y;
+        f(x)
+    } => [],
+}
+
+macro_rules! test_dispatch_macro {
+    (
+        $module:ident;
+
+        $(
+            $test_name:ident: { $($input:tt)* } => $output:tt
+        ),+ $(,)?
+    ) => {
+        $(
+            #[test]
+            fn $test_name() {
+                crate::util::in_test(|| {
+                    let input = ::quote::quote!{ $($input)* };
+
+                    let actual_output = crate::$module::expand(input, &Options::default())
+                        .map(|output| output.to_string())
+                        .map_err(|err| err.to_string());
+
+                    assert_eq!(test_dispatch_macro!(@expected $output), actual_output);
+                })
+            }
+        )*
+    };
+
+    (@expected { $($output:tt)* }) => {
+        Ok(::quote::quote!{ $($output)* }.to_string())
+    };
+
+    (@expected $message:literal) => {
+        Err($message.to_string())
+    };
+}
+
+test_dispatch_macro! {
+    const_dispatch;
+
+    const_dispatch_range: { n in 1..=2, |N| kernel::<N>(&data) } => {
+        match n {
+            1 => { kernel::<1>(&data) },
+            2 => { kernel::<2>(&data) },
+            #[allow(unreachable_patterns)]
+            _ => { panic!("`n` is not one of the values of `const_dispatch!`") },
+        }
+    },
+
+    const_dispatch_multiple: { n in [-1, 4], flag in bool, |N, FLAG| f::<N, FLAG>() } => {
+        match n {
+            -1 => match flag {
+                false => { f::<{ -1 }, false>() },
+                true => { f::<{ -1 }, true>() },
+            },
+            4 => match flag {
+                false => { f::<4, false>() },
+                true => { f::<4, true>() },
+            },
+            #[allow(unreachable_patterns)]
+            _ => match flag {
+                false => { panic!("`n` is not one of the values of `const_dispatch!`") },
+                true => { panic!("`n` is not one of the values of `const_dispatch!`") },
+            },
+        }
+    },
+
+    const_dispatch_max_paths: {
+        #![cain(max_paths = 4)]
+        n in 0..4, flag in bool, |N, FLAG| f::<N, FLAG>()
+    } => "const_dispatch! forks into 8 paths, which exceeds `max_paths = 4`",
+
+    const_dispatch_default_max_paths: { n in 0..1000, m in 0..1000, |N, M| f::<N, M>() }
+        => "const_dispatch! forks into 1000000 paths, which exceeds the limit of 1024 paths without a `max_paths` option",
+
+    const_dispatch_name_count: { n in 0..4, flag in bool, |N| f::<N>() }
+        => "const_dispatch! expects 2 names, one for each value, but got 1",
+
+    const_dispatch_bad_values: { n in 0.., |N| f::<N>() }
+        => "const_dispatch! ranges must have both bounds",
+
+    const_dispatch_huge_range: { n in 0..=9223372036854775807, |N| f::<N>() }
+        => "const_dispatch! range has 9223372036854775808 values, which exceeds the limit of 1024 values without a `max_paths` option",
+
+    const_dispatch_range_max_paths: {
+        #![cain(max_paths = 4)]
+        n in 0..8, |N| f::<N>()
+    } => "const_dispatch! range has 8 values, which exceeds `max_paths = 4`",
+
+    const_dispatch_unparsable_body: { n in 1..=2, |N| { fn N() {} } }
+        => "const_dispatch! cannot replace the names in the body with the values of an arm: expected identifier",
+}
+
+test_dispatch_macro! {
+    type_dispatch;
+
+    type_dispatch_simple: { format, { "json" => Json, "cbor" | "cb" => Cbor }, |T| T::run() } => {
+        match format {
+            "json" => { Json::run() },
+            "cbor" | "cb" => { Cbor::run() },
+            #[allow(unreachable_patterns)]
+            _ => { panic!("`format` is not one of the values of `type_dispatch!`") },
+        }
+    },
+
+    type_dispatch_fallback: {
+        format, { "json" => Json, _ => Raw },
+        width, { 4 => u32, _ => Vec<u8> },
+        |C, W| run::<C, W>(W::default())
+    } => {
+        match format {
+            "json" => match width {
+                4 => { run::<Json, u32>(u32::default()) },
+                _ => { run::<Json, Vec<u8> >(<Vec<u8> >::default()) },
+            },
+            _ => match width {
+                4 => { run::<Raw, u32>(u32::default()) },
+                _ => { run::<Raw, Vec<u8> >(<Vec<u8> >::default()) },
+            },
+        }
+    },
+
+    type_dispatch_no_arms: { format, {}, |T| T::run() }
+        => "type_dispatch! has no arms to dispatch on",
+}
+
+#[test]
+fn derive_dispatch() {
+    let input = syn::parse_quote! {
+        enum HttpBody {
+            Text(String),
+            Bytes(Vec<u8>),
+        }
+    };
+
+    let expected_output = ::quote::quote! {
+        #[allow(unused_macros)]
+        macro_rules! http_body_dispatch {
+            ($value:expr, |$inner:pat_param| $body:expr $(,)?) => {
+                match $value {
+                    HttpBody::Text($inner) => $body,
+                    HttpBody::Bytes($inner) => $body,
+                }
+            };
+        }
+
+        #[allow(unused_imports)]
+        pub(crate) use http_body_dispatch;
+    };
+
+    let actual_output = crate::derive_dispatch::derive_dispatch(input).unwrap();
+
+    assert_eq!(expected_output.to_string(), actual_output.to_string());
+}
+
+#[test]
+fn derive_dispatch_unit_variant() {
+    let input = syn::parse_quote! {
+        enum Shape {
+            Circle(Circle),
+            Empty,
+        }
+    };
+
+    let actual_error = crate::derive_dispatch::derive_dispatch(input).unwrap_err();
+
+    assert_eq!(
+        "derive(Dispatch) only supports variants with a single unnamed field",
+        actual_error.to_string()
+    );
+}
+
+#[test]
+fn auto_enum() {
+    let input = syn::parse_quote! {
+        fn values(x: bool) -> Result<impl Display, Error> {
+            if x {
+                return Err(Error);
+            }
+            match y {
+                Some(v) => Ok(v),
+                None => parse("none"),
+            }
+        }
+    };
+
+    let expected_output = ::quote::quote! {
+        fn values(x: bool) -> Result<impl Display, Error> {
+            enum __CainEnum<T0, T1> {
+                V0(T0),
+                V1(T1)
+            }
+
+            impl<T0: ::core::fmt::Display, T1: ::core::fmt::Display> ::core::fmt::Display for __CainEnum<T0, T1> {
+                fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                    match self {
+                        Self::V0(value) => ::core::fmt::Display::fmt(value, f),
+                        Self::V1(value) => ::core::fmt::Display::fmt(value, f),
+                    }
+                }
+            }
+
+            if x {
+                return Err(Error);
+            }
+            match y {
+                Some(v) => Ok(__CainEnum::V0(v)),
+                None => (parse("none")).map(|__cain_ok| __CainEnum::V1(__cain_ok)),
+            }
+        }
+    };
+
+    let (actual_output, _) = crate::auto_enum::expand(input, &Options::default()).unwrap();
+
+    assert_eq!(
+        expected_output.to_string(),
+        actual_output.to_token_stream().to_string()
+    );
+}
+
+#[test]
+fn auto_enum_single_value() {
+    let input = syn::parse_quote! {
+        fn values(n: u32) -> impl Iterator<Item = u32> {
+            (0..n).map(|x| x)
+        }
+    };
+
+    let expected_output = ::quote::quote! {
+        fn values(n: u32) -> impl Iterator<Item = u32> {
+            (0..n).map(|x| x)
+        }
+    };
+
+    let (actual_output, warnings) = crate::auto_enum::expand(input, &Options::default()).unwrap();
+
+    assert_eq!(
+        expected_output.to_string(),
+        actual_output.to_token_stream().to_string()
+    );
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn auto_enum_unsupported_trait() {
+    let input = syn::parse_quote! {
+        fn values() -> impl Read + Send {
+            reader()
+        }
+    };
+
+    let actual_error = match crate::auto_enum::expand(input, &Options::default()) {
+        Ok(_) => panic!("expected an error"),
+        Err(err) => err,
+    };
+
+    assert_eq!(
+        "#[cain::auto_enum] cannot implement `Read` for the returned values, only `Iterator`, `DoubleEndedIterator`, `ExactSizeIterator`, `FusedIterator`, `Future`, `Debug`, `Display` and `Error` are supported",
+        actual_error.to_string()
+    );
 }
@@ -0,0 +1,183 @@
+//! Conditions of `if` expressions that are known while `cain!` is expanded.
+//!
+//! If the condition is a `bool` literal, or a `bool` constant that is declared in the
+//! `cain!` block, only one of the arms is live, and it takes the place of the `if`
+//! expression instead of forking the continuation.
+//!
+//! A procedural macro cannot evaluate `cfg!(...)`, so conditions that depend on it keep
+//! both arms. Instead of a runtime branch, each arm is guarded by a `#[cfg]` attribute,
+//! and the dead arm is removed by the compiler before it is type-checked.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+
+use crate::util::path_is;
+
+/// A condition that does not need to be evaluated at runtime.
+pub enum Condition {
+    Const(bool),
+
+    /// The condition holds if the configuration predicate holds.
+    Cfg(TokenStream),
+}
+
+impl Condition {
+    fn not(self) -> Condition {
+        match self {
+            Condition::Const(value) => Condition::Const(!value),
+            Condition::Cfg(pred) => Condition::Cfg(quote! { not(#pred) }),
+        }
+    }
+
+    fn and(self, other: Condition) -> Condition {
+        match (self, other) {
+            (Condition::Const(true), other) | (other, Condition::Const(true)) => other,
+            (Condition::Const(false), _) | (_, Condition::Const(false)) => Condition::Const(false),
+            (Condition::Cfg(left), Condition::Cfg(right)) => {
+                Condition::Cfg(quote! { all(#left, #right) })
+            }
+        }
+    }
+
+    fn or(self, other: Condition) -> Condition {
+        match (self, other) {
+            (Condition::Const(false), other) | (other, Condition::Const(false)) => other,
+            (Condition::Const(true), _) | (_, Condition::Const(true)) => Condition::Const(true),
+            (Condition::Cfg(left), Condition::Cfg(right)) => {
+                Condition::Cfg(quote! { any(#left, #right) })
+            }
+        }
+    }
+}
+
+/// Evaluate the condition of an `if` expression, with the `bool` constants in `consts`.
+pub fn evaluate(expr: &syn::Expr, consts: &[(syn::Ident, bool)]) -> Option<Condition> {
+    match expr {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Bool(lit),
+            ..
+        }) => Some(Condition::Const(lit.value)),
+
+        // constants that are declared later shadow those declared in enclosing blocks
+        syn::Expr::Path(expr_path) if expr_path.qself.is_none() => {
+            let ident = expr_path.path.get_ident()?;
+            consts
+                .iter()
+                .rev()
+                .find(|(name, _)| name == ident)
+                .map(|(_, value)| Condition::Const(*value))
+        }
+
+        syn::Expr::Macro(expr_macro) if is_cfg_macro(&expr_macro.mac.path) => {
+            Some(Condition::Cfg(expr_macro.mac.tokens.clone()))
+        }
+
+        syn::Expr::Paren(expr_paren) => evaluate(&expr_paren.expr, consts),
+        syn::Expr::Group(expr_group) => evaluate(&expr_group.expr, consts),
+
+        syn::Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Not(_),
+            expr,
+            ..
+        }) => Some(evaluate(expr, consts)?.not()),
+
+        syn::Expr::Binary(expr_binary) => {
+            let left = evaluate(&expr_binary.left, consts);
+            let right = evaluate(&expr_binary.right, consts);
+            match expr_binary.op {
+                syn::BinOp::And(_) => Some(left?.and(right?)),
+                syn::BinOp::Or(_) => Some(left?.or(right?)),
+                _ => None,
+            }
+        }
+
+        _ => None,
+    }
+}
+
+/// Returns whether a macro is the `cfg!` macro from the standard library.
+pub fn is_cfg_macro(path: &syn::Path) -> bool {
+    path_is(path, &["cfg"]) || path_is(path, &["std", "cfg"]) || path_is(path, &["core", "cfg"])
+}
+
+/// Collect the `bool` constants with known values that are declared by `stmts`.
+pub fn bool_consts(stmts: &[syn::Stmt], outer: &[(syn::Ident, bool)]) -> Vec<(syn::Ident, bool)> {
+    let mut consts = outer.to_vec();
+
+    for stmt in stmts {
+        if let syn::Stmt::Item(syn::Item::Const(item_const)) = stmt {
+            let is_bool = matches!(&*item_const.ty, syn::Type::Path(type_path) if type_path.qself.is_none() && type_path.path.is_ident("bool"));
+            if !is_bool {
+                continue;
+            }
+
+            if let Some(Condition::Const(value)) = evaluate(&item_const.expr, &consts) {
+                consts.push((item_const.ident.clone(), value));
+            }
+        }
+    }
+
+    consts
+}
+
+/// Returns the live arm of an `if` expression with a constant condition.
+pub fn live_branch(expr_if: &syn::ExprIf, value: bool) -> syn::Expr {
+    let mut block = if value {
+        expr_if.then_branch.clone()
+    } else {
+        match expr_if.else_branch.as_ref().map(|(_, expr)| &**expr) {
+            Some(syn::Expr::Block(expr_block))
+                if expr_block.attrs.is_empty() && expr_block.label.is_none() =>
+            {
+                expr_block.block.clone()
+            }
+            Some(expr) => syn::parse_quote! { { #expr } },
+            None => syn::parse_quote! { {} },
+        }
+    };
+
+    // keep the constants in the condition used
+    if !matches!(&*expr_if.cond, syn::Expr::Lit(_)) {
+        let cond = &expr_if.cond;
+        block.stmts.insert(0, syn::parse_quote! { let _ = #cond; });
+    }
+
+    // a block around a single expression would trigger the `unused_braces` lint
+    match &*block.stmts {
+        [syn::Stmt::Expr(expr)] => syn::Expr::Group(syn::ExprGroup {
+            attrs: Vec::new(),
+            group_token: Default::default(),
+            expr: Box::new(expr.clone()),
+        }),
+        _ => syn::Expr::Block(syn::ExprBlock {
+            attrs: Vec::new(),
+            label: None,
+            block,
+        }),
+    }
+}
+
+/// Replace an `if` expression whose condition is the configuration predicate `pred` by
+/// its arms, each of which is only compiled if the predicate has the corresponding value.
+pub fn cfg_branches(expr_if: &syn::ExprIf, pred: &TokenStream) -> Option<syn::Expr> {
+    let (_, else_branch) = expr_if.else_branch.as_ref()?;
+
+    // the continuation is wrapped in a block of its own
+    let then_branch = match &*expr_if.then_branch.stmts {
+        [syn::Stmt::Expr(syn::Expr::Block(expr_block))]
+            if expr_block.attrs.is_empty() && expr_block.label.is_none() =>
+        {
+            &expr_block.block
+        }
+        _ => &expr_if.then_branch,
+    };
+
+    Some(syn::parse_quote! {
+        {
+            #[cfg(#pred)]
+            #then_branch
+            #[cfg(not(#pred))]
+            #else_branch
+        }
+    })
+}
@@ -0,0 +1,200 @@
+//! Support for `cain::const_dispatch!`, which turns runtime values into constants.
+
+use proc_macro2::{Span, TokenStream};
+use quote::{quote, ToTokens};
+use syn::parse::ParseStream;
+
+use crate::dispatch_arms::{self, Dispatch, Kind, Values};
+use crate::options::Options;
+
+/// The number of values that a range may have if no `max_paths` option is given, which is
+/// checked before the range is expanded into values.
+const MAX_RANGE_VALUES: usize = dispatch_arms::MAX_PATHS;
+
+pub fn const_dispatch(input: TokenStream) -> syn::Result<TokenStream> {
+    let defaults = Options::load_defaults()?;
+    expand(input, &defaults)
+}
+
+/// Transform the input of the macro with the given default options.
+pub fn expand(input: TokenStream, defaults: &Options) -> syn::Result<TokenStream> {
+    let (options, dispatch) = dispatch_arms::parse_with_options(input, defaults, parse)?;
+    dispatch_arms::expand(&options, &dispatch)
+}
+
+/// Parse the input of the macro: `a in 1..=4, b in bool, |A, B| expr`.
+fn parse(input: ParseStream, options: &Options) -> syn::Result<Dispatch> {
+    let mut values = Vec::new();
+    while !input.peek(syn::Token![|]) {
+        values.push(parse_values(input, options)?);
+        input.parse::<syn::Token![,]>()?;
+    }
+
+    if values.is_empty() {
+        return Err(input.error("const_dispatch! expects at least one `value in values`"));
+    }
+
+    let (names, body) = dispatch_arms::parse_closure(input, "const_dispatch", values.len())?;
+
+    Ok(Dispatch {
+        macro_name: "const_dispatch",
+        kind: Kind::Const,
+        values,
+        names,
+        body,
+    })
+}
+
+/// Parse `value in values`.
+fn parse_values(input: ParseStream, options: &Options) -> syn::Result<Values> {
+    let scrutinee = input.parse::<syn::Expr>()?;
+    input.parse::<syn::Token![in]>()?;
+
+    if input.peek(syn::Ident) && input.fork().parse::<syn::Ident>()? == "bool" {
+        input.parse::<syn::Ident>()?;
+        return Ok(Values {
+            scrutinee,
+            arms: vec![
+                (quote! { false }, quote! { false }),
+                (quote! { true }, quote! { true }),
+            ],
+            exhaustive: true,
+        });
+    }
+
+    let values = match input.parse::<syn::Expr>()? {
+        syn::Expr::Array(expr_array) => expr_array
+            .elems
+            .iter()
+            .map(|elem| {
+                if lit_int(elem)?.is_none() && !matches!(elem, syn::Expr::Lit(_)) {
+                    return Err(syn::Error::new_spanned(
+                        elem,
+                        "const_dispatch! values must be literals",
+                    ));
+                }
+                Ok(elem.to_token_stream())
+            })
+            .collect::<syn::Result<Vec<_>>>()?,
+
+        syn::Expr::Range(expr_range) => range_values(&expr_range, options)?,
+
+        expr => {
+            return Err(syn::Error::new_spanned(
+                expr,
+                "const_dispatch! expects a range of integers, a list of literals, or `bool`",
+            ))
+        }
+    };
+
+    if values.is_empty() {
+        return Err(syn::Error::new_spanned(
+            scrutinee,
+            "const_dispatch! has no values to dispatch on",
+        ));
+    }
+
+    Ok(Values {
+        scrutinee,
+        arms: values
+            .into_iter()
+            .map(|value| (value.clone(), value))
+            .collect(),
+        exhaustive: false,
+    })
+}
+
+/// Returns the value and the suffix of an integer literal, which may be negated.
+fn lit_int(expr: &syn::Expr) -> syn::Result<Option<(i128, String)>> {
+    let (negative, lit) = match expr {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Int(lit),
+            ..
+        }) => (false, lit),
+        syn::Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Neg(_),
+            expr,
+            ..
+        }) => match &**expr {
+            syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Int(lit),
+                ..
+            }) => (true, lit),
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+
+    let value = lit.base10_parse::<i128>()?;
+    Ok(Some((
+        if negative { -value } else { value },
+        lit.suffix().to_string(),
+    )))
+}
+
+fn range_values(expr_range: &syn::ExprRange, options: &Options) -> syn::Result<Vec<TokenStream>> {
+    let bound = |expr: &Option<Box<syn::Expr>>| -> syn::Result<(i128, String)> {
+        match expr {
+            Some(expr) => lit_int(expr)?.ok_or_else(|| {
+                syn::Error::new_spanned(
+                    expr,
+                    "const_dispatch! range bounds must be integer literals",
+                )
+            }),
+            None => Err(syn::Error::new_spanned(
+                expr_range,
+                "const_dispatch! ranges must have both bounds",
+            )),
+        }
+    };
+
+    let (from, from_suffix) = bound(&expr_range.from)?;
+    let (to, to_suffix) = bound(&expr_range.to)?;
+    let to = match expr_range.limits {
+        syn::RangeLimits::HalfOpen(_) => to - 1,
+        syn::RangeLimits::Closed(_) => to,
+    };
+
+    // every value gets its own copy of the body, so the range is checked before it is
+    // expanded into values
+    let count = to.checked_sub(from).and_then(|count| count.checked_add(1));
+    let max_values = options.max_paths.unwrap_or(MAX_RANGE_VALUES);
+    match count {
+        Some(count) if count <= max_values as i128 => (),
+        _ => {
+            let limit = match options.max_paths {
+                Some(max_paths) => format!("`max_paths = {}`", max_paths),
+                None => format!(
+                    "the limit of {} values without a `max_paths` option",
+                    MAX_RANGE_VALUES
+                ),
+            };
+            return Err(syn::Error::new_spanned(
+                expr_range,
+                format!(
+                    "const_dispatch! range has {} values, which exceeds {}",
+                    count.map_or_else(|| "too many".to_string(), |count| count.to_string()),
+                    limit,
+                ),
+            ));
+        }
+    }
+
+    let suffix = if from_suffix.is_empty() {
+        to_suffix
+    } else {
+        from_suffix
+    };
+
+    Ok((from..=to)
+        .map(|value| {
+            let lit = proc_macro2::Literal::i128_unsuffixed(value.abs());
+            let lit = syn::LitInt::new(&format!("{}{}", lit, suffix), Span::call_site());
+            if value < 0 {
+                quote! { -#lit }
+            } else {
+                quote! { #lit }
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,170 @@
+//! Support for deferred initialization, as in `let x; if c { x = a; } else { x = b; }`.
+//!
+//! A `let` statement without an initializer is not a branch point by itself. When the first
+//! statement that uses its variable assigns to it, either directly or as the last statement
+//! of every arm of an `if` or `match` statement, the declaration is moved to that statement,
+//! and the assigned values become its initializer. The statement then forks the rest of the
+//! block like any other initializer.
+//!
+//! Moving a declaration changes when its variable is dropped, relative to the variables that
+//! are declared in between. So a declaration is only moved if the assigned value forks, and
+//! if no other `let` statement lies between the declaration and the assignment.
+//!
+//! An arm that does not assign to the variable, such as one that returns early, would get
+//! its own copy of the rest of the block with a variable of the wrong type, so such
+//! statements are left as they are.
+
+use syn::visit::Visit;
+
+use crate::util::UsedIdents;
+
+/// Move the declarations in `stmts` that are initialized later to the statements that
+/// initialize them, if `forks` returns whether the resulting `let` statement forks.
+pub fn move_declarations(stmts: &mut Vec<syn::Stmt>, forks: impl Fn(&syn::Local) -> bool) {
+    let mut index = 0;
+    while index < stmts.len() {
+        let initialized =
+            initialize(&stmts[index], &stmts[index + 1..]).filter(|(_, local)| forks(local));
+        if let Some((offset, local)) = initialized {
+            stmts[index + 1 + offset] = syn::Stmt::Local(local);
+            stmts.remove(index);
+        } else {
+            index += 1;
+        }
+    }
+}
+
+/// Returns the position in `rest` of the statement that initializes the variable that
+/// `stmt` declares, and the `let` statement that replaces it.
+fn initialize(stmt: &syn::Stmt, rest: &[syn::Stmt]) -> Option<(usize, syn::Local)> {
+    let local = match stmt {
+        syn::Stmt::Local(local) if local.init.is_none() => local,
+        _ => return None,
+    };
+    let ident = declared_ident(&local.pat)?.to_string();
+
+    // variables that are declared in between would be dropped in a different order
+    let offset = rest.iter().position(|stmt| match stmt {
+        syn::Stmt::Local(_) => true,
+        stmt => uses(&ident, |used| used.visit_stmt(stmt)),
+    })?;
+
+    let (attrs, init) = match rest[offset].clone() {
+        syn::Stmt::Expr(expr) | syn::Stmt::Semi(expr, _) => match expr {
+            syn::Expr::Assign(assign) if is_ident(&assign.left, &ident) => {
+                (assign.attrs, *assign.right)
+            }
+            expr => (Vec::new(), assigned_value(expr, &ident)?),
+        },
+        _ => return None,
+    };
+
+    let mut local = local.clone();
+    local.attrs.extend(attrs);
+    local.init = Some((Default::default(), Box::new(init)));
+    Some((offset, local))
+}
+
+/// Returns the identifier that a `let` statement without an initializer declares, if it
+/// declares a single variable, possibly with a type annotation.
+fn declared_ident(pat: &syn::Pat) -> Option<&syn::Ident> {
+    match pat {
+        syn::Pat::Ident(pat_ident) if pat_ident.by_ref.is_none() && pat_ident.subpat.is_none() => {
+            Some(&pat_ident.ident)
+        }
+        syn::Pat::Type(pat_type) => declared_ident(&pat_type.pat),
+        _ => None,
+    }
+}
+
+/// Turn an `if` or `match` statement that assigns to `ident` at the end of every arm into an
+/// expression with the assigned values.
+fn assigned_value(expr: syn::Expr, ident: &str) -> Option<syn::Expr> {
+    match expr {
+        syn::Expr::If(mut if_expr) => {
+            if uses(ident, |used| used.visit_expr(&if_expr.cond)) {
+                return None;
+            }
+
+            if_expr.then_branch = assigned_block(if_expr.then_branch, ident)?;
+            let (else_token, else_branch) = if_expr.else_branch?;
+            let else_branch = match *else_branch {
+                syn::Expr::Block(mut expr_block) => {
+                    expr_block.block = assigned_block(expr_block.block, ident)?;
+                    syn::Expr::Block(expr_block)
+                }
+                else_branch => assigned_value(else_branch, ident)?,
+            };
+            if_expr.else_branch = Some((else_token, Box::new(else_branch)));
+
+            Some(syn::Expr::If(if_expr))
+        }
+
+        syn::Expr::Match(mut match_expr) => {
+            if uses(ident, |used| used.visit_expr(&match_expr.expr)) {
+                return None;
+            }
+
+            for arm in &mut match_expr.arms {
+                if let Some((_, guard)) = &arm.guard {
+                    if uses(ident, |used| used.visit_expr(guard)) {
+                        return None;
+                    }
+                }
+
+                let body =
+                    std::mem::replace(&mut *arm.body, syn::Expr::Verbatim(Default::default()));
+                *arm.body = match body {
+                    syn::Expr::Assign(assign) if is_ident(&assign.left, ident) => *assign.right,
+                    syn::Expr::Block(mut expr_block) => {
+                        expr_block.block = assigned_block(expr_block.block, ident)?;
+                        syn::Expr::Block(expr_block)
+                    }
+                    body => assigned_value(body, ident)?,
+                };
+            }
+
+            Some(syn::Expr::Match(match_expr))
+        }
+
+        _ => None,
+    }
+}
+
+/// Turn a block that assigns to `ident` in its last statement into a block with the
+/// assigned value as its tail expression.
+fn assigned_block(mut block: syn::Block, ident: &str) -> Option<syn::Block> {
+    let last = block.stmts.pop()?;
+    if block
+        .stmts
+        .iter()
+        .any(|stmt| uses(ident, |used| used.visit_stmt(stmt)))
+    {
+        return None;
+    }
+
+    let value = match last {
+        syn::Stmt::Expr(syn::Expr::Assign(assign))
+        | syn::Stmt::Semi(syn::Expr::Assign(assign), _)
+            if is_ident(&assign.left, ident) =>
+        {
+            *assign.right
+        }
+        syn::Stmt::Expr(expr) | syn::Stmt::Semi(expr, _) => assigned_value(expr, ident)?,
+        _ => return None,
+    };
+    block.stmts.push(syn::Stmt::Expr(value));
+
+    Some(block)
+}
+
+fn is_ident(expr: &syn::Expr, ident: &str) -> bool {
+    matches!(expr, syn::Expr::Path(expr_path) if expr_path.qself.is_none() && expr_path.path.is_ident(ident))
+}
+
+/// Returns whether the identifiers that `visit` collects include `ident`.
+fn uses(ident: &str, visit: impl FnOnce(&mut UsedIdents)) -> bool {
+    let mut used = UsedIdents::default();
+    visit(&mut used);
+    used.idents.contains(ident)
+}
@@ -0,0 +1,121 @@
+//! Support for `#[derive(cain::Dispatch)]`.
+//!
+//! Procedural macros can only export macros, so instead of a trait with a generic method,
+//! the derive generates a `macro_rules!` macro that matches on every variant and
+//! duplicates its body into each arm. `cain!` forks the rest of a block at
+//! `let inner = shape_dispatch!(shape);` if the enum is declared in the block, or if the
+//! macro is listed in the `dispatch_macros` option.
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+
+use crate::util::path_is;
+
+pub fn derive_dispatch(input: syn::DeriveInput) -> syn::Result<TokenStream> {
+    let data = match &input.data {
+        syn::Data::Enum(data) => data,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "derive(Dispatch) only supports enums",
+            ))
+        }
+    };
+
+    if data.variants.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "derive(Dispatch) needs at least one variant",
+        ));
+    }
+
+    let variants = data
+        .variants
+        .iter()
+        .map(|variant| match &variant.fields {
+            syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => Ok(&variant.ident),
+            _ => Err(syn::Error::new_spanned(
+                variant,
+                "derive(Dispatch) only supports variants with a single unnamed field",
+            )),
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    let name = &input.ident;
+    let macro_name = macro_name(name);
+
+    Ok(quote! {
+        #[allow(unused_macros)]
+        macro_rules! #macro_name {
+            ($value:expr, |$inner:pat_param| $body:expr $(,)?) => {
+                match $value {
+                    #( #name::#variants($inner) => $body, )*
+                }
+            };
+        }
+
+        #[allow(unused_imports)]
+        pub(crate) use #macro_name;
+    })
+}
+
+/// Returns the name of the macro for an enum, such as `shape_dispatch` for `Shape`.
+fn macro_name(name: &syn::Ident) -> syn::Ident {
+    format_ident!("{}_dispatch", snake_case(&name.to_string()))
+}
+
+/// Collect the macros that are generated for the enums in `stmts` that derive `Dispatch`,
+/// along with the `outer` ones.
+pub fn dispatch_macros(stmts: &[syn::Stmt], outer: &[syn::Ident]) -> Vec<syn::Ident> {
+    let mut macros = outer.to_vec();
+
+    for stmt in stmts {
+        if let syn::Stmt::Item(syn::Item::Enum(item_enum)) = stmt {
+            if item_enum.attrs.iter().any(derives_dispatch) {
+                macros.push(macro_name(&item_enum.ident));
+            }
+        }
+    }
+
+    macros
+}
+
+/// Returns whether an attribute is `#[derive(..., Dispatch)]` or `#[derive(cain::Dispatch)]`.
+fn derives_dispatch(attr: &syn::Attribute) -> bool {
+    if !attr.path.is_ident("derive") {
+        return false;
+    }
+
+    let paths = match attr
+        .parse_args_with(syn::punctuated::Punctuated::<syn::Path, syn::Token![,]>::parse_terminated)
+    {
+        Ok(paths) => paths,
+        Err(_) => return false,
+    };
+
+    paths
+        .iter()
+        .any(|path| path_is(path, &["Dispatch"]) || path_is(path, &["cain", "Dispatch"]))
+}
+
+/// Convert a type name such as `HttpMethod` to `http_method`.
+fn snake_case(name: &str) -> String {
+    let chars = name.chars().collect::<Vec<_>>();
+
+    let mut snake = String::new();
+    for (index, &ch) in chars.iter().enumerate() {
+        if ch.is_uppercase() && index > 0 {
+            let prev = chars[index - 1];
+            let next_is_lower = chars
+                .get(index + 1)
+                .into_iter()
+                .any(|next| next.is_lowercase());
+            if prev != '_' && (prev.is_lowercase() || prev.is_numeric() || next_is_lower) {
+                snake.push('_');
+            }
+        }
+        snake.extend(ch.to_lowercase());
+    }
+
+    snake
+}
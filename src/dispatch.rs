@@ -0,0 +1,89 @@
+//! Support for dispatching branching `let` statements through trait object references.
+//!
+//! Each arm of a branching initializer stores its value in a separate, deferred-initialized
+//! local variable, and evaluates to a reference to it. The references are coerced to the
+//! trait object type, so that the rest of the block only has to be emitted once.
+
+use quote::format_ident;
+use syn::visit_mut::VisitMut;
+
+use crate::util::{is_diverging, unique_ident};
+
+const SLOT: &str = "__cain_slot";
+
+/// Wrap an arm value so that it is stored in a slot, and evaluates to a reference to it.
+pub fn wrap_slot(dispatch: &syn::TypeReference, expr: syn::Expr) -> syn::Expr {
+    let slot = format_ident!("{}", SLOT);
+    let mutability = &dispatch.mutability;
+
+    syn::parse_quote! {
+        {
+            #slot = #expr;
+            &#mutability #slot
+        }
+    }
+}
+
+/// Give every slot that was inserted by [`wrap_slot`] a unique name, and return the
+/// declarations of the slots.
+pub fn declare_slots(dispatch: &syn::TypeReference, expr: &mut syn::Expr) -> Vec<syn::Stmt> {
+    let mut visitor = SlotVisitor { slots: Vec::new() };
+    visitor.visit_expr_mut(expr);
+
+    let mutability = &dispatch.mutability;
+    visitor
+        .slots
+        .into_iter()
+        .map(|slot| syn::parse_quote! { let #mutability #slot; })
+        .collect()
+}
+
+struct SlotVisitor {
+    slots: Vec<syn::Ident>,
+}
+
+fn is_slot(expr: &syn::Expr) -> bool {
+    matches!(expr, syn::Expr::Path(expr_path) if expr_path.path.is_ident(SLOT))
+}
+
+impl VisitMut for SlotVisitor {
+    fn visit_block_mut(&mut self, i: &mut syn::Block) {
+        syn::visit_mut::visit_block_mut(self, i);
+
+        if let [syn::Stmt::Semi(syn::Expr::Assign(assign), _), syn::Stmt::Expr(syn::Expr::Reference(reference))] =
+            &mut i.stmts[..]
+        {
+            if is_slot(&assign.left) && is_slot(&reference.expr) {
+                unwrap_block(&mut assign.right);
+
+                // an arm that never produces a value needs no slot, which would not get a
+                // type otherwise
+                if is_diverging(&assign.right) {
+                    let value = (*assign.right).clone();
+                    i.stmts = vec![syn::Stmt::Expr(value)];
+                    return;
+                }
+
+                let slot = unique_ident();
+                *assign.left = syn::parse_quote! { #slot };
+                *reference.expr = syn::parse_quote! { #slot };
+                self.slots.push(slot);
+            }
+        }
+    }
+}
+
+/// Remove the braces around a block with a single expression, which would otherwise
+/// trigger `unused_braces` warnings for the values of `if` branches.
+pub fn unwrap_block(expr: &mut syn::Expr) {
+    if let syn::Expr::Block(syn::ExprBlock {
+        attrs,
+        label: None,
+        block,
+    }) = expr
+    {
+        if let ([], [syn::Stmt::Expr(inner)]) = (&attrs[..], &mut block.stmts[..]) {
+            *expr = std::mem::replace(inner, syn::parse_quote! { () });
+        }
+    }
+}
@@ -0,0 +1,366 @@
+//! The arm nesting that is shared by `const_dispatch!` and `type_dispatch!`.
+//!
+//! Each runtime value that is dispatched on is a branch point with one arm per pattern, and
+//! the expression is forked into every combination of arms just like the continuation of a
+//! branching `let` statement. Every arm starts by binding the names from the closure to
+//! marker macros, which are replaced by the constants or types of the arm once the arms are
+//! nested.
+
+use proc_macro2::{Span, TokenStream, TokenTree};
+use quote::{format_ident, quote, ToTokens};
+use syn::parse::{ParseStream, Parser};
+use syn::visit_mut::VisitMut;
+
+use crate::macros::{fork_expr, Context};
+use crate::options::Options;
+use crate::placeholder::PlaceholderId;
+use crate::util::path_is;
+use crate::warnings::Warnings;
+
+const MARKER: &str = "__cain_arm";
+
+/// The number of paths that a dispatch may fork into if no `max_paths` option is given.
+pub const MAX_PATHS: usize = 1024;
+
+/// The parsed input of a dispatch macro.
+pub struct Dispatch {
+    /// The name of the macro, for error messages.
+    pub macro_name: &'static str,
+
+    /// Whether the names are replaced by constants or types.
+    pub kind: Kind,
+
+    pub values: Vec<Values>,
+    pub names: Vec<syn::Ident>,
+    pub body: syn::Expr,
+}
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Const,
+    Type,
+}
+
+/// A runtime value, and the arms that it is dispatched on.
+pub struct Values {
+    pub scrutinee: syn::Expr,
+
+    /// The pattern of each arm, and what the name is replaced with in the arm.
+    pub arms: Vec<(TokenStream, TokenStream)>,
+
+    /// Whether the arms are known to cover all values, so that no arm has to panic.
+    pub exhaustive: bool,
+}
+
+/// Parse the options at the start of the input of a dispatch macro, followed by the rest
+/// of the input, which may depend on the options.
+pub fn parse_with_options<T>(
+    input: TokenStream,
+    defaults: &Options,
+    parse: impl FnOnce(ParseStream, &Options) -> syn::Result<T>,
+) -> syn::Result<(Options, T)> {
+    let parser = |input: ParseStream| {
+        let options = Options::parse(input, defaults)?;
+        let value = parse(input, &options)?;
+        Ok((options, value))
+    };
+    parser.parse2(input)
+}
+
+/// Parse the closure `|A, B| expr` at the end of the input of a dispatch macro, and return
+/// the names and the body.
+pub fn parse_closure(
+    input: ParseStream,
+    macro_name: &str,
+    count: usize,
+) -> syn::Result<(Vec<syn::Ident>, syn::Expr)> {
+    let closure = input.parse::<syn::ExprClosure>()?;
+    input.parse::<Option<syn::Token![,]>>()?;
+
+    if closure.asyncness.is_some() || closure.movability.is_some() || closure.capture.is_some() {
+        return Err(syn::Error::new_spanned(
+            &closure,
+            format!("{}! expects a plain closure `|N| expr`", macro_name),
+        ));
+    }
+
+    let names = closure
+        .inputs
+        .iter()
+        .map(|pat| match pat {
+            syn::Pat::Ident(pat_ident)
+                if pat_ident.by_ref.is_none()
+                    && pat_ident.mutability.is_none()
+                    && pat_ident.subpat.is_none() =>
+            {
+                Ok(pat_ident.ident.clone())
+            }
+            _ => Err(syn::Error::new_spanned(
+                pat,
+                format!("{}! expects a name", macro_name),
+            )),
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    if names.len() != count {
+        return Err(syn::Error::new_spanned(
+            &closure.inputs,
+            format!(
+                "{}! expects {} names, one for each value, but got {}",
+                macro_name,
+                count,
+                names.len()
+            ),
+        ));
+    }
+
+    Ok((names, *closure.body))
+}
+
+/// Returns whether a macro is `cain::const_dispatch!` or `cain::type_dispatch!`.
+pub fn is_builtin_dispatch_macro(mac: &syn::Macro) -> bool {
+    path_is(&mac.path, &["cain", "const_dispatch"])
+        || path_is(&mac.path, &["cain", "type_dispatch"])
+}
+
+/// Returns whether the arguments of a dispatch macro end with a closure. Without one, as in
+/// `const_dispatch!(n in 1..=4)` or `shape_dispatch!(shape)`, `cain!` can give the rest of a
+/// block to the macro as the body of the closure.
+pub fn has_closure(mac: &syn::Macro) -> bool {
+    // the closure is the last of the comma-separated arguments
+    let last_arg = mac
+        .tokens
+        .clone()
+        .into_iter()
+        .collect::<Vec<_>>()
+        .split(|token| matches!(token, TokenTree::Punct(punct) if punct.as_char() == ','))
+        .rfind(|arg| !arg.is_empty())
+        .map(|arg| arg.to_vec());
+    matches!(
+        last_arg.as_deref(),
+        Some([TokenTree::Punct(punct), ..]) if punct.as_char() == '|'
+    )
+}
+
+/// Nest the arms of all values, and replace the names in each copy of the body.
+pub fn expand(options: &Options, dispatch: &Dispatch) -> syn::Result<TokenStream> {
+    let paths = dispatch.values.iter().try_fold(1_usize, |paths, values| {
+        paths.checked_mul(values.arms.len())
+    });
+    let max_paths = options.max_paths.unwrap_or(MAX_PATHS);
+    match paths {
+        Some(paths) if paths <= max_paths => (),
+        paths => {
+            let limit = match options.max_paths {
+                Some(max_paths) => format!("`max_paths = {}`", max_paths),
+                None => format!(
+                    "the limit of {} paths without a `max_paths` option",
+                    MAX_PATHS
+                ),
+            };
+            return Err(syn::Error::new(
+                Span::call_site(),
+                format!(
+                    "{}! forks into {} paths, which exceeds {}",
+                    dispatch.macro_name,
+                    paths.map_or_else(|| "too many".to_string(), |paths| paths.to_string()),
+                    limit,
+                ),
+            ));
+        }
+    }
+
+    // only the nesting of the arms is needed, not any of the other options
+    let options = Options {
+        max_paths: options.max_paths,
+        ..Options::default()
+    };
+    let warnings = Warnings::default();
+    let cx = Context::new(&options, &warnings, false);
+
+    let marker = format_ident!("{}", MARKER);
+    let mut branches = Vec::new();
+    let mut lets = Vec::new();
+    for (values, name) in dispatch.values.iter().zip(&dispatch.names) {
+        let placeholder_id = PlaceholderId::new();
+        lets.push(quote! { let #name = #placeholder_id; });
+
+        let scrutinee = &values.scrutinee;
+        let arms = values.arms.iter().map(|(pat, replacement)| {
+            quote! { #pat => #marker!(#replacement), }
+        });
+        let fallback = (!values.exhaustive).then(|| {
+            quote! {
+                #[allow(unreachable_patterns)]
+                _ => #marker!(),
+            }
+        });
+        branches.push((
+            placeholder_id,
+            syn::parse_quote! {
+                match #scrutinee {
+                    #(#arms)*
+                    #fallback
+                }
+            },
+        ));
+    }
+
+    let body = &dispatch.body;
+    let expr = syn::parse_quote! {
+        {
+            #(#lets)*
+            #body
+        }
+    };
+
+    let mut expr = fork_expr(cx, expr, branches, None)?;
+    let mut visitor = Substitute {
+        dispatch,
+        error: None,
+    };
+    visitor.visit_expr_mut(&mut expr);
+    if let Some(error) = visitor.error {
+        return Err(error);
+    }
+
+    Ok(expr.into_token_stream())
+}
+
+/// Replaces the names by the constants or types of each arm.
+struct Substitute<'a> {
+    dispatch: &'a Dispatch,
+    error: Option<syn::Error>,
+}
+
+impl<'a> VisitMut for Substitute<'a> {
+    fn visit_block_mut(&mut self, i: &mut syn::Block) {
+        let mut replacements = Vec::new();
+        while let Some((name, replacement)) = i.stmts.first().and_then(marker_value) {
+            i.stmts.remove(0);
+
+            // the value does not match any of the arms
+            if replacement.is_empty() {
+                let index = self
+                    .dispatch
+                    .names
+                    .iter()
+                    .position(|other| *other == name)
+                    .unwrap();
+                let message = format!(
+                    "`{}` is not one of the values of `{}!`",
+                    self.dispatch.values[index].scrutinee.to_token_stream(),
+                    self.dispatch.macro_name,
+                );
+                i.stmts = vec![syn::Stmt::Expr(syn::parse_quote! { panic!(#message) })];
+                return;
+            }
+
+            replacements.push((name, replacement));
+        }
+
+        if replacements.is_empty() {
+            return syn::visit_mut::visit_block_mut(self, i);
+        }
+
+        let stmts = &i.stmts;
+        let tokens = substitute(self.dispatch.kind, quote! { #(#stmts)* }, &replacements);
+        match syn::Block::parse_within.parse2(tokens) {
+            Ok(stmts) => i.stmts = stmts,
+            // a name may be used where its replacement is not allowed, as in `let N = 1;`
+            Err(error) => {
+                self.error.get_or_insert(syn::Error::new(
+                    error.span(),
+                    format!(
+                        "{}! cannot replace the names in the body with the values of an arm: {}",
+                        self.dispatch.macro_name, error
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// Returns the name and the replacement of a marker `let` statement.
+fn marker_value(stmt: &syn::Stmt) -> Option<(syn::Ident, TokenStream)> {
+    match stmt {
+        syn::Stmt::Local(syn::Local {
+            pat: syn::Pat::Ident(pat_ident),
+            init: Some((_, init)),
+            ..
+        }) => match &**init {
+            syn::Expr::Macro(expr_macro) if expr_macro.mac.path.is_ident(MARKER) => {
+                Some((pat_ident.ident.clone(), expr_macro.mac.tokens.clone()))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn substitute(
+    kind: Kind,
+    tokens: TokenStream,
+    replacements: &[(syn::Ident, TokenStream)],
+) -> TokenStream {
+    let tokens = tokens.into_iter().collect::<Vec<_>>();
+    let mut output = TokenStream::new();
+
+    for (index, token) in tokens.iter().enumerate() {
+        match token {
+            TokenTree::Ident(ident) => match replacements.iter().find(|(name, _)| ident == name) {
+                Some((_, replacement)) => {
+                    let prev = index.checked_sub(1).map(|index| &tokens[index]);
+                    let next = &tokens[index + 1..];
+                    output.extend(wrap_replacement(kind, replacement, prev, next));
+                }
+                None => output.extend(Some(token.clone())),
+            },
+            TokenTree::Group(group) => {
+                let mut new_group = proc_macro2::Group::new(
+                    group.delimiter(),
+                    substitute(kind, group.stream(), replacements),
+                );
+                new_group.set_span(group.span());
+                output.extend(Some(TokenTree::Group(new_group)));
+            }
+            token => output.extend(Some(token.clone())),
+        }
+    }
+
+    output
+}
+
+/// Wrap a replacement so that it can be used where the name was.
+fn wrap_replacement(
+    kind: Kind,
+    replacement: &TokenStream,
+    prev: Option<&TokenTree>,
+    next: &[TokenTree],
+) -> TokenStream {
+    let is_punct = |token: Option<&TokenTree>, chars: &[char]| matches!(token, Some(TokenTree::Punct(punct)) if chars.contains(&punct.as_char()));
+
+    match kind {
+        // negative values need braces to be used as generic arguments, but braces at the
+        // start of a statement are a block
+        Kind::Const if replacement.to_string().starts_with('-') => {
+            if is_punct(prev, &['<', ',']) {
+                quote! { { #replacement } }
+            } else {
+                quote! { (#replacement) }
+            }
+        }
+
+        // a type that is followed by a path separator has to be qualified, as in
+        // `<Vec<u8>>::new()`
+        Kind::Type
+            if replacement.clone().into_iter().count() > 1
+                && is_punct(next.first(), &[':'])
+                && is_punct(next.get(1), &[':']) =>
+        {
+            quote! { <#replacement> }
+        }
+
+        Kind::Const | Kind::Type => replacement.clone(),
+    }
+}
@@ -0,0 +1,74 @@
+//! Support for `cain::each!(...)`, which unrolls a `for` loop over values of different types.
+//!
+//! A tuple cannot be iterated over, but `for x in cain::each!(a, b, c) { body }` gets one
+//! copy of the loop for each value, iterating over just that value, so that every copy of
+//! the body is type-checked with the type of its own value. The copies are wrapped in a
+//! `loop` that runs once, and every `break` of the original loop leaves that `loop` instead
+//! of its copy. `continue` moves on to the next copy as it is.
+
+use proc_macro2::Span;
+use quote::quote;
+
+use crate::breaks;
+use crate::util::{parse_macro_args, path_is, unique_ident};
+
+/// Returns the values that the iterator of a `for` loop is unrolled over, if it is
+/// `cain::each!(...)` or a tuple with the `#[cain::each]` attribute.
+pub fn values(expr: &syn::Expr) -> Option<syn::Result<Vec<syn::Expr>>> {
+    match expr {
+        syn::Expr::Macro(expr_macro) if is_each_path(&expr_macro.mac.path) => {
+            Some(parse_macro_args(&expr_macro.mac).map(|args| args.into_iter().collect()))
+        }
+
+        syn::Expr::Tuple(expr_tuple)
+            if expr_tuple.attrs.iter().any(|attr| is_each_path(&attr.path)) =>
+        {
+            if expr_tuple.attrs.len() > 1 {
+                return Some(Err(syn::Error::new_spanned(
+                    expr_tuple,
+                    "cain! does not support other attributes on a `#[cain::each]` tuple",
+                )));
+            }
+            Some(Ok(expr_tuple.elems.iter().cloned().collect()))
+        }
+
+        syn::Expr::Paren(expr_paren) => values(&expr_paren.expr),
+        syn::Expr::Group(expr_group) => values(&expr_group.expr),
+
+        _ => None,
+    }
+}
+
+/// Returns whether a path is `cain::each`.
+fn is_each_path(path: &syn::Path) -> bool {
+    path_is(path, &["cain", "each"])
+}
+
+/// Replace a `for` loop by a copy of the loop for each of `values`.
+pub fn unroll(for_loop: &syn::ExprForLoop, values: Vec<syn::Expr>) -> syn::Expr {
+    let syn::ExprForLoop {
+        attrs,
+        label,
+        pat,
+        body,
+        ..
+    } = for_loop;
+
+    let target = syn::Lifetime::new(&format!("'{}", unique_ident()), Span::call_site());
+    let mut body = body.clone();
+    breaks::retarget_breaks(&mut body, label.as_ref(), &target);
+
+    let copies = values.iter().map(|value| {
+        quote! {
+            #label for #pat in ::core::iter::once(#value) #body
+        }
+    });
+
+    syn::parse_quote! {
+        #(#attrs)*
+        #target: loop {
+            #(#copies)*
+            break;
+        }
+    }
+}
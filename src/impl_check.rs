@@ -0,0 +1,174 @@
+//! Support for `let` statements with `impl Trait` type annotations.
+//!
+//! The annotation is removed from the statement, and the initializer is instead passed
+//! through a local generic function that requires the declared bounds. After the
+//! continuation has been forked, the functions are named after the arm that they are in,
+//! so that a type error names the arm that does not implement the bounds.
+
+use quote::format_ident;
+use syn::visit::Visit;
+use syn::visit_mut::VisitMut;
+
+const CHECK_FN: &str = "__cain_impl_check";
+
+/// Remove an `impl Trait` type annotation from a `let` statement, and return the trait
+/// bounds of the annotation.
+///
+/// The check is a local item, so a trait bound that names a lifetime of the enclosing
+/// function is an error.
+pub fn take_impl_bounds(local: &mut syn::Local) -> syn::Result<Option<Vec<syn::TraitBound>>> {
+    let pat_type = match (&local.pat, &local.init) {
+        (syn::Pat::Type(pat_type), Some(_)) => pat_type,
+        _ => return Ok(None),
+    };
+
+    let type_impl_trait = match &*pat_type.ty {
+        syn::Type::ImplTrait(type_impl_trait) => type_impl_trait,
+        _ => return Ok(None),
+    };
+
+    let bounds = type_impl_trait
+        .bounds
+        .iter()
+        .filter_map(|bound| match bound {
+            syn::TypeParamBound::Trait(trait_bound) => Some(trait_bound.clone()),
+            // lifetimes of the enclosing function cannot be named in a local item
+            syn::TypeParamBound::Lifetime(_) => None,
+        })
+        .collect::<Vec<_>>();
+
+    for bound in &bounds {
+        let mut visitor = OuterLifetimes::default();
+        visitor.visit_trait_bound(bound);
+        if let Some(lifetime) = visitor.outer.first() {
+            return Err(syn::Error::new_spanned(
+                lifetime,
+                format!(
+                    "cain! cannot check `impl Trait` bounds that name the lifetime `{}` of the enclosing function",
+                    lifetime
+                ),
+            ));
+        }
+    }
+
+    local.pat = (*pat_type.pat).clone();
+
+    Ok(Some(bounds))
+}
+
+/// Collects the lifetimes in a bound other than `'static` and those declared by
+/// `for<...>` in the bound itself.
+#[derive(Default)]
+struct OuterLifetimes {
+    declared: Vec<syn::Lifetime>,
+    outer: Vec<syn::Lifetime>,
+}
+
+impl<'ast> Visit<'ast> for OuterLifetimes {
+    fn visit_bound_lifetimes(&mut self, i: &'ast syn::BoundLifetimes) {
+        self.declared
+            .extend(i.lifetimes.iter().map(|def| def.lifetime.clone()));
+    }
+
+    fn visit_lifetime(&mut self, i: &'ast syn::Lifetime) {
+        if i.ident != "static" && !self.declared.contains(i) {
+            self.outer.push(i.clone());
+        }
+    }
+}
+
+/// Wrap an expression in a check that its value implements all of `bounds`. The type
+/// parameter of the check has a name that the bounds cannot refer to by accident.
+pub fn wrap_impl_check(bounds: &[syn::TraitBound], expr: syn::Expr) -> syn::Expr {
+    let check_fn = format_ident!("{}", CHECK_FN);
+
+    syn::parse_quote! {
+        {
+            fn #check_fn<__CainValue: #(#bounds)+*>(value: __CainValue) -> __CainValue { value }
+            #check_fn(#expr)
+        }
+    }
+}
+
+/// Prefix the checks that were inserted by [`wrap_impl_check`] in an arm of a branch point
+/// with the number of the arm. The checks in an arm of a branch point that is nested into
+/// the arm have already been numbered by that arm, and keep that number after the prefix.
+pub fn number_arm(expr: &mut syn::Expr, arm: usize) {
+    arm_visitor(arm).visit_expr_mut(expr);
+}
+
+/// Like [`number_arm`], for an arm that is a block, as in the branches of an `if`.
+pub fn number_arm_block(block: &mut syn::Block, arm: usize) {
+    arm_visitor(arm).visit_block_mut(block);
+}
+
+fn arm_visitor(arm: usize) -> CheckVisitor<impl FnMut(&str, &syn::ItemFn) -> syn::Ident> {
+    CheckVisitor {
+        rename: move |suffix: &str, _: &syn::ItemFn| {
+            format_ident!("{}_{}{}", CHECK_FN, arm, suffix)
+        },
+    }
+}
+
+/// Give the checks that were inserted by [`wrap_impl_check`] names that include the
+/// numbers of their arms, which were added by [`number_arm`].
+pub fn number_impl_checks(expr: &mut syn::Expr) {
+    let mut visitor = CheckVisitor {
+        rename: |suffix: &str, item_fn: &syn::ItemFn| {
+            let traits = item_fn
+                .sig
+                .generics
+                .type_params()
+                .flat_map(|param| &param.bounds)
+                .filter_map(|bound| match bound {
+                    syn::TypeParamBound::Trait(trait_bound) => trait_bound
+                        .path
+                        .segments
+                        .last()
+                        .map(|segment| segment.ident.to_string()),
+                    syn::TypeParamBound::Lifetime(_) => None,
+                })
+                .collect::<Vec<_>>()
+                .join("_and_");
+
+            // a `let` statement that does not branch has a single arm
+            let suffix = if suffix.is_empty() { "_1" } else { suffix };
+            format_ident!("__cain_arm{}_must_implement_{}", suffix, traits)
+        },
+    };
+    visitor.visit_expr_mut(expr);
+}
+
+/// Renames the checks that were inserted by [`wrap_impl_check`] and have not been given
+/// their final names yet, where `rename` gets the part of the name after [`CHECK_FN`].
+struct CheckVisitor<F> {
+    rename: F,
+}
+
+impl<F: FnMut(&str, &syn::ItemFn) -> syn::Ident> VisitMut for CheckVisitor<F> {
+    fn visit_block_mut(&mut self, i: &mut syn::Block) {
+        if let [syn::Stmt::Item(syn::Item::Fn(item_fn)), syn::Stmt::Expr(syn::Expr::Call(call))] =
+            &mut i.stmts[..]
+        {
+            let name = item_fn.sig.ident.to_string();
+            if let Some(suffix) = name.strip_prefix(CHECK_FN) {
+                let ident = (self.rename)(suffix, item_fn);
+
+                // the final names include the names of the traits
+                if !ident.to_string().starts_with(CHECK_FN) {
+                    item_fn
+                        .attrs
+                        .push(syn::parse_quote! { #[allow(non_snake_case)] });
+                }
+                item_fn.sig.ident = ident.clone();
+                *call.func = syn::Expr::Path(syn::ExprPath {
+                    attrs: Vec::new(),
+                    qself: None,
+                    path: ident.into(),
+                });
+            }
+        }
+
+        syn::visit_mut::visit_block_mut(self, i)
+    }
+}
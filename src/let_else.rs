@@ -0,0 +1,224 @@
+//! Support for `let`-`else` statements, which `syn` 1 cannot parse.
+//!
+//! Before parsing, every `let PAT = EXPR else BLOCK;` statement is rewritten into a
+//! `let PAT = EXPR;` statement with a marker attribute that holds the `else` block. The
+//! marker is carried along through the transformation, and is turned back into a
+//! `let`-`else` statement after the transformation is done.
+
+use proc_macro2::{Delimiter, Group, TokenStream, TokenTree};
+use quote::{quote, ToTokens};
+use syn::parse::{ParseStream, Parser};
+use syn::visit_mut::VisitMut;
+
+const MARKER: &str = "__cain_let_else";
+
+/// Rewrite all `let`-`else` statements in `input` into marked `let` statements.
+pub fn desugar(input: TokenStream) -> TokenStream {
+    let tokens = input.into_iter().collect::<Vec<_>>();
+
+    let mut output = Vec::with_capacity(tokens.len());
+    let mut index = 0;
+    while index < tokens.len() {
+        if is_ident(&tokens[index], "let") && is_stmt_start(&tokens[..index]) {
+            let end = tokens[index..]
+                .iter()
+                .position(|token| is_punct(token, ';'))
+                .map(|end| index + end);
+
+            if let Some(end) = end {
+                if let Some(stmt) = desugar_stmt(&tokens[index..end]) {
+                    output.extend(stmt);
+                    output.push(tokens[end].clone());
+                    index = end + 1;
+                    continue;
+                }
+            }
+        }
+
+        output.push(desugar_token(&tokens[..index], &tokens[index]));
+        index += 1;
+    }
+
+    output.into_iter().collect()
+}
+
+fn desugar_stmt(stmt: &[TokenTree]) -> Option<TokenStream> {
+    let (else_block, init) = match stmt {
+        [init @ .., else_token, TokenTree::Group(block)]
+            if is_ident(else_token, "else") && block.delimiter() == Delimiter::Brace =>
+        {
+            (block, init)
+        }
+        _ => return None,
+    };
+
+    let stmt = stmt.iter().cloned().collect::<TokenStream>();
+    parse_let_else.parse2(stmt).ok()?;
+
+    let init = desugar(init.iter().cloned().collect());
+    let else_block = desugar_token(&[], &TokenTree::Group(else_block.clone()));
+    let marker = quote::format_ident!("{}", MARKER);
+
+    Some(quote! {
+        #[#marker #else_block] #init
+    })
+}
+
+/// Parse a `let`-`else` statement without the trailing semicolon.
+fn parse_let_else(input: ParseStream) -> syn::Result<()> {
+    input.parse::<syn::Token![let]>()?;
+    input.parse::<syn::Pat>()?;
+    if input.peek(syn::Token![:]) {
+        input.parse::<syn::Token![:]>()?;
+        input.parse::<syn::Type>()?;
+    }
+    input.parse::<syn::Token![=]>()?;
+    input.parse::<syn::Expr>()?;
+    input.parse::<syn::Token![else]>()?;
+    input.parse::<syn::Block>()?;
+    Ok(())
+}
+
+fn desugar_token(prefix: &[TokenTree], token: &TokenTree) -> TokenTree {
+    match token {
+        // the input of other macros is left alone
+        TokenTree::Group(_) if is_macro_call(prefix) => token.clone(),
+
+        TokenTree::Group(group) => {
+            let mut new_group = Group::new(group.delimiter(), desugar(group.stream()));
+            new_group.set_span(group.span());
+            TokenTree::Group(new_group)
+        }
+
+        _ => token.clone(),
+    }
+}
+
+fn is_stmt_start(prefix: &[TokenTree]) -> bool {
+    match prefix {
+        [] => true,
+        [.., token] if is_punct(token, ';') => true,
+        [.., TokenTree::Group(group)] if group.delimiter() == Delimiter::Brace => true,
+        [.., pound, TokenTree::Group(group)] if is_punct(pound, '#') => {
+            group.delimiter() == Delimiter::Bracket
+        }
+        // an inner attribute, as in `#![cain(...)]`
+        [.., pound, bang, TokenTree::Group(group)] if is_punct(bang, '!') => {
+            is_punct(pound, '#') && group.delimiter() == Delimiter::Bracket
+        }
+        _ => false,
+    }
+}
+
+fn is_macro_call(prefix: &[TokenTree]) -> bool {
+    match prefix {
+        [.., TokenTree::Ident(_), bang] => is_punct(bang, '!'),
+        [.., bang, TokenTree::Ident(_)] => is_punct(bang, '!'),
+        _ => false,
+    }
+}
+
+fn is_ident(token: &TokenTree, name: &str) -> bool {
+    matches!(token, TokenTree::Ident(ident) if ident == name)
+}
+
+fn is_punct(token: &TokenTree, ch: char) -> bool {
+    matches!(token, TokenTree::Punct(punct) if punct.as_char() == ch)
+}
+
+fn is_marker(attr: &syn::Attribute) -> bool {
+    attr.path.is_ident(MARKER)
+}
+
+/// Returns whether a `let` statement is a marked `let`-`else` statement.
+pub fn has_else_block(local: &syn::Local) -> bool {
+    local.attrs.iter().any(is_marker)
+}
+
+/// Remove the `else` block from a marked `let` statement.
+fn take_else_block(local: &mut syn::Local) -> syn::Result<Option<syn::Block>> {
+    match local.attrs.iter().position(is_marker) {
+        Some(index) => {
+            let attr = local.attrs.remove(index);
+            syn::parse2(attr.tokens).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Turn all marked `let` statements back into `let`-`else` statements.
+pub fn resugar(stmts: &mut [syn::Stmt]) -> syn::Result<()> {
+    let mut visitor = ResugarVisitor { error: None };
+    visitor.visit_stmts_mut(stmts);
+
+    match visitor.error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+struct ResugarVisitor {
+    error: Option<syn::Error>,
+}
+
+impl ResugarVisitor {
+    fn visit_stmts_mut(&mut self, stmts: &mut [syn::Stmt]) {
+        for stmt in stmts {
+            self.visit_stmt_mut(stmt);
+
+            if let syn::Stmt::Local(local) = stmt {
+                match self.resugar_local(local) {
+                    Ok(Some(tokens)) => *stmt = syn::Stmt::Item(syn::Item::Verbatim(tokens)),
+                    Ok(None) => (),
+                    Err(err) => {
+                        self.error.get_or_insert(err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn resugar_local(&mut self, local: &mut syn::Local) -> syn::Result<Option<TokenStream>> {
+        let mut else_block = match take_else_block(local)? {
+            Some(else_block) => else_block,
+            None => return Ok(None),
+        };
+
+        self.visit_stmts_mut(&mut else_block.stmts);
+
+        let attrs = &local.attrs;
+        let pat = &local.pat;
+        let init = match &local.init {
+            Some((_, init)) => init,
+            None => {
+                return Err(syn::Error::new_spanned(
+                    local,
+                    "internal macro error: `let`-`else` statement without initializer",
+                ))
+            }
+        };
+
+        // the parentheses that are needed around an initializer that ends with a `}` are
+        // often no longer needed after the transformation, and vice versa
+        let mut init = &**init;
+        while let syn::Expr::Paren(expr_paren) = init {
+            init = &expr_paren.expr;
+        }
+        let init = match init.to_token_stream().into_iter().last() {
+            Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => {
+                quote! { (#init) }
+            }
+            _ => init.to_token_stream(),
+        };
+
+        Ok(Some(quote! {
+            #(#attrs)* let #pat = #init else #else_block;
+        }))
+    }
+}
+
+impl VisitMut for ResugarVisitor {
+    fn visit_block_mut(&mut self, i: &mut syn::Block) {
+        self.visit_stmts_mut(&mut i.stmts);
+    }
+}
@@ -1,9 +1,32 @@
 #![doc = include_str!("../README.md")]
+#![cfg_attr(feature = "nightly", feature(proc_macro_diagnostic))]
 
+mod auto_enum;
+mod breaks;
+mod condition;
+mod const_dispatch;
+mod deferred;
+mod derive_dispatch;
+mod dispatch;
+mod dispatch_arms;
+mod each;
+mod impl_check;
+mod let_else;
+mod local_macro;
 mod macros;
+mod marker;
+mod narrow;
+mod nested;
+mod options;
+mod paths;
 mod placeholder;
+mod shared;
+mod type_dispatch;
 mod util;
+mod warnings;
 
+#[cfg(test)]
+mod bench;
 #[cfg(test)]
 mod codegen_tests;
 
@@ -18,3 +41,146 @@ pub fn cain(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
         .unwrap_or_else(|err| err.to_compile_error())
         .into()
 }
+
+/// Turn runtime values into constants, by duplicating an expression for each value.
+///
+/// `const_dispatch!(n in 1..=16, |N| kernel::<N>(&data))` matches on `n`, and evaluates
+/// the expression with every `N` replaced by the value of `n`, so that it can be used as
+/// a const generic argument. Several values can be dispatched on at once, and each value
+/// can be a range of integers, a list of literals, or `bool`:
+///
+/// ```nocompile
+/// const_dispatch!(n in [1, 2, 4, 8], simd in bool, |N, SIMD| kernel::<N, SIMD>(&data))
+/// ```
+///
+/// Values that are not in a range or list panic. The number of arms counts against the
+/// `max_paths` option, which can be set like for `cain!`.
+#[proc_macro]
+pub fn const_dispatch(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input);
+
+    const_dispatch::const_dispatch(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// Run generic code with a type that is chosen by runtime tags.
+///
+/// `type_dispatch!(format, { "json" => JsonCodec, "cbor" => CborCodec }, |T| run::<T>())`
+/// matches on `format`, and evaluates the expression with every `T` replaced by the type
+/// of the arm. Several tags can be dispatched on at once, and a `_` arm can be used as a
+/// fallback:
+///
+/// ```nocompile
+/// type_dispatch!(
+///     format, { "json" => JsonCodec, "cbor" => CborCodec },
+///     width, { 4 => u32, 8 => u64, _ => usize },
+///     |C, W| run::<C, W>(&input)
+/// )
+/// ```
+///
+/// Without a fallback arm, tags that do not match any arm panic.
+#[proc_macro]
+pub fn type_dispatch(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input);
+
+    type_dispatch::type_dispatch(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// Generate a macro that runs the same code for the value in every variant of an enum.
+///
+/// For an enum `Shape` whose variants each have a single unnamed field, this generates
+/// `shape_dispatch!(shape, |inner| body)`, which matches on `shape` and duplicates `body`
+/// into every arm, with `inner` bound to the value in the variant. The macro can be used
+/// after the enum in the same module, or imported from it.
+///
+/// Inside a `cain!` block, `let inner = shape_dispatch!(shape);` forks the rest of the
+/// block instead, if the enum is declared in the block or the macro is listed in the
+/// `dispatch_macros` option:
+///
+/// ```nocompile
+/// cain! {
+///     #![cain(dispatch_macros(shape_dispatch))]
+///     let inner = shape_dispatch!(shape);
+///     println!("{}", inner.area());
+/// }
+/// ```
+#[proc_macro_derive(Dispatch)]
+pub fn derive_dispatch(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input);
+
+    derive_dispatch::derive_dispatch(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// Name the arms that were taken to reach this point of a `cain!` block.
+///
+/// Expands to a `&'static str` such as `"x => Some(n), y > 0 => true"`. Outside of a
+/// `cain!` block, this macro is an error.
+#[proc_macro]
+pub fn path(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = proc_macro2::TokenStream::from(input);
+
+    syn::Error::new(
+        proc_macro2::Span::call_site(),
+        if input.is_empty() {
+            "`cain::path!()` can only be used inside a `cain!` block"
+        } else {
+            "`cain::path!()` does not take any arguments"
+        },
+    )
+    .to_compile_error()
+    .into()
+}
+
+/// Unroll a `for` loop inside a `cain!` block over values of different types.
+///
+/// `for x in cain::each!(a, b, c) { ... }` runs the body once for each value, with a copy
+/// of the body for each type. Outside of a `cain!` block, this macro is an error.
+#[proc_macro]
+pub fn each(_input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    syn::Error::new(
+        proc_macro2::Span::call_site(),
+        "`cain::each!(...)` can only be used as the iterator of a `for` loop inside a `cain!` block",
+    )
+    .to_compile_error()
+    .into()
+}
+
+/// Let a function that returns `impl Trait` return values of different types.
+///
+/// The body of the function is transformed like a `cain!` block, and every value that it
+/// returns is wrapped in its own variant of a local enum, which implements the traits of the
+/// return type. Only `Iterator`, `DoubleEndedIterator`, `ExactSizeIterator`,
+/// `FusedIterator`, `Future`, `Debug`, `Display` and `Error` can be implemented, along with
+/// auto traits such as `Send`. For `Result<impl Trait, E>`, only the `Ok` values are
+/// wrapped:
+///
+/// ```nocompile
+/// #[cain::auto_enum]
+/// fn numbers(config: &Config) -> Result<impl Iterator<Item = u32>, Error> {
+///     let step = config.step()?;
+///     if config.reverse {
+///         Ok((0..10).rev().step_by(step))
+///     } else {
+///         Ok((0..10).step_by(step))
+///     }
+/// }
+/// ```
+///
+/// Since `cain` is already the name of the function-like macro, this attribute has a name
+/// of its own.
+#[proc_macro_attribute]
+pub fn auto_enum(
+    args: proc_macro::TokenStream,
+    input: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(input);
+
+    auto_enum::auto_enum(args.into(), input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
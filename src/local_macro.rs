@@ -0,0 +1,66 @@
+//! Support for the "macro" strategy, which emits the continuation of a branching `let`
+//! statement once, as a local `macro_rules!` macro that each arm invokes with its own value.
+//!
+//! The compiler still expands and type checks the continuation once for each arm, but the
+//! output of `cain!` only grows linearly with the number of branching `let` statements,
+//! rather than with the number of paths. Local variables in the body of a `macro_rules!`
+//! macro are resolved where the macro is defined, so the continuation can still use the
+//! variables that are bound before the `let` statement.
+
+use quote::{format_ident, ToTokens};
+use syn::visit_mut::VisitMut;
+
+use crate::dispatch;
+
+/// Returns the definition of a macro `name` that evaluates `local`, with the value that the
+/// macro is invoked with as its initializer, followed by `rest`, and an invocation of that
+/// macro with `value`.
+pub fn continuation(
+    name: &syn::Ident,
+    local: &syn::Local,
+    rest: &[syn::Stmt],
+    value: &syn::Expr,
+) -> (syn::Stmt, syn::Expr) {
+    // the name of the metavariable must not clash with those of the macros for the
+    // `let` statements in `rest`, which are defined inside the body of this one
+    let metavar = format_ident!("{}_value", name);
+    let attrs = &local.attrs;
+    let pat = &local.pat;
+
+    let definition = syn::Stmt::Item(syn::parse_quote! {
+        macro_rules! #name {
+            ($#metavar:expr) => {
+                {
+                    #(#attrs)*
+                    let #pat = $#metavar;
+                    #(#rest)*
+                }
+            };
+        }
+    });
+    let invocation = syn::parse_quote! { #name!(#value) };
+
+    (definition, invocation)
+}
+
+/// Remove the braces around the values that the macro `name` is invoked with.
+pub fn unwrap_values(expr: &mut syn::Expr, name: &syn::Ident) {
+    UnwrapVisitor { name }.visit_expr_mut(expr);
+}
+
+struct UnwrapVisitor<'a> {
+    name: &'a syn::Ident,
+}
+
+impl<'a> VisitMut for UnwrapVisitor<'a> {
+    fn visit_macro_mut(&mut self, i: &mut syn::Macro) {
+        if !i.path.is_ident(self.name) {
+            return;
+        }
+
+        if let Ok(mut value) = i.parse_body::<syn::Expr>() {
+            dispatch::unwrap_block(&mut value);
+            i.tokens = value.into_token_stream();
+        }
+    }
+}
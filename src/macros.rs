@@ -1,64 +1,369 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::iter::once;
 
 use proc_macro2::TokenStream;
-use quote::quote;
-use syn::parse::Parser;
+use quote::{quote, ToTokens};
+use syn::parse::{ParseStream, Parser};
+use syn::visit::Visit;
 use syn::visit_mut::VisitMut;
 use syn::Block;
 
+use crate::auto_enum;
+use crate::breaks;
+use crate::condition::{self, Condition};
+use crate::deferred;
+use crate::derive_dispatch;
+use crate::dispatch;
+use crate::dispatch_arms;
+use crate::each;
+use crate::impl_check;
+use crate::let_else;
+use crate::local_macro;
+use crate::marker;
+use crate::narrow;
+use crate::nested;
+use crate::options::{Options, Shared, Strategy};
+use crate::paths;
 use crate::placeholder::{
-    replace_with_placeholder, wrap_placeholder_block_mut, wrap_placeholder_expr_mut, PlaceholderId,
+    contains_placeholder_id, get_placeholder_id, replace_expr, replace_with_placeholder,
+    wrap_placeholder_block_mut, wrap_placeholder_expr_mut, PlaceholderId,
 };
-use crate::util::{drain_filter, unique_ident};
+use crate::shared::{self, Binding};
+use crate::util::{
+    arm_diverges, block_diverges, block_expr, drain_filter, parse_macro_args, shadowing_bindings,
+    unique_ident, UsedIdents,
+};
+use crate::warnings::{self, Warning, Warnings};
 
 pub fn cain(input: TokenStream) -> syn::Result<TokenStream> {
-    let stmts = Block::parse_within.parse2(input)?;
-    let stmts = chain_stmts(stmts)?;
+    let (output, warnings) = expand(input, &Options::load_defaults()?)?;
+    Ok(warnings::emit(warnings, output))
+}
+
+/// Transform the input of the macro, and return the output together with the warnings.
+pub fn expand(input: TokenStream, defaults: &Options) -> syn::Result<(TokenStream, Vec<Warning>)> {
+    let parser = |input: ParseStream| {
+        let options = Options::parse(input, defaults)?;
+        let stmts = Block::parse_within(input)?;
+        Ok((options, stmts))
+    };
+
+    let (input, has_paths) = paths::desugar(input);
+    let (options, stmts) = parser.parse2(let_else::desugar(input))?;
+    let track_paths = has_paths || options.path_hook.is_some();
+
+    let warnings = Warnings::default();
+    let mut stmts = chain_stmts(Context::new(&options, &warnings, track_paths), stmts)?;
+    let_else::resugar(&mut stmts)?;
 
     // wrap the result in a block expression
-    Ok(quote! {
+    let output = quote! {
         { #(#stmts)* }
-    })
+    };
+    let output = if track_paths {
+        paths::resolve(output)
+    } else {
+        output
+    };
+
+    let warnings = warnings.finish(&output);
+    Ok((output, warnings))
+}
+
+/// The state of the transformation at a given point in the input.
+#[derive(Clone, Copy)]
+pub struct Context<'a> {
+    options: &'a Options,
+    warnings: &'a Warnings,
+
+    /// Whether the labels of the arms need to be tracked for `cain::path!()`.
+    track_paths: bool,
+
+    /// The number of paths through the enclosing blocks that lead to this point.
+    paths: usize,
+
+    /// The `bool` constants with known values that are declared in the enclosing blocks.
+    consts: &'a [(syn::Ident, bool)],
+
+    /// The dispatch macros for the enums that derive `Dispatch` in the enclosing blocks.
+    dispatch_macros: &'a [syn::Ident],
+
+    /// The variables that are bound before this point, which are only tracked for the
+    /// "shared" strategy.
+    locals: &'a [Binding],
+}
+
+impl<'a> Context<'a> {
+    pub fn new(options: &'a Options, warnings: &'a Warnings, track_paths: bool) -> Context<'a> {
+        Context {
+            options,
+            warnings,
+            track_paths,
+            paths: 1,
+            consts: &[],
+            dispatch_macros: &[],
+            locals: &[],
+        }
+    }
+
+    fn with_paths(self, paths: usize) -> Context<'a> {
+        Context { paths, ..self }
+    }
+
+    /// Returns whether a macro is a dispatch macro without a closure, which can be given the
+    /// rest of the block as the body of its closure.
+    fn is_open_dispatch_macro(self, mac: &syn::Macro) -> bool {
+        let is_derived = match mac.path.segments.last() {
+            Some(segment) => self
+                .options
+                .dispatch_macros
+                .iter()
+                .chain(self.dispatch_macros)
+                .any(|name| segment.ident == *name),
+            None => false,
+        };
+
+        (is_derived || dispatch_arms::is_builtin_dispatch_macro(mac))
+            && !dispatch_arms::has_closure(mac)
+    }
+
+    /// Returns the copy of a continuation for one of the arms of a branch point.
+    fn continuation(self, expr: &syn::Expr) -> syn::Expr {
+        // a branch point without anything after it has no continuation to enter
+        let is_empty = matches!(expr, syn::Expr::Path(expr_path) if get_placeholder_id(&expr_path.path).is_some());
+
+        match &self.options.path_hook {
+            Some(hook) if !is_empty => paths::call_hook(hook, expr.clone()),
+            _ => expr.clone(),
+        }
+    }
+
+    /// Returns the dispatch type if a branching `let` statement at this point should use
+    /// dynamic dispatch instead of duplicating the rest of the block.
+    fn dispatch(self, arms: usize) -> Option<&'a syn::TypeReference> {
+        match (self.options.strategy, self.options.max_paths) {
+            (Strategy::Duplicate | Strategy::Shared | Strategy::Macro, _) => None,
+            (Strategy::Hybrid, Some(max_paths)) if self.paths.saturating_mul(arms) <= max_paths => {
+                None
+            }
+            (Strategy::Dyn | Strategy::Hybrid | Strategy::Enum, _) => {
+                self.options.dispatch.as_ref()
+            }
+        }
+    }
+
+    /// Returns whether branching `let` statements that use dispatch wrap their values in
+    /// an enum instead of coercing references to them to the dispatch type.
+    fn uses_enum(self) -> bool {
+        match self.options.strategy {
+            Strategy::Enum => true,
+            Strategy::Hybrid => self.options.fallback == Some(Strategy::Enum),
+            _ => false,
+        }
+    }
+
+    /// Returns the signature of the shared continuation if branching `let` statements
+    /// should share the rest of the block instead of duplicating it.
+    fn shared(self) -> Option<&'a Shared> {
+        match self.options.strategy {
+            Strategy::Shared => self.options.shared.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Returns whether branching `let` statements should move the rest of the block into a
+    /// local macro instead of copying it into each arm.
+    fn uses_macro(self) -> bool {
+        // paths are resolved in each copy of the rest of the block
+        self.options.strategy == Strategy::Macro && !self.track_paths
+    }
+
+    /// Returns whether a branching `let` statement at this point duplicates the rest of
+    /// the block.
+    fn duplicates(self, arms: usize) -> bool {
+        self.dispatch(arms).is_none() && self.shared().is_none()
+    }
+}
+
+fn chain_stmts(cx: Context, stmts: Vec<syn::Stmt>) -> syn::Result<Vec<syn::Stmt>> {
+    chain_stmts_before(cx, stmts, Vec::new())
 }
 
-fn chain_stmts(mut stmts: Vec<syn::Stmt>) -> syn::Result<Vec<syn::Stmt>> {
+/// Transform `stmts`, which are followed by `rest`, the already transformed rest of the
+/// block.
+fn chain_stmts_before(
+    cx: Context,
+    mut stmts: Vec<syn::Stmt>,
+    rest: Vec<syn::Stmt>,
+) -> syn::Result<Vec<syn::Stmt>> {
+    nested::unwrap_stmt_macros(&mut stmts);
     let mut items = drain_filter(&mut stmts, |stmt| matches!(stmt, syn::Stmt::Item(_)));
 
-    let stmts = stmts.into_iter().rev().try_fold(Vec::new(), chain_stmt)?;
+    let consts = condition::bool_consts(&items, cx.consts);
+    let dispatch_macros = derive_dispatch::dispatch_macros(&items, cx.dispatch_macros);
+    let cx = Context {
+        consts: &consts,
+        dispatch_macros: &dispatch_macros,
+        ..cx
+    };
+
+    for stmt in &mut stmts {
+        if let syn::Stmt::Local(local) = stmt {
+            marker::move_local_markers(local)?;
+        }
+    }
+
+    deferred::move_declarations(&mut stmts, |local| count_local_paths(cx, local) > 1);
+
+    // the number of paths and the variables that lead to each statement
+    let mut paths = Vec::with_capacity(stmts.len());
+    let mut current = cx.paths;
+    let mut locals = cx.locals.to_vec();
+    let mut local_counts = Vec::with_capacity(stmts.len());
+    for stmt in &mut stmts {
+        paths.push(current);
+        local_counts.push(locals.len());
+
+        if let syn::Stmt::Local(local) = stmt {
+            if cx.shared().is_some() {
+                shared::allow_unused_mut(local);
+                locals.extend(shared::bindings(local));
+            }
+
+            let arms = count_local_paths(cx, local);
+            if cx.with_paths(current).duplicates(arms) {
+                current = current.saturating_mul(arms);
+
+                match cx.options.max_paths {
+                    Some(max_paths) if current > max_paths => {
+                        return Err(syn::Error::new_spanned(
+                            local,
+                            format!(
+                                "cain! block forks into {} paths here, which exceeds `max_paths = {}`",
+                                current, max_paths,
+                            ),
+                        ));
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    let stmts = stmts
+        .into_iter()
+        .zip(paths.into_iter().zip(local_counts))
+        .rev()
+        .try_fold(rest, |rest, (stmt, (paths, local_count))| {
+            let cx = Context {
+                paths,
+                locals: &locals[..local_count],
+                ..cx
+            };
+            chain_stmt(cx, rest, stmt)
+        })?;
 
     items.extend(stmts);
 
     Ok(items)
 }
 
-fn chain_stmt(rest: Vec<syn::Stmt>, stmt: syn::Stmt) -> syn::Result<Vec<syn::Stmt>> {
+fn chain_stmt(cx: Context, rest: Vec<syn::Stmt>, stmt: syn::Stmt) -> syn::Result<Vec<syn::Stmt>> {
     match stmt {
+        syn::Stmt::Expr(expr) if rest.is_empty() => {
+            let expr = chain_expr(cx, expr, None)?;
+            Ok(vec![syn::Stmt::Expr(expr)])
+        }
+
         syn::Stmt::Expr(expr) => {
-            let expr = chain_expr(expr, None)?;
+            let expr = chain_inner_expr(cx, expr)?;
             Ok(once(syn::Stmt::Expr(expr)).chain(rest).collect())
         }
 
         syn::Stmt::Semi(expr, semi) => {
-            let expr = chain_expr(expr, None)?;
+            let expr = chain_inner_expr(cx, expr)?;
             Ok(once(syn::Stmt::Semi(expr, semi)).chain(rest).collect())
         }
 
         syn::Stmt::Local(mut local) => {
+            let mut rest = rest;
+            if let Some(stmts) = chain_nested_local(cx, &mut rest, &local)? {
+                return Ok(stmts);
+            }
+
+            // the `else` block of a `let`-`else` statement diverges, and stays as it is
+            let has_else = let_else::has_else_block(&local);
+            if has_else {
+                cx.warnings.add_branch_points(1);
+            }
+
+            let impl_bounds = impl_check::take_impl_bounds(&mut local)?;
+
+            if let Some(dispatch) = cx.dispatch(count_local_paths(cx, &local)) {
+                if let Some(stmts) =
+                    dispatch_local(cx, dispatch, &mut rest, &local, impl_bounds.as_deref())?
+                {
+                    return Ok(stmts);
+                }
+            }
+
+            if let Some(shared) = cx.shared() {
+                if let Some(stmts) = shared_local(cx, shared, &mut rest, &local)? {
+                    return Ok(stmts);
+                }
+            }
+
+            if let Some(stmts) = chain_break_local(cx, &mut rest, &local, impl_bounds.as_deref())? {
+                return Ok(stmts);
+            }
+
+            if let Some(stmts) = chain_dispatch_macro_local(cx, &rest, &local)? {
+                return Ok(stmts);
+            }
+
             if let Some((_, init)) = &mut local.init {
                 let (placeholder_id, init_expr) = replace_with_placeholder(init);
 
-                let inner_expr: syn::Expr = syn::parse_quote! {
-                    { #local #(#rest)* }
-                };
+                if let Some(bounds) = &impl_bounds {
+                    let placeholder =
+                        std::mem::replace(&mut **init, syn::Expr::Verbatim(TokenStream::new()));
+                    **init = impl_check::wrap_impl_check(bounds, placeholder);
+                }
 
-                let expr = chain_expr(init_expr, Some((placeholder_id, inner_expr)))?;
+                let (init_expr, branches) = find_branches(cx, init_expr)?;
+                let value = (**init).clone();
 
-                if let syn::Expr::Block(expr_block) = expr {
-                    Ok(expr_block.block.stmts)
-                } else {
-                    Ok(vec![syn::Stmt::Expr(expr)])
+                // the `else` block of a `let`-`else` statement stays with its initializer
+                let mut definition = None;
+                let inner_expr: syn::Expr =
+                    if cx.uses_macro() && !branches.is_empty() && !rest.is_empty() && !has_else {
+                        let name = unique_ident();
+                        let (stmt, invocation) =
+                            local_macro::continuation(&name, &local, &rest, &value);
+                        definition = Some((name, stmt));
+                        invocation
+                    } else {
+                        block_expr(once(syn::Stmt::Local(local)).chain(rest).collect())
+                    };
+
+                let mut expr =
+                    fork_expr(cx, init_expr, branches, Some((placeholder_id, inner_expr)))?;
+
+                if impl_bounds.is_some() {
+                    impl_check::number_impl_checks(&mut expr);
                 }
+
+                let definition = definition.map(|(name, stmt)| {
+                    local_macro::unwrap_values(&mut expr, &name);
+                    stmt
+                });
+
+                let stmts = if let syn::Expr::Block(expr_block) = expr {
+                    expr_block.block.stmts
+                } else {
+                    vec![syn::Stmt::Expr(expr)]
+                };
+                Ok(definition.into_iter().chain(stmts).collect())
             } else {
                 Ok(once(syn::Stmt::Local(local)).chain(rest).collect())
             }
@@ -68,35 +373,613 @@ fn chain_stmt(rest: Vec<syn::Stmt>, stmt: syn::Stmt) -> syn::Result<Vec<syn::Stm
     }
 }
 
+/// Fork the continuation of a `let` statement whose initializer is a nested `cain!` block,
+/// by moving the continuation into the nested block, after its statements and with its tail
+/// expression as the initializer.
+///
+/// If the nested block binds a variable that would shadow one that the continuation uses,
+/// the nested block keeps its own scope instead, and only forks within itself.
+///
+/// Returns `None` if the initializer is not a `cain!` block.
+fn chain_nested_local(
+    cx: Context,
+    rest: &mut Vec<syn::Stmt>,
+    local: &syn::Local,
+) -> syn::Result<Option<Vec<syn::Stmt>>> {
+    let mac = match nested::local_macro(local) {
+        Some(mac) => mac,
+        None => return Ok(None),
+    };
+    let (options, mut stmts) = nested::parse(mac, cx.options, cx.track_paths)?;
+
+    // the pattern of the `let` statement is bound again before the rest of the block
+    let block = block_expr(stmts.clone());
+    let pat = &local.pat;
+    rest.insert(0, syn::parse_quote! { let #pat = (); });
+    let shadowing = warnings::check_shadowing(cx.warnings, &block, rest);
+    rest.remove(0);
+
+    // variables of the nested block that the rest of the block would see instead of its
+    // own keep their scope, and the nested block does not fork the rest of the block
+    if shadowing {
+        let expr_macro = syn::ExprMacro {
+            attrs: Vec::new(),
+            mac: mac.clone(),
+        };
+        let mut local = local.clone();
+        local.init = Some((
+            Default::default(),
+            Box::new(chain_nested_block(cx, &expr_macro)?),
+        ));
+        return chain_stmts_before(cx, vec![syn::Stmt::Local(local)], std::mem::take(rest))
+            .map(Some);
+    }
+
+    let tail = match stmts.pop() {
+        Some(syn::Stmt::Expr(expr)) => expr,
+        Some(stmt) => {
+            stmts.push(stmt);
+            syn::parse_quote! { () }
+        }
+        None => syn::parse_quote! { () },
+    };
+
+    // `let x = cain! { ...; x };` needs no `let x = x;`
+    let is_rebinding = match (&local.pat, &tail) {
+        (syn::Pat::Ident(pat_ident), syn::Expr::Path(expr_path)) => {
+            local.attrs.is_empty()
+                && pat_ident.by_ref.is_none()
+                && pat_ident.mutability.is_none()
+                && pat_ident.subpat.is_none()
+                && expr_path.qself.is_none()
+                && expr_path.path.is_ident(&pat_ident.ident)
+        }
+        _ => false,
+    };
+    if !is_rebinding {
+        let mut local = local.clone();
+        local.init = Some((Default::default(), Box::new(tail)));
+        stmts.push(syn::Stmt::Local(local));
+    }
+
+    let cx = Context {
+        options: &options,
+        ..cx
+    };
+    chain_stmts_before(cx, stmts, std::mem::take(rest)).map(Some)
+}
+
+/// Transform a nested `cain!` block that is not the initializer of a `let` statement into a
+/// block expression, whose branch points do not fork anything outside of it.
+fn chain_nested_block(cx: Context, expr_macro: &syn::ExprMacro) -> syn::Result<syn::Expr> {
+    let (options, stmts) = nested::parse(&expr_macro.mac, cx.options, cx.track_paths)?;
+
+    let cx = Context {
+        options: &options,
+        ..cx
+    };
+    let mut expr = block_expr(chain_stmts(cx, stmts)?);
+    if let syn::Expr::Block(expr_block) = &mut expr {
+        expr_block.attrs = expr_macro.attrs.clone();
+    }
+    Ok(expr)
+}
+
+/// Returns the value of the `exit`-th exit point of a `loop` or labeled block, checked
+/// against the `impl Trait` bounds of the `let` statement that it initializes. Every exit
+/// point is an arm of its own.
+fn exit_value(
+    value: Option<syn::Expr>,
+    impl_bounds: Option<&[syn::TraitBound]>,
+    exit: usize,
+) -> syn::Expr {
+    let value = value.unwrap_or_else(|| syn::parse_quote! { () });
+    match impl_bounds {
+        Some(bounds) => {
+            let mut value = impl_check::wrap_impl_check(bounds, value);
+            impl_check::number_arm(&mut value, exit);
+            value
+        }
+        None => value,
+    }
+}
+
+/// Fork the continuation of a `let` statement whose initializer is a `loop` or a
+/// labeled block at each of its exit points.
+///
+/// Returns `None` if the initializer is not a `loop` or labeled block, or if it has no
+/// exit points.
+fn chain_break_local(
+    cx: Context,
+    rest: &mut Vec<syn::Stmt>,
+    local: &syn::Local,
+    impl_bounds: Option<&[syn::TraitBound]>,
+) -> syn::Result<Option<Vec<syn::Stmt>>> {
+    let target = match &local.init {
+        Some((_, init)) if breaks::is_break_target(init) => &**init,
+        _ => return Ok(None),
+    };
+    let mut init = target.clone();
+
+    // every exit point gets its own placeholder, so that it can get its own label
+    let mut placeholder_ids = Vec::new();
+    let count = breaks::replace_break_values(&mut init, |value| {
+        let placeholder_id = PlaceholderId::new();
+        placeholder_ids.push(placeholder_id);
+
+        let mut local = local.clone();
+        let value = exit_value(value, impl_bounds, placeholder_ids.len());
+        local.init = Some((Default::default(), Box::new(value)));
+
+        syn::parse_quote! {
+            { #local #placeholder_id }
+        }
+    });
+
+    if count == 0 {
+        return Ok(None);
+    }
+    cx.warnings.add_branch_points(1);
+
+    // the rest of the block is moved into the scope of the variables of the loop
+    if let Some(ident) = shadowing_bindings(target, rest).first() {
+        return Err(syn::Error::new_spanned(
+            ident,
+            format!(
+                "cain! cannot fork at the exit points of this loop, since `{}` would shadow a variable that the rest of the block uses, rename it",
+                ident
+            ),
+        ));
+    }
+
+    if let syn::Expr::Loop(_) = init {
+        if let Some(expr) = breaks::find_unlabeled_control_flow(rest) {
+            return Err(syn::Error::new_spanned(
+                expr,
+                "cain! cannot move unlabeled `break` or `continue` into a `loop`, add a label to the target loop",
+            ));
+        }
+    }
+
+    let mut init = chain_expr(cx, init, None)?;
+    if impl_bounds.is_some() {
+        impl_check::number_impl_checks(&mut init);
+    }
+
+    let rest_expr = block_expr(std::mem::take(rest));
+
+    let expr = placeholder_ids.into_iter().enumerate().try_fold(
+        init,
+        |init, (index, placeholder_id)| {
+            let mut rest_expr = cx.continuation(&rest_expr);
+            if cx.track_paths {
+                paths::label_expr(&mut rest_expr, &paths::break_label(target, index + 1));
+            }
+            replace_expr(init, placeholder_id, rest_expr)
+        },
+    )?;
+
+    Ok(Some(vec![syn::Stmt::Expr(expr)]))
+}
+
+/// Fork the continuation of a `let` statement whose initializer is a dispatch macro such
+/// as `shape_dispatch!(shape)`, by giving the rest of the block to the macro as the body of
+/// its closure.
+///
+/// Returns `None` if the initializer is not a dispatch macro without a closure.
+fn chain_dispatch_macro_local(
+    cx: Context,
+    rest: &[syn::Stmt],
+    local: &syn::Local,
+) -> syn::Result<Option<Vec<syn::Stmt>>> {
+    let mut mac = match &local.init {
+        Some((_, init)) => match &**init {
+            syn::Expr::Macro(expr_macro) if cx.is_open_dispatch_macro(&expr_macro.mac) => {
+                expr_macro.mac.clone()
+            }
+            _ => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+
+    // a tuple pattern has one name for each value that is dispatched on
+    let names = match &local.pat {
+        syn::Pat::Tuple(pat_tuple) => pat_tuple.elems.iter().cloned().collect::<Vec<_>>(),
+        syn::Pat::Type(pat_type) => {
+            return Err(syn::Error::new_spanned(
+                &pat_type.ty,
+                "cain! does not support type annotations on the result of a dispatch macro",
+            ))
+        }
+        pat => vec![pat.clone()],
+    };
+
+    cx.warnings.add_branch_points(1);
+
+    // attributes and an `else` block stay with a `let` statement at the start of the body,
+    // which binds the value that the closure gets
+    let mut body = rest.to_vec();
+    let names = if local.attrs.is_empty() {
+        names
+    } else if let syn::Pat::Tuple(_) = &local.pat {
+        return Err(syn::Error::new_spanned(
+            &local.attrs[0],
+            "cain! does not support attributes or an `else` block on a `let` statement that names several values of a dispatch macro",
+        ));
+    } else {
+        let name = unique_ident();
+        let mut local = local.clone();
+        local.init = Some((Default::default(), syn::parse_quote! { #name }));
+        body.insert(0, syn::Stmt::Local(local));
+        vec![syn::parse_quote! { #name }]
+    };
+
+    // the body of the closure is not visited again, so marked `let`-`else` statements in it
+    // have to be turned back already
+    let_else::resugar(&mut body)?;
+
+    let args = &mac.tokens;
+    let separator = match args.clone().into_iter().last() {
+        Some(proc_macro2::TokenTree::Punct(punct)) if punct.as_char() == ',' => None,
+        _ => Some(quote! { , }),
+    };
+    mac.tokens = quote! {
+        #args #separator |#(#names),*| { #(#body)* }
+    };
+
+    Ok(Some(vec![syn::Stmt::Expr(syn::Expr::Macro(
+        syn::ExprMacro {
+            attrs: Vec::new(),
+            mac,
+        },
+    ))]))
+}
+
+/// Fork only the initializer of a `let` statement whose value either has a declared type
+/// or is discarded, since the rest of the block does not depend on the type of the value.
+///
+/// Returns `None` if the statement has no initializer, or binds a value without a declared
+/// type.
+fn chain_init_only(
+    cx: Context,
+    rest: &mut Vec<syn::Stmt>,
+    local: &syn::Local,
+) -> syn::Result<Option<Vec<syn::Stmt>>> {
+    if local.init.is_none() || !matches!(local.pat, syn::Pat::Type(_) | syn::Pat::Wild(_)) {
+        return Ok(None);
+    }
+
+    let mut local = local.clone();
+    if let Some((_, init)) = &mut local.init {
+        let init_expr = std::mem::replace(&mut **init, syn::Expr::Verbatim(TokenStream::new()));
+        **init = chain_expr(cx, init_expr, None)?;
+    }
+
+    Ok(Some(
+        once(syn::Stmt::Local(local))
+            .chain(std::mem::take(rest))
+            .collect(),
+    ))
+}
+
+/// Returns the identifier that the pattern of a `let` statement binds, or an error that
+/// `cain!` can only `action` statements that bind a single identifier.
+fn single_ident<'a>(pat: &'a syn::Pat, action: &str) -> syn::Result<&'a syn::PatIdent> {
+    match pat {
+        syn::Pat::Ident(pat_ident) if pat_ident.by_ref.is_none() && pat_ident.subpat.is_none() => {
+            Ok(pat_ident)
+        }
+        _ => Err(syn::Error::new_spanned(
+            pat,
+            format!(
+                "cain! can only {} `let` statements that bind a single identifier",
+                action
+            ),
+        )),
+    }
+}
+
+/// Fork branches in a `let` statement with dynamic dispatch enabled.
+///
+/// If the initializer branches, every arm stores its value in a separate slot, and the
+/// statement binds a reference to the slot, coerced to the dispatch type. With the "enum"
+/// strategy, every arm wraps its value in its own variant of a local enum instead. The
+/// rest of the block is emitted only once.
+///
+/// Returns `None` if the initializer does not branch.
+fn dispatch_local(
+    cx: Context,
+    dispatch: &syn::TypeReference,
+    rest: &mut Vec<syn::Stmt>,
+    local: &syn::Local,
+    impl_bounds: Option<&[syn::TraitBound]>,
+) -> syn::Result<Option<Vec<syn::Stmt>>> {
+    if let Some(stmts) = chain_init_only(cx, rest, local)? {
+        return Ok(Some(stmts));
+    }
+
+    let mut local = local.clone();
+    let init = match &mut local.init {
+        Some((_, init)) => init,
+        None => return Ok(None),
+    };
+
+    let uses_enum = cx.uses_enum();
+    let wrap_value = |value: syn::Expr| {
+        if uses_enum {
+            auto_enum::wrap_variant(value)
+        } else {
+            dispatch::wrap_slot(dispatch, value)
+        }
+    };
+
+    let mut expr = if breaks::is_break_target(init) {
+        let mut expr = std::mem::replace(&mut **init, syn::Expr::Verbatim(TokenStream::new()));
+        let mut exits = 0;
+        let count = breaks::replace_break_values(&mut expr, |value| {
+            exits += 1;
+            wrap_value(exit_value(value, impl_bounds, exits))
+        });
+
+        if count == 0 {
+            return Ok(None);
+        }
+        cx.warnings.add_branch_points(1);
+
+        chain_expr(cx, expr, None)?
+    } else {
+        let (placeholder_id, init_expr) = replace_with_placeholder(init);
+        let (expr, branches) = find_branches(cx, init_expr)?;
+
+        if branches.is_empty() {
+            return Ok(None);
+        }
+
+        let value = syn::parse_quote! { #placeholder_id };
+        let wrap_expr = wrap_value(match impl_bounds {
+            Some(bounds) => impl_check::wrap_impl_check(bounds, value),
+            None => value,
+        });
+        fork_expr(cx, expr, branches, Some((placeholder_id, wrap_expr)))?
+    };
+
+    let pat_ident = single_ident(&local.pat, "dispatch")?;
+
+    if impl_bounds.is_some() {
+        impl_check::number_impl_checks(&mut expr);
+    }
+
+    let attrs = &local.attrs;
+    if uses_enum {
+        let expr = auto_enum::declare_variants(dispatch, expr)?;
+
+        // the enum owns the value, which has to be mutable to be used as `&mut dyn Trait`
+        let mut pat_ident = pat_ident.clone();
+        if dispatch.mutability.is_some() {
+            pat_ident.mutability = Some(Default::default());
+        }
+
+        let local: syn::Stmt = syn::parse_quote! {
+            #(#attrs)* let #pat_ident = #expr;
+        };
+        return Ok(Some(once(local).chain(std::mem::take(rest)).collect()));
+    }
+
+    let slots = dispatch::declare_slots(dispatch, &mut expr);
+
+    let local: syn::Stmt = syn::parse_quote! {
+        #(#attrs)* let #pat_ident: #dispatch = #expr;
+    };
+
+    Ok(Some(
+        slots
+            .into_iter()
+            .chain(once(local))
+            .chain(std::mem::take(rest))
+            .collect(),
+    ))
+}
+
+/// Share the continuation of a branching `let` statement between its arms, by moving it
+/// into a local generic function that each arm calls with its own value.
+///
+/// Returns `None` if the initializer has no branch points.
+fn shared_local(
+    cx: Context,
+    shared: &Shared,
+    rest: &mut Vec<syn::Stmt>,
+    local: &syn::Local,
+) -> syn::Result<Option<Vec<syn::Stmt>>> {
+    if let Some(stmts) = chain_init_only(cx, rest, local)? {
+        return Ok(Some(stmts));
+    }
+
+    let mut local = local.clone();
+    let init = match &mut local.init {
+        Some((_, init)) => init,
+        None => return Ok(None),
+    };
+
+    let function = unique_ident();
+    let call = |captures: &[&Binding], value: syn::Expr| -> syn::Expr {
+        let captures = captures.iter().map(|binding| &binding.ident);
+        syn::parse_quote! { #function(#value, #(#captures),*) }
+    };
+
+    let pat_ident = single_ident(&local.pat, "share the continuation of")?;
+
+    let (mut expr, captures) = if breaks::is_break_target(init) {
+        let mut expr = std::mem::replace(&mut **init, syn::Expr::Verbatim(TokenStream::new()));
+        let mut exits = Vec::new();
+        breaks::replace_break_values(&mut expr, |value| {
+            let placeholder_id = PlaceholderId::new();
+            exits.push((placeholder_id, value));
+            syn::parse_quote! { #placeholder_id }
+        });
+
+        if exits.is_empty() {
+            return Ok(None);
+        }
+        cx.warnings.add_branch_points(1);
+        let captures = shared::captures(cx.locals, rest, &pat_ident.ident)?;
+
+        let expr = exits
+            .into_iter()
+            .try_fold(expr, |expr, (placeholder_id, value)| {
+                let value = value.unwrap_or_else(|| syn::parse_quote! { () });
+                replace_expr(expr, placeholder_id, call(&captures, value))
+            })?;
+        (chain_expr(cx, expr, None)?, captures)
+    } else {
+        let (placeholder_id, init_expr) = replace_with_placeholder(init);
+        let (expr, branches) = find_branches(cx, init_expr)?;
+
+        if branches.is_empty() {
+            return Ok(None);
+        }
+        let captures = shared::captures(cx.locals, rest, &pat_ident.ident)?;
+
+        let wrap_expr = call(&captures, syn::parse_quote! { #placeholder_id });
+        let expr = fork_expr(cx, expr, branches, Some((placeholder_id, wrap_expr)))?;
+        (expr, captures)
+    };
+
+    shared::check_control_flow(rest)?;
+    shared::unwrap_values(&mut expr, &function);
+    let function = shared::function(
+        shared,
+        &function,
+        pat_ident,
+        &captures,
+        std::mem::take(rest),
+    );
+
+    Ok(Some(vec![syn::Stmt::Item(function), syn::Stmt::Expr(expr)]))
+}
+
 fn chain_expr(
-    mut expr: syn::Expr,
+    cx: Context,
+    expr: syn::Expr,
     wrap_expr: Option<(PlaceholderId, syn::Expr)>,
 ) -> syn::Result<syn::Expr> {
-    let mut visitor = Visitor::default();
+    let (expr, branches) = find_branches(cx, expr)?;
+    fork_expr(cx, expr, branches, wrap_expr)
+}
+
+/// Transform an expression that is not the initializer of a `let` statement or the tail
+/// expression of a block, which is not a branch point itself with the `shallow` option.
+fn chain_inner_expr(cx: Context, expr: syn::Expr) -> syn::Result<syn::Expr> {
+    let (expr, branches) = find_branches_within(cx, expr, false)?;
+    fork_expr(cx, expr, branches, None)
+}
+
+/// Replace all branch points in an expression with placeholders, and return the
+/// replaced expression together with the branches.
+fn find_branches(
+    cx: Context,
+    expr: syn::Expr,
+) -> syn::Result<(syn::Expr, Vec<(PlaceholderId, syn::Expr)>)> {
+    find_branches_within(cx, expr, true)
+}
+
+/// Like [`find_branches`], where `top` is whether the expression is the initializer of a
+/// `let` statement or the tail expression of a block.
+fn find_branches_within(
+    cx: Context,
+    mut expr: syn::Expr,
+    top: bool,
+) -> syn::Result<(syn::Expr, Vec<(PlaceholderId, syn::Expr)>)> {
+    let mut visitor = Visitor::new(cx, top);
     visitor.visit_expr_mut(&mut expr);
 
     if let Some(err) = visitor.error {
         return Err(err);
     }
 
+    cx.warnings.add_branch_points(visitor.branches.len());
+    warnings::check_reordering(cx.warnings, &expr, &visitor.branches);
+
+    Ok((expr, visitor.branches))
+}
+
+/// Nest the branches found by [`find_branches`], so that every arm gets its own copy
+/// of the expression, optionally wrapped in `wrap_expr`.
+pub fn fork_expr(
+    cx: Context,
+    mut expr: syn::Expr,
+    branches: Vec<(PlaceholderId, syn::Expr)>,
+    wrap_expr: Option<(PlaceholderId, syn::Expr)>,
+) -> syn::Result<syn::Expr> {
     if let Some((placeholder_id, wrap_expr)) = wrap_expr {
         wrap_placeholder_expr_mut(&mut expr, placeholder_id, wrap_expr)?;
     }
 
-    visitor
-        .branches
+    fork_branches(cx, expr, branches)
+}
+
+/// Nest `branches` around `expr`. A branch that is found in an arm of another branch is
+/// only nested into the copies of `expr` that take that arm.
+fn fork_branches(
+    cx: Context,
+    expr: syn::Expr,
+    branches: Vec<(PlaceholderId, syn::Expr)>,
+) -> syn::Result<syn::Expr> {
+    // the identifiers in the branches that are nested into the arms of a branch later
+    let mut pending = Vec::with_capacity(branches.len());
+    let mut used = BTreeSet::new();
+    for (_, branch_expr) in &branches {
+        pending.push(used.clone());
+        let mut visitor = UsedIdents { idents: used };
+        visitor.visit_expr(branch_expr);
+        used = visitor.idents;
+    }
+
+    // the branches in the arms of other branches are only evaluated when that arm is taken
+    let nested_ids = branches
+        .iter()
+        .map(|(branch_id, _)| *branch_id)
+        .filter(|branch_id| {
+            branches.iter().any(|(_, branch_expr)| {
+                branch_arms(branch_expr)
+                    .into_iter()
+                    .any(|arm| contains_placeholder_id(arm, *branch_id))
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut branches = branches.into_iter().zip(pending).collect::<Vec<_>>();
+    let mut nested = drain_filter(&mut branches, |((branch_id, _), _)| {
+        nested_ids.contains(branch_id)
+    })
+    .into_iter()
+    .map(|(branch, _)| branch)
+    .collect::<Vec<_>>();
+
+    branches
         .into_iter()
         .rev()
-        .try_fold(expr, |expr, (branch_id, mut branch_expr)| {
+        .try_fold(expr, |expr, ((branch_id, mut branch_expr), pending)| {
+            // the bindings of an arm only need new names if they would shadow variables
+            // that the continuation uses
+            let mut used = UsedIdents { idents: pending };
+            used.visit_expr(&expr);
+            let used = used.idents;
+
             match &mut branch_expr {
                 syn::Expr::Match(match_expr) => {
                     let old_arms = std::mem::take(&mut match_expr.arms);
+                    // pruned arms panic if they are reached
+                    let scrutinee = narrow::scrutinee_ident(&match_expr.expr)
+                        .filter(|_| !cx.options.panic_free)
+                        .cloned();
+                    let mut previous_pats = Vec::new();
 
-                    for mut arm in old_arms {
+                    for (index, mut arm) in old_arms.into_iter().enumerate() {
                         if let Some((if_, _)) = &arm.guard {
                             return Err(syn::Error::new_spanned(if_, "cain! dos not support match guards"));
                         }
 
+                        let arm_branches = take_nested_branches(&arm.body, &mut nested);
+
                         let unreachable_arm = {
                             let mut arm = arm.clone();
                             arm.body = syn::parse_quote! { unreachable!() };
@@ -109,10 +992,28 @@ fn chain_expr(
                             arm
                         };
 
+                        let label = cx
+                            .track_paths
+                            .then(|| paths::match_label(&match_expr.expr, &arm.pat));
+
+                        let knowledge = scrutinee
+                            .as_ref()
+                            .and_then(|_| narrow::Knowledge::from_arm(&arm.pat, &previous_pats));
+                        previous_pats.push(arm.pat.clone());
+
                         let mut pat_idents = BTreeMap::new();
-                        replace_pat_idents(&mut arm.pat, &mut pat_idents)?;
+                        if binds_any(&arm.pat, &used) {
+                            replace_pat_idents(&mut arm.pat, &mut pat_idents)?;
+                        }
 
-                        if !pat_idents.is_empty() {
+                        if !pat_idents.is_empty() && cx.options.panic_free {
+                            let body = std::mem::replace(&mut *arm.body, syn::Expr::Verbatim(TokenStream::new()));
+                            let stmts = match body {
+                                syn::Expr::Block(syn::ExprBlock { attrs, label: None, block }) if attrs.is_empty() => block.stmts,
+                                body => vec![syn::Stmt::Expr(body)],
+                            };
+                            *arm.body = block_expr(rebind_pat_idents(&pat_idents, stmts)?);
+                        } else if !pat_idents.is_empty() {
                             let old_idents = pat_idents.keys().collect::<Vec<_>>();
                             let new_idents = pat_idents.values().map(|(ident, _)| ident).collect::<Vec<_>>();
                             let mutability = pat_idents.values().map(|(_, mutability)| mutability).collect::<Vec<_>>();
@@ -147,23 +1048,57 @@ fn chain_expr(
                             };
                         }
 
-                        wrap_placeholder_expr_mut(&mut arm.body, branch_id, expr.clone())?;
+                        // the continuation is unreachable after an arm that diverges
+                        if !arm_branches.is_empty() || !arm_diverges(&arm.body) {
+                            wrap_placeholder_expr_mut(&mut arm.body, branch_id, cx.continuation(&expr))?;
+                        }
+                        if !arm_branches.is_empty() {
+                            let body = std::mem::replace(&mut *arm.body, syn::Expr::Verbatim(TokenStream::new()));
+                            *arm.body = fork_branches(cx, body, arm_branches)?;
+                        }
+                        impl_check::number_arm(&mut arm.body, index + 1);
+                        if let Some(label) = &label {
+                            paths::label_expr(&mut arm.body, label);
+                        }
+                        if let (Some(scrutinee), Some(knowledge)) = (&scrutinee, &knowledge) {
+                            narrow::narrow_expr(&mut arm.body, scrutinee, knowledge);
+                        }
 
                         match_expr.arms.push(arm);
-                        if !pat_idents.is_empty() {
+                        if !pat_idents.is_empty() && !cx.options.panic_free {
                             match_expr.arms.push(unreachable_arm);
                         }
                     }
                 }
 
                 syn::Expr::If(if_expr) => {
+                    let labels = cx.track_paths.then(|| {
+                        (
+                            paths::if_label(&if_expr.cond, true),
+                            paths::if_label(&if_expr.cond, false),
+                        )
+                    });
+
                     let mut guard = None;
+                    let mut unwrap_value = false;
 
                     if let syn::Expr::Let(expr_let) = &mut *if_expr.cond {
                         let mut pat_idents = BTreeMap::new();
-                        replace_pat_idents(&mut expr_let.pat, &mut pat_idents)?;
+                        if binds_any(&expr_let.pat, &used) {
+                            replace_pat_idents(&mut expr_let.pat, &mut pat_idents)?;
+                        }
 
-                        if !pat_idents.is_empty() {
+                        // braces around a binding of the pattern trigger `unused_braces`
+                        unwrap_value = pat_idents.is_empty()
+                            && matches!(
+                                &if_expr.then_branch.stmts[..],
+                                [syn::Stmt::Expr(syn::Expr::Path(_) | syn::Expr::Lit(_))]
+                            );
+
+                        if !pat_idents.is_empty() && cx.options.panic_free {
+                            let stmts = std::mem::take(&mut if_expr.then_branch.stmts);
+                            if_expr.then_branch.stmts = rebind_pat_idents(&pat_idents, stmts)?;
+                        } else if !pat_idents.is_empty() {
                             guard = Some(
                                 pat_idents
                                     .iter()
@@ -187,9 +1122,7 @@ fn chain_expr(
                             let then_branch = &if_expr.then_branch;
                             if_expr.then_branch = syn::parse_quote! {
                                 {
-                                    if let ( #( #mutability #old_idents, )* ) = ( #( #new_idents, )* ) {
-                                        #then_branch
-                                    } else {
+                                    if let ( #( #mutability #old_idents, )* ) = ( #( #new_idents, )* ) #then_branch else {
                                         unreachable!()
                                     }
                                 }
@@ -197,10 +1130,39 @@ fn chain_expr(
                         }
                     }
 
-                    wrap_placeholder_block_mut(&mut if_expr.then_branch, branch_id, expr.clone())?;
+                    if unwrap_value {
+                        let mut value = block_expr(std::mem::take(&mut if_expr.then_branch.stmts));
+                        dispatch::unwrap_block(&mut value);
+                        wrap_placeholder_expr_mut(&mut value, branch_id, cx.continuation(&expr))?;
+                        if_expr.then_branch.stmts = vec![syn::Stmt::Expr(value)];
+                    } else if !block_diverges(&if_expr.then_branch) {
+                        wrap_placeholder_block_mut(&mut if_expr.then_branch, branch_id, cx.continuation(&expr))?;
+                    }
+                    impl_check::number_arm_block(&mut if_expr.then_branch, 1);
 
                     if let Some((_, else_branch)) = &mut if_expr.else_branch {
-                        wrap_placeholder_expr_mut(else_branch, branch_id, expr)?;
+                        let else_branches = take_nested_branches(else_branch, &mut nested);
+                        if !else_branches.is_empty() || !arm_diverges(else_branch) {
+                            wrap_placeholder_expr_mut(else_branch, branch_id, cx.continuation(&expr))?;
+                        }
+                        if !else_branches.is_empty() {
+                            let body = std::mem::replace(&mut **else_branch, syn::Expr::Verbatim(TokenStream::new()));
+                            **else_branch = fork_branches(cx, body, else_branches)?;
+                        }
+                        impl_check::number_arm(else_branch, 2);
+                    }
+
+                    if let Some((then_label, else_label)) = &labels {
+                        paths::label_block(&mut if_expr.then_branch, then_label);
+                        if let Some((_, else_branch)) = &mut if_expr.else_branch {
+                            paths::label_expr(else_branch, else_label);
+                        }
+                    }
+
+                    if let Some(Condition::Cfg(pred)) = condition::evaluate(&if_expr.cond, cx.consts) {
+                        if let Some(cfg_expr) = condition::cfg_branches(if_expr, &pred) {
+                            return Ok(cfg_expr);
+                        }
                     }
 
                     if let Some(guard) = guard {
@@ -224,35 +1186,312 @@ fn chain_expr(
         })
 }
 
-#[derive(Default)]
-struct Visitor {
+/// Returns the arms of a branch, which are only evaluated on some of its paths.
+fn branch_arms(branch_expr: &syn::Expr) -> Vec<&syn::Expr> {
+    match branch_expr {
+        syn::Expr::Match(match_expr) => match_expr.arms.iter().map(|arm| &*arm.body).collect(),
+        syn::Expr::If(if_expr) => if_expr
+            .else_branch
+            .iter()
+            .map(|(_, else_branch)| &**else_branch)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Take the branches that are found in `arm` out of `nested`, together with the branches
+/// that are found in those, in their original order.
+fn take_nested_branches(
+    arm: &syn::Expr,
+    nested: &mut Vec<(PlaceholderId, syn::Expr)>,
+) -> Vec<(PlaceholderId, syn::Expr)> {
+    let mut taken = nested
+        .iter()
+        .map(|(branch_id, _)| contains_placeholder_id(arm, *branch_id))
+        .collect::<Vec<_>>();
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for index in 0..nested.len() {
+            let branch_id = nested[index].0;
+            let found = |(other, (_, branch_expr)): (&bool, &(PlaceholderId, syn::Expr))| {
+                *other && contains_placeholder_id(branch_expr, branch_id)
+            };
+            if !taken[index] && taken.iter().zip(nested.iter()).any(found) {
+                taken[index] = true;
+                changed = true;
+            }
+        }
+    }
+
+    let mut taken = taken.into_iter();
+    let (arm_branches, rest) = std::mem::take(nested)
+        .into_iter()
+        .partition(|_| taken.next().unwrap_or(false));
+    *nested = rest;
+    arm_branches
+}
+
+/// Count the number of paths that a `let` statement forks its continuation into.
+fn count_local_paths(cx: Context, local: &syn::Local) -> usize {
+    if let Some(mac) = nested::local_macro(local) {
+        return count_nested_paths(cx, mac);
+    }
+
+    match &local.init {
+        Some((_, init)) if breaks::is_break_target(init) => breaks::count_break_values(init).max(1),
+
+        Some((_, init)) => {
+            let mut counter = PathCounter::new(cx);
+            counter.visit_expr(init);
+            counter.paths
+        }
+
+        None => 1,
+    }
+}
+
+/// Count the number of paths that a nested `cain!` block forks the continuation of the
+/// `let` statement that it initializes into.
+fn count_nested_paths(cx: Context, mac: &syn::Macro) -> usize {
+    // errors are reported when the nested block is transformed
+    let (options, stmts) = match nested::parse(mac, cx.options, cx.track_paths) {
+        Ok(nested) => nested,
+        Err(_) => return 1,
+    };
+    let consts = condition::bool_consts(&stmts, cx.consts);
+    let cx = Context {
+        options: &options,
+        consts: &consts,
+        ..cx
+    };
+
+    let mut paths = 1_usize;
+    for (index, stmt) in stmts.iter().enumerate() {
+        let arms = match stmt {
+            syn::Stmt::Local(local) => count_local_paths(cx, local),
+            syn::Stmt::Expr(tail) if index + 1 == stmts.len() => {
+                let mut counter = PathCounter::new(cx);
+                counter.visit_expr(tail);
+                counter.paths
+            }
+            _ => 1,
+        };
+
+        let cx = cx.with_paths(cx.paths.saturating_mul(paths));
+        if cx.duplicates(arms) {
+            paths = paths.saturating_mul(arms);
+        }
+    }
+
+    paths
+}
+
+/// Counts the paths through the branch points that [`Visitor`] would find.
+struct PathCounter<'a> {
+    cx: Context<'a>,
+    paths: usize,
+    top: bool,
+}
+
+impl<'a> PathCounter<'a> {
+    fn new(cx: Context<'a>) -> PathCounter<'a> {
+        PathCounter {
+            cx,
+            paths: 1,
+            top: true,
+        }
+    }
+
+    /// Count the paths through an arm of a branch point, which only runs on the paths
+    /// that take that arm.
+    fn count_arm(&self, expr: &syn::Expr, top: bool) -> usize {
+        let mut counter = PathCounter {
+            cx: self.cx,
+            paths: 1,
+            top,
+        };
+        counter.visit_expr(expr);
+        counter.paths
+    }
+}
+
+impl<'a, 'ast> Visit<'ast> for PathCounter<'a> {
+    fn visit_expr(&mut self, i: &'ast syn::Expr) {
+        let top = self.top;
+        let unmarked = is_unmarked_branch_point(self.cx, &mut self.top, i);
+
+        match i {
+            syn::Expr::Macro(expr_macro) if self.cx.options.is_transparent(&expr_macro.mac) => {
+                if let Ok(args) = parse_macro_args(&expr_macro.mac) {
+                    args.iter().for_each(|arg| self.visit_expr(arg));
+                }
+            }
+
+            // the arms of an expression that is not a branch point are transformed on
+            // their own
+            syn::Expr::Match(match_expr)
+                if !marker::is_branch_point(&match_expr.attrs, unmarked) =>
+            {
+                return self.visit_expr(&match_expr.expr);
+            }
+            syn::Expr::If(if_expr) if !marker::is_branch_point(&if_expr.attrs, unmarked) => {
+                return self.visit_expr(&if_expr.cond);
+            }
+
+            // the paths through the arms add up, while the paths through the parts of an
+            // expression that are evaluated one after another multiply
+            syn::Expr::Match(match_expr) => {
+                self.visit_expr(&match_expr.expr);
+                let arms = match_expr
+                    .arms
+                    .iter()
+                    .map(|arm| self.count_arm(&arm.body, false))
+                    .fold(0_usize, usize::saturating_add);
+                self.paths = self.paths.saturating_mul(arms);
+                return;
+            }
+
+            syn::Expr::If(if_expr) => match condition::evaluate(&if_expr.cond, self.cx.consts) {
+                Some(Condition::Const(value)) => {
+                    self.top = top;
+                    return self.visit_expr(&condition::live_branch(if_expr, value));
+                }
+                _ => {
+                    if let Some((_, else_branch)) = &if_expr.else_branch {
+                        self.visit_expr(&if_expr.cond);
+                        // an `else if` branch is part of the same expression
+                        let arms = 1_usize.saturating_add(self.count_arm(else_branch, top));
+                        self.paths = self.paths.saturating_mul(arms);
+                        return;
+                    }
+                }
+            },
+
+            _ => (),
+        }
+
+        syn::visit::visit_expr(self, i);
+    }
+
+    // branch points in blocks and closures do not fork the enclosing expression
+    fn visit_block(&mut self, _: &'ast syn::Block) {}
+    fn visit_expr_closure(&mut self, _: &'ast syn::ExprClosure) {}
+}
+
+struct Visitor<'a> {
+    cx: Context<'a>,
     branches: Vec<(PlaceholderId, syn::Expr)>,
     error: Option<syn::Error>,
+    top: bool,
 }
 
-impl Visitor {
+impl<'a> Visitor<'a> {
+    fn new(cx: Context<'a>, top: bool) -> Visitor<'a> {
+        Visitor {
+            cx,
+            branches: Vec::new(),
+            error: None,
+            top,
+        }
+    }
+
     fn fail(&mut self, error: syn::Error) {
         self.error.get_or_insert(error);
     }
+
+    /// Transform an expression that is only evaluated in some cases, such as an arm of a
+    /// `match` that is not a branch point, on its own.
+    fn chain_in_place(&mut self, expr: &mut syn::Expr) {
+        let body = std::mem::replace(expr, syn::Expr::Verbatim(TokenStream::new()));
+        *expr = match chain_inner_expr(self.cx, body) {
+            Ok(expr) => expr,
+            Err(err) => return self.fail(err),
+        };
+    }
+
+    /// Visit an `if` expression that is a branch point, where `top` is whether the
+    /// expression is a whole initializer or tail expression. Its `else if` branches are
+    /// part of the same expression, so they are as well.
+    fn visit_forked_if(&mut self, if_expr: &mut syn::ExprIf, top: bool) {
+        for attr in &mut if_expr.attrs {
+            self.visit_attribute_mut(attr);
+        }
+        self.visit_expr_mut(&mut if_expr.cond);
+        self.visit_block_mut(&mut if_expr.then_branch);
+
+        if let Some((_, else_branch)) = &mut if_expr.else_branch {
+            self.top = top;
+            self.visit_expr_mut(else_branch);
+        }
+    }
+
+    /// Transform the branches of an `if` expression that is not a branch point, including
+    /// the conditions of its `else if` branches, which are only evaluated in some cases.
+    fn visit_unforked_if_branches(&mut self, if_expr: &mut syn::ExprIf) {
+        self.visit_block_mut(&mut if_expr.then_branch);
+
+        if let Some((_, else_branch)) = &mut if_expr.else_branch {
+            if let syn::Expr::If(else_if) = &mut **else_branch {
+                self.chain_in_place(&mut else_if.cond);
+                self.visit_unforked_if_branches(else_if);
+            } else {
+                self.visit_expr_mut(else_branch);
+            }
+        }
+    }
 }
 
-impl VisitMut for Visitor {
+impl<'a> VisitMut for Visitor<'a> {
     fn visit_block_mut(&mut self, i: &mut Block) {
-        i.stmts = match chain_stmts(i.stmts.clone()) {
+        i.stmts = match chain_stmts(self.cx, std::mem::take(&mut i.stmts)) {
             Ok(stmts) => stmts,
             Err(err) => return self.fail(err),
         };
     }
 
     fn visit_expr_closure_mut(&mut self, i: &mut syn::ExprClosure) {
-        i.body = match chain_expr((*i.body).clone(), None) {
+        let body = std::mem::replace(&mut *i.body, syn::Expr::Verbatim(TokenStream::new()));
+        i.body = match chain_inner_expr(self.cx, body) {
             Ok(expr) => Box::new(expr),
             Err(err) => return self.fail(err),
         };
     }
 
+    fn visit_attribute_mut(&mut self, i: &mut syn::Attribute) {
+        if let Err(err) = marker::check_misplaced(i) {
+            self.fail(err);
+        }
+    }
+
     fn visit_expr_mut(&mut self, i: &mut syn::Expr) {
+        let top = self.top;
+        let unmarked = is_unmarked_branch_point(self.cx, &mut self.top, i);
+        let is_branch_point = match i {
+            syn::Expr::Match(syn::ExprMatch { attrs, .. })
+            | syn::Expr::If(syn::ExprIf { attrs, .. }) => {
+                match marker::take_branch_point(attrs, unmarked) {
+                    Ok(is_branch_point) => is_branch_point,
+                    Err(err) => return self.fail(err),
+                }
+            }
+            _ => false,
+        };
+
         match i {
+            syn::Expr::Match(match_expr) if !is_branch_point => {
+                self.visit_expr_mut(&mut match_expr.expr);
+                for arm in &mut match_expr.arms {
+                    self.chain_in_place(&mut arm.body);
+                }
+            }
+
+            syn::Expr::If(if_expr) if !is_branch_point => {
+                self.visit_expr_mut(&mut if_expr.cond);
+                self.visit_unforked_if_branches(if_expr);
+            }
+
             syn::Expr::Match(_) => {
                 let (branch_id, mut expr) = replace_with_placeholder(i);
                 let match_expr = match &mut expr {
@@ -265,23 +1504,145 @@ impl VisitMut for Visitor {
                 self.branches.push((branch_id, expr));
             }
 
-            syn::Expr::If(_) => {
+            syn::Expr::If(if_expr) => {
+                // an `if` expression with a constant condition is not forked, but the block
+                // still relies on `cain!` if its arms have different types
+                if let Some(Condition::Const(value)) =
+                    condition::evaluate(&if_expr.cond, self.cx.consts)
+                {
+                    self.cx.warnings.add_branch_points(1);
+                    *i = condition::live_branch(if_expr, value);
+                    self.top = top;
+                    return self.visit_expr_mut(i);
+                }
+
                 let (branch_id, mut expr) = replace_with_placeholder(i);
                 let if_expr = match &mut expr {
                     syn::Expr::If(if_expr) => if_expr,
                     _ => unreachable!(),
                 };
 
-                self.visit_expr_if_mut(if_expr);
+                self.visit_forked_if(if_expr, top);
 
                 self.branches.push((branch_id, expr));
             }
 
+            syn::Expr::ForLoop(for_loop) => match each::values(&for_loop.expr) {
+                Some(Ok(values)) => {
+                    self.cx.warnings.add_branch_points(1);
+                    *i = each::unroll(for_loop, values);
+                    syn::visit_mut::visit_expr_mut(self, i);
+                }
+                Some(Err(err)) => self.fail(err),
+                None => syn::visit_mut::visit_expr_mut(self, i),
+            },
+
+            syn::Expr::Macro(expr_macro) if nested::is_cain_macro(&expr_macro.mac) => {
+                match chain_nested_block(self.cx, expr_macro) {
+                    Ok(expr) => *i = expr,
+                    Err(err) => self.fail(err),
+                }
+            }
+
+            syn::Expr::Macro(expr_macro) if self.cx.options.is_transparent(&expr_macro.mac) => {
+                let mac = &mut expr_macro.mac;
+
+                let mut args = match parse_macro_args(mac) {
+                    Ok(args) => args,
+                    Err(err) => {
+                        return self.fail(syn::Error::new(
+                            err.span(),
+                            format!(
+                                "cain! cannot parse the arguments of transparent macro `{}!`: {}",
+                                mac.path.to_token_stream(),
+                                err
+                            ),
+                        ))
+                    }
+                };
+
+                args.iter_mut().for_each(|arg| self.visit_expr_mut(arg));
+                mac.tokens = args.into_token_stream();
+            }
+
             _ => syn::visit_mut::visit_expr_mut(self, i),
         }
     }
 }
 
+/// Returns whether a `match` or `if` expression is a branch point without a marker, where
+/// `top` is whether it is the whole initializer of a `let` statement or the tail expression
+/// of a block, and is cleared for the subexpressions of `expr`. The `else if` branches of
+/// an `if` expression restore it themselves.
+fn is_unmarked_branch_point(cx: Context, top: &mut bool, expr: &syn::Expr) -> bool {
+    let unmarked = !cx.options.explicit && (*top || !cx.options.shallow);
+
+    // parentheses do not change what the initializer is, as in `let (a, b) = (match ...);`
+    if !matches!(expr, syn::Expr::Paren(_) | syn::Expr::Group(_)) {
+        *top = false;
+    }
+
+    unmarked
+}
+
+/// Bind the original names of renamed pattern bindings before `stmts`, with an irrefutable
+/// `let` statement instead of an `if let` with an `unreachable!()` fallback.
+fn rebind_pat_idents(
+    pat_idents: &BTreeMap<syn::Ident, (syn::Ident, Option<syn::token::Mut>)>,
+    stmts: Vec<syn::Stmt>,
+) -> syn::Result<Vec<syn::Stmt>> {
+    // a constant or a unit variant would make the `let` statement refutable
+    let is_uppercase = |ident: &&syn::Ident| ident.to_string().starts_with(char::is_uppercase);
+    if let Some(ident) = pat_idents.keys().find(is_uppercase) {
+        return Err(syn::Error::new_spanned(
+            ident,
+            format!(
+                "cain! cannot tell whether `{}` is a binding or a constant in `panic_free` mode, write constants and unit variants as paths, as in `Option::None`",
+                ident
+            ),
+        ));
+    }
+
+    let old_idents = pat_idents.keys();
+    let new_idents = pat_idents.values().map(|(ident, _)| ident);
+    let mutability = pat_idents.values().map(|(_, mutability)| mutability);
+    let rebind: syn::Stmt = syn::parse_quote! {
+        let ( #( #mutability #old_idents, )* ) = ( #( #new_idents, )* );
+    };
+
+    Ok(once(rebind).chain(stmts).collect())
+}
+
+/// Returns whether a pattern binds any of the identifiers in `idents`, or may do so
+/// through a pattern that [`replace_pat_idents`] cannot look into.
+fn binds_any(pat: &syn::Pat, idents: &BTreeSet<String>) -> bool {
+    struct BindsVisitor<'a> {
+        idents: &'a BTreeSet<String>,
+        binds: bool,
+    }
+
+    impl<'a, 'ast> Visit<'ast> for BindsVisitor<'a> {
+        fn visit_pat(&mut self, i: &'ast syn::Pat) {
+            match i {
+                syn::Pat::Ident(pat_ident)
+                    if self.idents.contains(&pat_ident.ident.to_string()) =>
+                {
+                    self.binds = true
+                }
+                syn::Pat::Macro(_) | syn::Pat::Verbatim(_) => self.binds = true,
+                _ => syn::visit::visit_pat(self, i),
+            }
+        }
+    }
+
+    let mut visitor = BindsVisitor {
+        idents,
+        binds: false,
+    };
+    visitor.visit_pat(pat);
+    visitor.binds
+}
+
 fn replace_pat_idents(
     pat: &mut syn::Pat,
     ident_map: &mut BTreeMap<syn::Ident, (syn::Ident, Option<syn::token::Mut>)>,
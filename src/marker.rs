@@ -0,0 +1,110 @@
+//! The `#[cain::skip]` and `#[cain::fork]` markers, which choose whether an individual
+//! `match` or `if` expression is a branch point.
+//!
+//! Without markers, every `match` and `if` expression is a branch point. A `match` whose
+//! arms already have the same type does not need to be one, and `#[cain::skip]` leaves it
+//! alone. With the `explicit` option, only expressions that are marked with
+//! `#[cain::fork]` are branch points.
+
+use crate::util::path_is;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Marker {
+    Skip,
+    Fork,
+}
+
+fn marker(attr: &syn::Attribute) -> Option<Marker> {
+    if path_is(&attr.path, &["cain", "skip"]) {
+        Some(Marker::Skip)
+    } else if path_is(&attr.path, &["cain", "fork"]) {
+        Some(Marker::Fork)
+    } else {
+        None
+    }
+}
+
+/// Returns whether an expression with `attrs` is a branch point, where `unmarked` is
+/// whether it would be one without a marker, without checking that its markers are valid.
+pub fn is_branch_point(attrs: &[syn::Attribute], unmarked: bool) -> bool {
+    match attrs.iter().find_map(marker) {
+        Some(marker) => marker == Marker::Fork,
+        None => unmarked,
+    }
+}
+
+/// Remove the marker from the attributes of a `match` or `if` expression, and return
+/// whether the expression is a branch point, where `unmarked` is whether it would be one
+/// without a marker. The other attributes are kept.
+pub fn take_branch_point(attrs: &mut Vec<syn::Attribute>, unmarked: bool) -> syn::Result<bool> {
+    let mut found = None;
+    for attr in attrs.iter() {
+        if marker(attr).is_none() {
+            continue;
+        }
+
+        if !attr.tokens.is_empty() {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "cain! markers do not take arguments",
+            ));
+        }
+        if found.is_some() {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "cain! expected at most one `#[cain::skip]` or `#[cain::fork]` marker",
+            ));
+        }
+        found = marker(attr);
+    }
+
+    attrs.retain(|attr| marker(attr).is_none());
+
+    Ok(match found {
+        Some(marker) => marker == Marker::Fork,
+        None => unmarked,
+    })
+}
+
+/// Move the markers of a `let` statement onto its initializer, which they apply to.
+pub fn move_local_markers(local: &mut syn::Local) -> syn::Result<()> {
+    let first = match local.attrs.iter().find(|attr| marker(attr).is_some()) {
+        Some(attr) => attr,
+        None => return Ok(()),
+    };
+
+    let init_attrs = match local.init.as_mut().map(|(_, init)| &mut **init) {
+        Some(syn::Expr::Match(match_expr)) => &mut match_expr.attrs,
+        Some(syn::Expr::If(if_expr)) => &mut if_expr.attrs,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                first,
+                "cain! markers on a `let` statement need a `match` or `if` initializer",
+            ));
+        }
+    };
+
+    let mut markers = Vec::new();
+    local.attrs.retain(|attr| {
+        let is_marker = marker(attr).is_some();
+        if is_marker {
+            markers.push(attr.clone());
+        }
+        !is_marker
+    });
+    init_attrs.splice(0..0, markers);
+
+    Ok(())
+}
+
+/// Returns an error for a marker that was left on an expression that is not a `match` or
+/// `if` expression.
+pub fn check_misplaced(attr: &syn::Attribute) -> syn::Result<()> {
+    match marker(attr) {
+        Some(_) => Err(syn::Error::new_spanned(
+            attr,
+            "cain! markers can only be put on `match` and `if` expressions",
+        )),
+        None => Ok(()),
+    }
+}
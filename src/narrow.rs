@@ -0,0 +1,430 @@
+//! Pruning of impossible combinations of arms when the same variable is matched twice.
+//!
+//! When the continuation of an arm of `match x` contains another `match x`, only the
+//! arms of the inner match that are compatible with the outer arm can be taken. The
+//! other arms are replaced by a single `_ => unreachable!()` arm, so that their copies of
+//! the continuation are never generated.
+//!
+//! Only literal patterns are compared. Two literals of the same kind with different values
+//! never match the same value. Paths are never compared, since two different paths can
+//! name constants with the same value, so arms with path patterns are always kept.
+
+use proc_macro2::{TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::visit::Visit;
+use syn::visit_mut::VisitMut;
+
+/// What is known about the value of a variable in an arm that matched it.
+#[derive(Clone, Debug)]
+pub enum Knowledge {
+    /// The value is one of these values.
+    OneOf(Vec<Value>),
+
+    /// The value is none of these values.
+    NoneOf(Vec<Value>),
+}
+
+/// A value in a literal pattern.
+#[derive(Clone, Debug)]
+pub struct Value {
+    kind: &'static str,
+    value: String,
+}
+
+impl Value {
+    fn definitely_equal(&self, other: &Value) -> bool {
+        self.kind == other.kind && self.value == other.value
+    }
+
+    fn definitely_different(&self, other: &Value) -> bool {
+        self.kind == other.kind && self.value != other.value
+    }
+}
+
+/// The values that a pattern matches.
+enum PatValues {
+    /// The pattern matches any value.
+    Any,
+
+    /// The pattern matches exactly these values.
+    OneOf(Vec<Value>),
+
+    /// The pattern is not understood.
+    Unknown,
+}
+
+fn pat_values(pat: &syn::Pat) -> PatValues {
+    match pat {
+        syn::Pat::Wild(_) => PatValues::Any,
+
+        // by convention, a capitalized identifier is a constant or a unit variant instead
+        // of a binding
+        syn::Pat::Ident(pat_ident)
+            if pat_ident.subpat.is_none()
+                && (pat_ident.by_ref.is_some()
+                    || pat_ident.mutability.is_some()
+                    || !starts_with_uppercase(&pat_ident.ident)) =>
+        {
+            PatValues::Any
+        }
+
+        syn::Pat::Lit(pat_lit) => match lit_value(&pat_lit.expr) {
+            Some(value) => PatValues::OneOf(vec![value]),
+            None => PatValues::Unknown,
+        },
+
+        syn::Pat::Or(pat_or) => {
+            let mut values = Vec::new();
+            for case in &pat_or.cases {
+                match pat_values(case) {
+                    PatValues::Any => return PatValues::Any,
+                    PatValues::OneOf(case_values) => values.extend(case_values),
+                    PatValues::Unknown => return PatValues::Unknown,
+                }
+            }
+            PatValues::OneOf(values)
+        }
+
+        _ => PatValues::Unknown,
+    }
+}
+
+fn starts_with_uppercase(ident: &syn::Ident) -> bool {
+    ident
+        .to_string()
+        .chars()
+        .next()
+        .into_iter()
+        .any(char::is_uppercase)
+}
+
+fn lit_value(expr: &syn::Expr) -> Option<Value> {
+    let (negative, lit) = match expr {
+        syn::Expr::Lit(expr_lit) => (false, &expr_lit.lit),
+        syn::Expr::Unary(syn::ExprUnary {
+            op: syn::UnOp::Neg(_),
+            expr,
+            ..
+        }) => match &**expr {
+            syn::Expr::Lit(expr_lit) => (true, &expr_lit.lit),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let (kind, value) = match lit {
+        syn::Lit::Int(lit) => ("int", lit.base10_digits().to_string()),
+        syn::Lit::Str(lit) => ("str", lit.value()),
+        syn::Lit::ByteStr(lit) => ("byte_str", format!("{:?}", lit.value())),
+        syn::Lit::Byte(lit) => ("byte", lit.value().to_string()),
+        syn::Lit::Char(lit) => ("char", lit.value().to_string()),
+        syn::Lit::Bool(lit) => ("bool", lit.value.to_string()),
+        _ => return None,
+    };
+
+    // `-0` matches the same value as `0`
+    let value = if negative && value != "0" {
+        format!("-{}", value)
+    } else {
+        value
+    };
+
+    Some(Value { kind, value })
+}
+
+impl Knowledge {
+    /// What is known about the scrutinee in an arm with pattern `pat`, after the arms
+    /// with `previous` patterns did not match.
+    pub fn from_arm(pat: &syn::Pat, previous: &[syn::Pat]) -> Option<Knowledge> {
+        match pat_values(pat) {
+            PatValues::OneOf(values) => Some(Knowledge::OneOf(values)),
+
+            PatValues::Any => {
+                let excluded = previous
+                    .iter()
+                    .flat_map(|pat| match pat_values(pat) {
+                        PatValues::OneOf(values) => values,
+                        PatValues::Any | PatValues::Unknown => Vec::new(),
+                    })
+                    .collect();
+                Some(Knowledge::NoneOf(excluded))
+            }
+
+            PatValues::Unknown => None,
+        }
+    }
+
+    /// Returns whether an arm with this pattern can match, and whether it certainly
+    /// matches.
+    fn check_arm(&self, arm: &syn::Arm) -> (bool, bool) {
+        let guarded = arm.guard.is_some();
+
+        match (self, pat_values(&arm.pat)) {
+            (_, PatValues::Any) => (true, !guarded),
+            (_, PatValues::Unknown) => (true, false),
+
+            (Knowledge::OneOf(known), PatValues::OneOf(values)) => {
+                let possible = known.iter().any(|known| {
+                    values
+                        .iter()
+                        .any(|value| !known.definitely_different(value))
+                });
+                let certain = known
+                    .iter()
+                    .all(|known| values.iter().any(|value| known.definitely_equal(value)));
+                (possible, certain && !guarded)
+            }
+
+            (Knowledge::NoneOf(excluded), PatValues::OneOf(values)) => {
+                let possible = values.iter().any(|value| {
+                    !excluded
+                        .iter()
+                        .any(|excluded| excluded.definitely_equal(value))
+                });
+                (possible, false)
+            }
+        }
+    }
+
+    /// Remove the arms that cannot match from a `match` expression on the same value.
+    fn narrow_arms(&self, expr_match: &mut syn::ExprMatch) {
+        let old_arms = std::mem::take(&mut expr_match.arms);
+        let old_len = old_arms.len();
+
+        for arm in old_arms {
+            let (possible, certain) = self.check_arm(&arm);
+            if possible {
+                expr_match.arms.push(arm);
+            }
+            if certain {
+                break;
+            }
+        }
+
+        // the compiler cannot tell that the remaining arms are exhaustive
+        let irrefutable = match expr_match.arms.last() {
+            Some(arm) => arm.guard.is_none() && matches!(pat_values(&arm.pat), PatValues::Any),
+            None => false,
+        };
+        if !irrefutable && expr_match.arms.len() < old_len {
+            if let Some(arm) = expr_match.arms.last_mut() {
+                arm.comma.get_or_insert_with(Default::default);
+            }
+
+            expr_match.arms.push(syn::parse_quote! {
+                #[allow(unreachable_patterns)]
+                _ => unreachable!()
+            });
+        }
+    }
+}
+
+/// Narrow all matches on `scrutinee` in `expr`, as long as `scrutinee` is not shadowed
+/// or possibly modified.
+pub fn narrow_expr(expr: &mut syn::Expr, scrutinee: &syn::Ident, knowledge: &Knowledge) {
+    let mut visitor = NarrowVisitor {
+        scrutinee,
+        knowledge,
+        stopped: false,
+    };
+    visitor.visit_expr_mut(expr);
+}
+
+/// Returns the variable that a `match` expression matches on, if it is a plain variable.
+///
+/// By convention, a capitalized identifier is a constant or a static, and a `static mut`
+/// can have a different value every time it is read, so it is never narrowed.
+pub fn scrutinee_ident(expr: &syn::Expr) -> Option<&syn::Ident> {
+    match expr {
+        syn::Expr::Path(expr_path) if expr_path.qself.is_none() && expr_path.attrs.is_empty() => {
+            expr_path
+                .path
+                .get_ident()
+                .filter(|ident| !starts_with_uppercase(ident))
+        }
+        _ => None,
+    }
+}
+
+struct NarrowVisitor<'a> {
+    scrutinee: &'a syn::Ident,
+    knowledge: &'a Knowledge,
+
+    /// Set once the variable may have been modified, after which nothing is known.
+    stopped: bool,
+}
+
+impl<'a> NarrowVisitor<'a> {
+    fn binds_scrutinee(&self, pat: &syn::Pat) -> bool {
+        struct BindingVisitor<'a>(&'a syn::Ident, bool);
+
+        impl<'a, 'ast> Visit<'ast> for BindingVisitor<'a> {
+            fn visit_pat_ident(&mut self, i: &'ast syn::PatIdent) {
+                self.1 |= i.ident == *self.0;
+                syn::visit::visit_pat_ident(self, i);
+            }
+        }
+
+        let mut visitor = BindingVisitor(self.scrutinee, false);
+        visitor.visit_pat(pat);
+        visitor.1
+    }
+
+    fn mentions_scrutinee(&self, tokens: TokenStream) -> bool {
+        tokens.into_iter().any(|token| match token {
+            TokenTree::Ident(ident) => ident == *self.scrutinee,
+            TokenTree::Group(group) => self.mentions_scrutinee(group.stream()),
+            _ => false,
+        })
+    }
+
+    fn is_scrutinee(&self, expr: &syn::Expr) -> bool {
+        scrutinee_ident(expr) == Some(self.scrutinee)
+    }
+
+    /// Returns whether evaluating `expr` may modify the variable, not counting the
+    /// subexpressions of `expr`.
+    fn may_modify(&self, expr: &syn::Expr) -> bool {
+        match expr {
+            syn::Expr::Assign(syn::ExprAssign { left, .. })
+            | syn::Expr::AssignOp(syn::ExprAssignOp { left, .. }) => {
+                self.mentions_scrutinee(left.to_token_stream())
+            }
+            syn::Expr::Reference(syn::ExprReference {
+                mutability: Some(_),
+                expr,
+                ..
+            }) => self.mentions_scrutinee(expr.to_token_stream()),
+            syn::Expr::MethodCall(expr_method_call) => {
+                self.is_scrutinee(&expr_method_call.receiver)
+            }
+
+            // `ref mut` bindings can modify the variable that is matched
+            syn::Expr::Match(expr_match) => {
+                self.mentions_scrutinee(expr_match.expr.to_token_stream())
+                    && expr_match.arms.iter().any(|arm| has_ref_mut(&arm.pat))
+            }
+            syn::Expr::Let(expr_let) => {
+                self.mentions_scrutinee(expr_let.expr.to_token_stream())
+                    && has_ref_mut(&expr_let.pat)
+            }
+
+            // the variable can be modified in ways that cannot be seen here
+            syn::Expr::Macro(expr_macro) => self.mentions_scrutinee(expr_macro.mac.tokens.clone()),
+            syn::Expr::Closure(_) | syn::Expr::Async(_) => {
+                self.mentions_scrutinee(expr.to_token_stream())
+            }
+
+            // a later iteration sees the modifications of an earlier iteration
+            syn::Expr::ForLoop(_) | syn::Expr::Loop(_) | syn::Expr::While(_) => {
+                let mut visitor = NarrowVisitor {
+                    scrutinee: self.scrutinee,
+                    knowledge: self.knowledge,
+                    stopped: false,
+                };
+                syn::visit_mut::visit_expr_mut(&mut visitor, &mut expr.clone());
+                visitor.stopped
+            }
+
+            _ => false,
+        }
+    }
+}
+
+impl<'a> VisitMut for NarrowVisitor<'a> {
+    fn visit_expr_mut(&mut self, i: &mut syn::Expr) {
+        if self.stopped {
+            return;
+        }
+
+        if self.may_modify(i) {
+            self.stopped = true;
+            return;
+        }
+
+        match i {
+            syn::Expr::Match(expr_match) if self.is_scrutinee(&expr_match.expr) => {
+                self.knowledge.narrow_arms(expr_match);
+                syn::visit_mut::visit_expr_match_mut(self, expr_match);
+            }
+
+            syn::Expr::If(expr_if) => {
+                self.visit_expr_mut(&mut expr_if.cond);
+
+                let shadowed = match &*expr_if.cond {
+                    syn::Expr::Let(expr_let) => self.binds_scrutinee(&expr_let.pat),
+                    _ => false,
+                };
+                if !shadowed {
+                    self.visit_block_mut(&mut expr_if.then_branch);
+                }
+
+                if let Some((_, else_branch)) = &mut expr_if.else_branch {
+                    self.visit_expr_mut(else_branch);
+                }
+            }
+
+            _ => syn::visit_mut::visit_expr_mut(self, i),
+        }
+    }
+
+    fn visit_arm_mut(&mut self, i: &mut syn::Arm) {
+        if !self.binds_scrutinee(&i.pat) {
+            syn::visit_mut::visit_arm_mut(self, i);
+        }
+    }
+
+    fn visit_block_mut(&mut self, i: &mut syn::Block) {
+        for stmt in &mut i.stmts {
+            if self.stopped {
+                return;
+            }
+
+            if let syn::Stmt::Local(syn::Local {
+                pat,
+                init: Some((_, init)),
+                ..
+            }) = stmt
+            {
+                if has_ref_mut(pat) && self.mentions_scrutinee(init.to_token_stream()) {
+                    self.stopped = true;
+                    return;
+                }
+            }
+
+            self.visit_stmt_mut(stmt);
+
+            // the rest of the block refers to another variable, but the code after the
+            // block refers to the same variable again
+            if let syn::Stmt::Local(local) = stmt {
+                if self.binds_scrutinee(&local.pat) {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn visit_item_mut(&mut self, i: &mut syn::Item) {
+        // statement macros with braces are parsed as items
+        if let syn::Item::Macro(item_macro) = i {
+            if self.mentions_scrutinee(item_macro.mac.tokens.clone()) {
+                self.stopped = true;
+            }
+        }
+    }
+}
+
+fn has_ref_mut(pat: &syn::Pat) -> bool {
+    struct RefMutVisitor(bool);
+
+    impl<'ast> Visit<'ast> for RefMutVisitor {
+        fn visit_pat_ident(&mut self, i: &'ast syn::PatIdent) {
+            self.0 |= i.by_ref.is_some() && i.mutability.is_some();
+            syn::visit::visit_pat_ident(self, i);
+        }
+    }
+
+    let mut visitor = RefMutVisitor(false);
+    visitor.visit_pat(pat);
+    visitor.0
+}
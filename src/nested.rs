@@ -0,0 +1,74 @@
+//! Support for `cain!` blocks inside other `cain!` blocks.
+//!
+//! A nested `cain!` invocation is not left for the compiler to expand on its own, since its
+//! branch points would then be hidden from the enclosing block. Instead, its statements are
+//! transformed together with the enclosing block, with its own options and with the paths
+//! that lead to it. If the nested block is the initializer of a `let` statement, its tail
+//! expression becomes the initializer, so that its branch points fork the continuation of
+//! the enclosing block, and the number of paths is counted across both blocks.
+
+use syn::parse::{ParseStream, Parser};
+
+use crate::options::Options;
+use crate::util::path_is;
+
+/// Returns whether a macro is `cain!`.
+pub fn is_cain_macro(mac: &syn::Macro) -> bool {
+    (mac.path.leading_colon.is_none() && path_is(&mac.path, &["cain"]))
+        || path_is(&mac.path, &["cain", "cain"])
+}
+
+/// Returns the `cain!` macro that is the initializer of a `let` statement, if there is one.
+pub fn local_macro(local: &syn::Local) -> Option<&syn::Macro> {
+    match local.init.as_ref().map(|(_, init)| &**init) {
+        Some(syn::Expr::Macro(expr_macro)) if is_cain_macro(&expr_macro.mac) => {
+            Some(&expr_macro.mac)
+        }
+        _ => None,
+    }
+}
+
+/// Parse the options and the statements of a nested `cain!` block, with the options of the
+/// enclosing block as the defaults.
+pub fn parse(
+    mac: &syn::Macro,
+    defaults: &Options,
+    track_paths: bool,
+) -> syn::Result<(Options, Vec<syn::Stmt>)> {
+    let parser = |input: ParseStream| {
+        let options = Options::parse(input, defaults)?;
+        let stmts = syn::Block::parse_within(input)?;
+        Ok((options, stmts))
+    };
+    let (options, stmts) = parser.parse2(mac.tokens.clone())?;
+
+    // the paths are only resolved if the outermost block tracks them
+    if options.path_hook.is_some() && !track_paths {
+        return Err(syn::Error::new_spanned(
+            mac,
+            "cain! does not support setting `path_hook` in a nested `cain!` block only",
+        ));
+    }
+
+    Ok((options, stmts))
+}
+
+/// Turn `cain! { ... }` statements, which are parsed as items, into expression statements.
+pub fn unwrap_stmt_macros(stmts: &mut [syn::Stmt]) {
+    for stmt in stmts {
+        if let syn::Stmt::Item(syn::Item::Macro(item_macro)) = stmt {
+            if item_macro.ident.is_some() || !is_cain_macro(&item_macro.mac) {
+                continue;
+            }
+
+            let expr = syn::Expr::Macro(syn::ExprMacro {
+                attrs: item_macro.attrs.clone(),
+                mac: item_macro.mac.clone(),
+            });
+            *stmt = match item_macro.semi_token {
+                Some(semi) => syn::Stmt::Semi(expr, semi),
+                None => syn::Stmt::Expr(expr),
+            };
+        }
+    }
+}
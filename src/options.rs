@@ -0,0 +1,601 @@
+use proc_macro2::Span;
+use syn::parse::ParseStream;
+
+/// Options for a single invocation of the `cain!` macro.
+#[derive(Clone, Default)]
+pub struct Options {
+    /// How branching `let` statements fork the rest of the block.
+    pub strategy: Strategy,
+
+    /// The trait object reference type that branching `let` statements are coerced to
+    /// when using dynamic dispatch.
+    pub dispatch: Option<syn::TypeReference>,
+
+    /// The maximum number of paths through a block.
+    pub max_paths: Option<usize>,
+
+    /// The strategy that the "hybrid" strategy uses beyond `max_paths`, which is "dyn" if
+    /// it is not given.
+    pub fallback: Option<Strategy>,
+
+    /// Macros whose arguments are parsed as expressions and searched for branch points.
+    pub transparent_macros: Vec<syn::Ident>,
+
+    /// Macros that `#[derive(cain::Dispatch)]` generated outside of the block, which fork
+    /// the rest of the block when they are used without a closure.
+    pub dispatch_macros: Vec<syn::Ident>,
+
+    /// A function that is called with the value of `cain::path!()` at the start of every
+    /// continuation.
+    pub path_hook: Option<syn::Path>,
+
+    /// Whether the generated code must not add any panicking paths of its own, such as
+    /// `unreachable!()` arms.
+    pub panic_free: bool,
+
+    /// Whether only `match` and `if` expressions that are marked with `#[cain::fork]` are
+    /// branch points.
+    pub explicit: bool,
+
+    /// Whether only a `match` or `if` expression that is the whole initializer of a `let`
+    /// statement or the tail expression of a block is a branch point.
+    pub shallow: bool,
+
+    /// The signature of the generic function that branching `let` statements share their
+    /// continuation through when using the "shared" strategy.
+    pub shared: Option<Shared>,
+}
+
+/// The `shared T: Trait -> Type;` header, which names the type parameter of a shared
+/// continuation, its bounds and the type of the rest of the block.
+#[derive(Clone)]
+pub struct Shared {
+    pub param: syn::Ident,
+    pub bounds: Vec<syn::TypeParamBound>,
+    pub output: syn::ReturnType,
+}
+
+/// How branching `let` statements fork the rest of the block.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Strategy {
+    /// Duplicate the rest of the block into each arm. Exceeding `max_paths` is an error.
+    Duplicate,
+
+    /// Coerce the value of each arm to the dispatch type, and emit the rest of the block
+    /// once.
+    Dyn,
+
+    /// Duplicate the rest of the block as long as the number of paths stays within
+    /// `max_paths`, and use the fallback strategy beyond it.
+    Hybrid,
+
+    /// Wrap the value of each arm in its own variant of a local enum that implements the
+    /// trait of the dispatch type, and emit the rest of the block once.
+    Enum,
+
+    /// Emit the rest of the block once, as a local generic function that each arm calls
+    /// with its own value.
+    Shared,
+
+    /// Emit the rest of the block once, as a local `macro_rules!` macro that each arm
+    /// invokes with its own value.
+    Macro,
+}
+
+// `#[default]` on enum variants is not supported by the MSRV
+#[allow(clippy::derivable_impls)]
+impl Default for Strategy {
+    fn default() -> Strategy {
+        Strategy::Duplicate
+    }
+}
+
+impl Strategy {
+    const NAMES: &'static [(&'static str, Strategy)] = &[
+        ("duplicate", Strategy::Duplicate),
+        ("dyn", Strategy::Dyn),
+        ("hybrid", Strategy::Hybrid),
+        ("enum", Strategy::Enum),
+        ("shared", Strategy::Shared),
+        ("macro", Strategy::Macro),
+    ];
+
+    fn from_name(value: &str) -> Result<Strategy, String> {
+        Strategy::NAMES
+            .iter()
+            .find(|(name, _)| *name == value)
+            .map(|(_, strategy)| *strategy)
+            .ok_or_else(|| {
+                let names = Strategy::NAMES
+                    .iter()
+                    .map(|(name, _)| format!("\"{}\"", name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                format!("unknown strategy \"{}\", expected one of {}", value, names)
+            })
+    }
+
+    fn parse_lit(lit: &syn::LitStr) -> syn::Result<Strategy> {
+        Strategy::from_name(&lit.value()).map_err(|message| syn::Error::new_spanned(lit, message))
+    }
+
+    /// The strategy that is used when none is given explicitly.
+    fn infer(options: &Options) -> Strategy {
+        match (&options.dispatch, options.max_paths) {
+            (None, _) if options.shared.is_some() => Strategy::Shared,
+            (None, _) => Strategy::Duplicate,
+            (Some(_), None) => Strategy::Dyn,
+            (Some(_), Some(_)) => Strategy::Hybrid,
+        }
+    }
+}
+
+/// The options that were given explicitly in a `cain!` block, rather than taken from the
+/// defaults.
+#[derive(Default)]
+struct Explicit {
+    strategy: Option<(Strategy, Span)>,
+    dispatch: bool,
+    shared: bool,
+
+    /// The keys of the `#![cain(...)]` attributes, which may each be given only once.
+    keys: Vec<syn::Ident>,
+}
+
+impl Options {
+    const KEYS: &'static [&'static str] = &[
+        "strategy",
+        "dispatch",
+        "max_paths",
+        "fallback",
+        "transparent_macros",
+        "dispatch_macros",
+        "path_hook",
+        "panic_free",
+        "explicit",
+        "shallow",
+    ];
+
+    /// Parse the options at the start of the macro input: any number of `#![cain(...)]`
+    /// inner attributes, followed by an optional `dyn Trait;`,
+    /// `dyn Trait, max_paths = N;` or `shared T: Trait -> Type;` header. Options that are not given are taken from
+    /// `defaults`.
+    pub fn parse(input: ParseStream, defaults: &Options) -> syn::Result<Options> {
+        let mut options = defaults.clone();
+        let mut explicit = Explicit::default();
+
+        for attr in input.call(syn::Attribute::parse_inner)? {
+            if !attr.path.is_ident("cain") {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "cain! only supports `#![cain(...)]` inner attributes",
+                ));
+            }
+
+            options.parse_attr(&attr, &mut explicit)?;
+        }
+
+        options.parse_header(input, &mut explicit)?;
+
+        options.strategy = match explicit.strategy {
+            Some((strategy, _)) => strategy,
+            None if explicit.dispatch || explicit.shared => Strategy::infer(&options),
+            None => defaults.strategy,
+        };
+
+        let span = match explicit.strategy {
+            Some((_, span)) => span,
+            None => Span::call_site(),
+        };
+        options
+            .validate()
+            .map_err(|message| syn::Error::new(span, message))?;
+
+        Ok(options)
+    }
+
+    /// Load the defaults from the `[package.metadata.cain]` table in the manifest of the
+    /// crate that invokes the macro.
+    pub fn load_defaults() -> syn::Result<Options> {
+        let manifest_dir = match std::env::var_os("CARGO_MANIFEST_DIR") {
+            Some(manifest_dir) => manifest_dir,
+            None => return Ok(Options::default()),
+        };
+        let path = std::path::Path::new(&manifest_dir).join("Cargo.toml");
+
+        let options = match std::fs::read_to_string(&path) {
+            Ok(manifest) => Options::from_manifest(&manifest),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Options::default()),
+            Err(err) => Err(err.to_string()),
+        };
+
+        options.map_err(|message| {
+            syn::Error::new(
+                Span::call_site(),
+                format!("invalid cain! defaults in {}: {}", path.display(), message),
+            )
+        })
+    }
+
+    /// Read the defaults from the `[package.metadata.cain]` table of a manifest.
+    pub fn from_manifest(manifest: &str) -> Result<Options, String> {
+        let manifest = manifest
+            .parse::<toml::Value>()
+            .map_err(|err| err.to_string())?;
+
+        let table = match manifest
+            .get("package")
+            .and_then(|package| package.get("metadata"))
+            .and_then(|metadata| metadata.get("cain"))
+        {
+            Some(toml::Value::Table(table)) => table,
+            Some(_) => return Err("`package.metadata.cain` must be a table".to_string()),
+            None => return Ok(Options::default()),
+        };
+
+        let mut options = Options::default();
+        let mut strategy = None;
+
+        for (key, value) in table {
+            // option names are written with dashes in manifests
+            let key = key.replace('-', "_");
+
+            match (key.as_str(), value) {
+                ("strategy", toml::Value::String(value)) => {
+                    strategy = Some(Strategy::from_name(value)?);
+                }
+                ("dispatch", toml::Value::String(value)) => {
+                    let ty = syn::parse_str(value).map_err(|err| err.to_string())?;
+                    options.dispatch =
+                        Some(parse_dispatch_type(ty).map_err(|err| err.to_string())?);
+                }
+                ("max_paths", toml::Value::Integer(value)) if *value >= 0 => {
+                    options.max_paths = Some(*value as usize);
+                }
+                ("fallback", toml::Value::String(value)) => {
+                    options.fallback = Some(Strategy::from_name(value)?);
+                }
+                ("transparent_macros", toml::Value::Array(values)) => {
+                    options.transparent_macros = manifest_idents(&key, values)?;
+                }
+                ("dispatch_macros", toml::Value::Array(values)) => {
+                    options.dispatch_macros = manifest_idents(&key, values)?;
+                }
+                ("path_hook", toml::Value::String(value)) => {
+                    options.path_hook = Some(syn::parse_str(value).map_err(|err| err.to_string())?);
+                }
+                ("panic_free", toml::Value::Boolean(value)) => {
+                    options.panic_free = *value;
+                }
+                ("explicit", toml::Value::Boolean(value)) => {
+                    options.explicit = *value;
+                }
+                ("shallow", toml::Value::Boolean(value)) => {
+                    options.shallow = *value;
+                }
+                ("strategy", _) | ("dispatch", _) | ("fallback", _) | ("path_hook", _) => {
+                    return Err(format!("expected `{}` to be a string", key))
+                }
+                ("max_paths", _) => {
+                    return Err("expected `max_paths` to be a non-negative integer".to_string())
+                }
+                ("transparent_macros", _) | ("dispatch_macros", _) => {
+                    return Err(format!("expected `{}` to be a list of names", key))
+                }
+                ("panic_free", _) | ("explicit", _) | ("shallow", _) => {
+                    return Err(format!("expected `{}` to be a boolean", key))
+                }
+                _ => {
+                    return Err(format!(
+                        "unknown option `{}`, expected one of {}",
+                        key,
+                        Options::key_list()
+                    ))
+                }
+            }
+        }
+
+        options.strategy = strategy.unwrap_or_else(|| Strategy::infer(&options));
+        options.validate()?;
+
+        Ok(options)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.strategy == Strategy::Shared && self.shared.is_none() {
+            return Err("the \"shared\" strategy requires a `shared T: Trait;` header".to_string());
+        }
+
+        if matches!(
+            self.strategy,
+            Strategy::Dyn | Strategy::Hybrid | Strategy::Enum
+        ) && self.dispatch.is_none()
+        {
+            return Err(
+                "this strategy requires a `dyn Trait;` header or a `dispatch = \"dyn Trait\"` option"
+                    .to_string(),
+            );
+        }
+
+        if self.strategy == Strategy::Hybrid && self.max_paths.is_none() {
+            return Err("the \"hybrid\" strategy requires a `max_paths` option".to_string());
+        }
+
+        match self.fallback {
+            Some(Strategy::Dyn | Strategy::Enum) | None => (),
+            Some(_) => return Err(
+                "the \"hybrid\" strategy can only fall back to the \"dyn\" or \"enum\" strategy"
+                    .to_string(),
+            ),
+        }
+
+        Ok(())
+    }
+
+    fn key_list() -> String {
+        Options::KEYS
+            .iter()
+            .map(|key| format!("`{}`", key))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Returns `true` if the arguments of a macro invocation should be searched for
+    /// branch points.
+    pub fn is_transparent(&self, mac: &syn::Macro) -> bool {
+        match mac.path.segments.last() {
+            Some(segment) => self.transparent_macros.contains(&segment.ident),
+            None => false,
+        }
+    }
+
+    fn parse_attr(&mut self, attr: &syn::Attribute, explicit: &mut Explicit) -> syn::Result<()> {
+        let list = match attr.parse_meta()? {
+            syn::Meta::List(list) => list,
+            meta => {
+                return Err(syn::Error::new_spanned(
+                    meta,
+                    "expected a list of options, as in `#![cain(max_paths = 16)]`",
+                ))
+            }
+        };
+
+        for nested in list.nested {
+            let meta = match nested {
+                syn::NestedMeta::Meta(meta) => meta,
+                syn::NestedMeta::Lit(lit) => {
+                    return Err(syn::Error::new_spanned(lit, "expected an option name"))
+                }
+            };
+
+            let key = match meta.path().get_ident() {
+                Some(key) if Options::KEYS.iter().any(|name| key == name) => key.clone(),
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        meta.path(),
+                        format!(
+                            "unknown cain! option, expected one of {}",
+                            Options::key_list()
+                        ),
+                    ));
+                }
+            };
+
+            if explicit.keys.contains(&key) {
+                return Err(syn::Error::new_spanned(
+                    &key,
+                    format!("duplicate cain! option `{}`", key),
+                ));
+            }
+            explicit.keys.push(key.clone());
+
+            if key == "strategy" {
+                let lit = expect_str(&key, &meta)?;
+                explicit.strategy = Some((Strategy::parse_lit(&lit)?, lit.span()));
+            } else if key == "dispatch" {
+                let lit = expect_str(&key, &meta)?;
+                self.dispatch = Some(parse_dispatch_type(lit.parse()?)?);
+                explicit.dispatch = true;
+            } else if key == "max_paths" {
+                self.max_paths = Some(expect_int(&key, &meta)?);
+            } else if key == "fallback" {
+                self.fallback = Some(Strategy::parse_lit(&expect_str(&key, &meta)?)?);
+            } else if key == "transparent_macros" {
+                self.transparent_macros = expect_idents(&key, &meta)?;
+            } else if key == "dispatch_macros" {
+                self.dispatch_macros = expect_idents(&key, &meta)?;
+            } else if key == "path_hook" {
+                self.path_hook = Some(expect_str(&key, &meta)?.parse()?);
+            } else if key == "panic_free" {
+                self.panic_free = expect_bool(&key, &meta)?;
+            } else if key == "explicit" {
+                self.explicit = expect_bool(&key, &meta)?;
+            } else if key == "shallow" {
+                self.shallow = expect_bool(&key, &meta)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_header(&mut self, input: ParseStream, explicit: &mut Explicit) -> syn::Result<()> {
+        if input.peek(syn::Ident) && input.peek2(syn::Ident) {
+            let keyword = input.fork().parse::<syn::Ident>()?;
+            if keyword == "shared" {
+                return self.parse_shared_header(input, explicit);
+            }
+        }
+
+        let has_header = input.peek(syn::Token![dyn])
+            || input.peek(syn::Token![&]) && input.peek2(syn::Token![dyn])
+            || input.peek(syn::Token![&])
+                && input.peek2(syn::Token![mut])
+                && input.peek3(syn::Token![dyn]);
+
+        if !has_header {
+            return Ok(());
+        }
+
+        let ty: syn::Type = input.parse()?;
+        if explicit.dispatch {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "the dispatch type is already set by a `dispatch` option",
+            ));
+        }
+        self.dispatch = Some(parse_dispatch_type(ty)?);
+        explicit.dispatch = true;
+
+        if input.peek(syn::Token![,]) {
+            input.parse::<syn::Token![,]>()?;
+
+            let key = input.parse::<syn::Ident>()?;
+            if key != "max_paths" {
+                return Err(syn::Error::new_spanned(key, "expected `max_paths`"));
+            }
+            if explicit.keys.iter().any(|seen| seen == "max_paths") {
+                return Err(syn::Error::new_spanned(
+                    key,
+                    "duplicate cain! option `max_paths`",
+                ));
+            }
+
+            input.parse::<syn::Token![=]>()?;
+            self.max_paths = Some(input.parse::<syn::LitInt>()?.base10_parse()?);
+        }
+
+        input.parse::<syn::Token![;]>()?;
+
+        Ok(())
+    }
+
+    /// Parse a `shared T: Trait -> Type;` header.
+    fn parse_shared_header(
+        &mut self,
+        input: ParseStream,
+        explicit: &mut Explicit,
+    ) -> syn::Result<()> {
+        let keyword = input.parse::<syn::Ident>()?;
+        if explicit.dispatch {
+            return Err(syn::Error::new_spanned(
+                keyword,
+                "a `shared` header cannot be combined with a dispatch type",
+            ));
+        }
+
+        let param = input.parse::<syn::Ident>()?;
+        input.parse::<syn::Token![:]>()?;
+
+        let mut bounds = vec![input.parse::<syn::TypeParamBound>()?];
+        while input.peek(syn::Token![+]) {
+            input.parse::<syn::Token![+]>()?;
+            bounds.push(input.parse()?);
+        }
+
+        let output = input.parse()?;
+        input.parse::<syn::Token![;]>()?;
+
+        // a dispatch type from the defaults would take precedence
+        self.dispatch = None;
+        self.shared = Some(Shared {
+            param,
+            bounds,
+            output,
+        });
+        explicit.shared = true;
+
+        Ok(())
+    }
+}
+
+fn expect_str(key: &syn::Ident, meta: &syn::Meta) -> syn::Result<syn::LitStr> {
+    match meta {
+        syn::Meta::NameValue(syn::MetaNameValue {
+            lit: syn::Lit::Str(lit),
+            ..
+        }) => Ok(lit.clone()),
+        _ => Err(syn::Error::new_spanned(
+            meta,
+            format!("expected a string, as in `{} = \"...\"`", key),
+        )),
+    }
+}
+
+fn expect_int(key: &syn::Ident, meta: &syn::Meta) -> syn::Result<usize> {
+    match meta {
+        syn::Meta::NameValue(syn::MetaNameValue {
+            lit: syn::Lit::Int(lit),
+            ..
+        }) => lit.base10_parse(),
+        _ => Err(syn::Error::new_spanned(
+            meta,
+            format!("expected an integer, as in `{} = 16`", key),
+        )),
+    }
+}
+
+/// A flag is either given by its name alone, or set to `true` or `false`.
+fn expect_bool(key: &syn::Ident, meta: &syn::Meta) -> syn::Result<bool> {
+    match meta {
+        syn::Meta::Path(_) => Ok(true),
+        syn::Meta::NameValue(syn::MetaNameValue {
+            lit: syn::Lit::Bool(lit),
+            ..
+        }) => Ok(lit.value),
+        _ => Err(syn::Error::new_spanned(
+            meta,
+            format!("expected a boolean, as in `{}` or `{} = false`", key, key),
+        )),
+    }
+}
+
+fn expect_idents(key: &syn::Ident, meta: &syn::Meta) -> syn::Result<Vec<syn::Ident>> {
+    let error = || {
+        syn::Error::new_spanned(
+            meta,
+            format!("expected a list of names, as in `{}(name, ...)`", key),
+        )
+    };
+
+    match meta {
+        syn::Meta::List(list) => list
+            .nested
+            .iter()
+            .map(|nested| match nested {
+                syn::NestedMeta::Meta(syn::Meta::Path(path)) => {
+                    path.get_ident().cloned().ok_or_else(error)
+                }
+                _ => Err(error()),
+            })
+            .collect(),
+        _ => Err(error()),
+    }
+}
+
+/// Parse a list of names in a manifest.
+fn manifest_idents(key: &str, values: &[toml::Value]) -> Result<Vec<syn::Ident>, String> {
+    values
+        .iter()
+        .map(|value| match value {
+            toml::Value::String(value) => syn::parse_str(value).map_err(|err| err.to_string()),
+            _ => Err(format!("expected `{}` to be a list of names", key)),
+        })
+        .collect()
+}
+
+/// Turn a `dyn Trait`, `&dyn Trait` or `&mut dyn Trait` type into a reference type.
+fn parse_dispatch_type(ty: syn::Type) -> syn::Result<syn::TypeReference> {
+    match ty {
+        syn::Type::TraitObject(_) => Ok(syn::parse_quote! { &#ty }),
+
+        syn::Type::Reference(type_ref) if matches!(*type_ref.elem, syn::Type::TraitObject(_)) => {
+            Ok(type_ref)
+        }
+
+        _ => Err(syn::Error::new_spanned(
+            ty,
+            "expected `dyn Trait`, `&dyn Trait` or `&mut dyn Trait`",
+        )),
+    }
+}
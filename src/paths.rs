@@ -0,0 +1,245 @@
+//! Support for `cain::path!()`, which names the arms that were taken to reach it.
+//!
+//! Before parsing, every `cain::path!()` invocation is rewritten into a marker macro.
+//! Whenever an arm gets its own copy of the continuation, the label of the arm is added to
+//! all markers in the arm, and after the transformation every marker is replaced by a
+//! string literal with its labels. Markers are also found in the tokens of other macros,
+//! so that `cain::path!()` can be used in `println!` and similar macros.
+
+use proc_macro2::{Group, Literal, Spacing, TokenStream, TokenTree};
+use quote::{format_ident, quote, ToTokens};
+use syn::visit_mut::VisitMut;
+
+const MARKER: &str = "__cain_path";
+
+/// Rewrite all `cain::path!()` invocations in `input` into markers, and return whether
+/// there were any.
+pub fn desugar(input: TokenStream) -> (TokenStream, bool) {
+    let tokens = input.into_iter().collect::<Vec<_>>();
+
+    let mut found = false;
+    let mut output = Vec::with_capacity(tokens.len());
+    let mut index = 0;
+    while index < tokens.len() {
+        if let Some(len) = path_macro_len(&tokens[..index], &tokens[index..]) {
+            output.extend(marker(TokenStream::new()));
+            found = true;
+            index += len;
+            continue;
+        }
+
+        match &tokens[index] {
+            TokenTree::Group(group) => {
+                let (stream, group_found) = desugar(group.stream());
+                let mut new_group = Group::new(group.delimiter(), stream);
+                new_group.set_span(group.span());
+                output.push(TokenTree::Group(new_group));
+                found |= group_found;
+            }
+            token => output.push(token.clone()),
+        }
+        index += 1;
+    }
+
+    (output.into_iter().collect(), found)
+}
+
+/// Returns the number of tokens of the `cain::path!()` invocation at the start of `tokens`,
+/// if there is one.
+fn path_macro_len(prefix: &[TokenTree], tokens: &[TokenTree]) -> Option<usize> {
+    let path_len = match tokens {
+        [TokenTree::Punct(colon1), TokenTree::Punct(colon2), TokenTree::Ident(krate), TokenTree::Punct(colon3), TokenTree::Punct(colon4), TokenTree::Ident(name), ..]
+            if is_path_sep(colon1, colon2)
+                && is_path_sep(colon3, colon4)
+                && krate == "cain"
+                && name == "path" =>
+        {
+            6
+        }
+        // `other::cain::path!()` is not ours
+        [TokenTree::Ident(krate), TokenTree::Punct(colon1), TokenTree::Punct(colon2), TokenTree::Ident(name), ..]
+            if is_path_sep(colon1, colon2)
+                && krate == "cain"
+                && name == "path"
+                && !ends_with_path_sep(prefix) =>
+        {
+            4
+        }
+        _ => return None,
+    };
+
+    match &tokens[path_len..] {
+        [TokenTree::Punct(bang), TokenTree::Group(group), ..]
+            if bang.as_char() == '!' && group.stream().is_empty() =>
+        {
+            Some(path_len + 2)
+        }
+        _ => None,
+    }
+}
+
+fn is_path_sep(colon1: &proc_macro2::Punct, colon2: &proc_macro2::Punct) -> bool {
+    colon1.as_char() == ':' && colon1.spacing() == Spacing::Joint && colon2.as_char() == ':'
+}
+
+fn ends_with_path_sep(prefix: &[TokenTree]) -> bool {
+    matches!(prefix, [.., TokenTree::Punct(colon1), TokenTree::Punct(colon2)] if is_path_sep(colon1, colon2))
+}
+
+fn marker(labels: TokenStream) -> TokenStream {
+    let marker = format_ident!("{}", MARKER);
+    quote! { #marker!(#labels) }
+}
+
+/// Add the label of an arm to the front of all markers in `expr`.
+pub fn label_expr(expr: &mut syn::Expr, label: &str) {
+    LabelVisitor { label }.visit_expr_mut(expr);
+}
+
+/// Add the label of an arm to the front of all markers in `block`.
+pub fn label_block(block: &mut syn::Block, label: &str) {
+    LabelVisitor { label }.visit_block_mut(block);
+}
+
+struct LabelVisitor<'a> {
+    label: &'a str,
+}
+
+impl<'a> LabelVisitor<'a> {
+    fn label_tokens(&self, tokens: TokenStream) -> TokenStream {
+        let tokens = tokens.into_iter().collect::<Vec<_>>();
+
+        let mut output = Vec::with_capacity(tokens.len());
+        for (index, token) in tokens.iter().enumerate() {
+            let new_token = match token {
+                TokenTree::Group(group) if is_marker_call(&tokens[..index]) => {
+                    let mut new_group = Group::new(group.delimiter(), self.prepend(group.stream()));
+                    new_group.set_span(group.span());
+                    TokenTree::Group(new_group)
+                }
+                TokenTree::Group(group) => {
+                    let mut new_group =
+                        Group::new(group.delimiter(), self.label_tokens(group.stream()));
+                    new_group.set_span(group.span());
+                    TokenTree::Group(new_group)
+                }
+                token => token.clone(),
+            };
+            output.push(new_token);
+        }
+
+        output.into_iter().collect()
+    }
+
+    fn prepend(&self, labels: TokenStream) -> TokenStream {
+        let label = Literal::string(self.label);
+        if labels.is_empty() {
+            quote! { #label }
+        } else {
+            quote! { #label, #labels }
+        }
+    }
+}
+
+impl<'a> VisitMut for LabelVisitor<'a> {
+    fn visit_macro_mut(&mut self, i: &mut syn::Macro) {
+        let tokens = std::mem::take(&mut i.tokens);
+        i.tokens = if i.path.is_ident(MARKER) {
+            self.prepend(tokens)
+        } else {
+            self.label_tokens(tokens)
+        };
+    }
+}
+
+fn is_marker_call(prefix: &[TokenTree]) -> bool {
+    matches!(
+        prefix,
+        [.., TokenTree::Ident(ident), TokenTree::Punct(bang)] if ident == MARKER && bang.as_char() == '!'
+    )
+}
+
+/// Replace all markers in the output of the macro with string literals.
+pub fn resolve(output: TokenStream) -> TokenStream {
+    let tokens = output.into_iter().collect::<Vec<_>>();
+
+    let mut output = Vec::with_capacity(tokens.len());
+    let mut index = 0;
+    while index < tokens.len() {
+        if let [TokenTree::Ident(ident), TokenTree::Punct(bang), TokenTree::Group(group), ..] =
+            &tokens[index..]
+        {
+            if ident == MARKER && bang.as_char() == '!' {
+                let labels = group
+                    .stream()
+                    .into_iter()
+                    .filter_map(|token| match token {
+                        TokenTree::Literal(literal) => {
+                            syn::parse2::<syn::LitStr>(literal.into_token_stream()).ok()
+                        }
+                        _ => None,
+                    })
+                    .map(|label| label.value())
+                    .collect::<Vec<_>>();
+
+                let mut literal = Literal::string(&labels.join(", "));
+                literal.set_span(ident.span());
+                output.push(TokenTree::Literal(literal));
+                index += 3;
+                continue;
+            }
+        }
+
+        match &tokens[index] {
+            TokenTree::Group(group) => {
+                let mut new_group = Group::new(group.delimiter(), resolve(group.stream()));
+                new_group.set_span(group.span());
+                output.push(TokenTree::Group(new_group));
+            }
+            token => output.push(token.clone()),
+        }
+        index += 1;
+    }
+
+    output.into_iter().collect()
+}
+
+/// Call `hook` with the path to the start of a continuation.
+pub fn call_hook(hook: &syn::Path, expr: syn::Expr) -> syn::Expr {
+    let marker = marker(TokenStream::new());
+    syn::parse_quote! {
+        {
+            #hook(#marker);
+            #expr
+        }
+    }
+}
+
+/// The label of the arm of a `match` expression.
+pub fn match_label(scrutinee: &syn::Expr, pat: &syn::Pat) -> String {
+    format!(
+        "{} => {}",
+        scrutinee.to_token_stream(),
+        pat.to_token_stream()
+    )
+}
+
+/// The label of a branch of an `if` expression.
+pub fn if_label(cond: &syn::Expr, then: bool) -> String {
+    format!("{} => {}", cond.to_token_stream(), then)
+}
+
+/// The label of the `n`th exit point of a `loop` or labeled block.
+pub fn break_label(target: &syn::Expr, n: usize) -> String {
+    let name = match target {
+        syn::Expr::Loop(syn::ExprLoop {
+            label: Some(label), ..
+        })
+        | syn::Expr::Block(syn::ExprBlock {
+            label: Some(label), ..
+        }) => label.name.to_string(),
+        _ => "loop".to_string(),
+    };
+
+    format!("{} => break {}", name, n)
+}
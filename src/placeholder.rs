@@ -1,9 +1,11 @@
 use std::fmt::{self, Display};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use proc_macro2::TokenStream;
+use proc_macro2::{TokenStream, TokenTree};
 use quote::{format_ident, IdentFragment, ToTokens};
-use syn::{ext::IdentExt, visit_mut::VisitMut};
+use syn::{ext::IdentExt, visit::Visit, visit_mut::VisitMut};
+
+use crate::util::parse_macro_args;
 
 static NEXT_PLACEHOLDER_ID: AtomicUsize = AtomicUsize::new(0);
 
@@ -44,7 +46,7 @@ pub fn replace_with_placeholder(expr: &mut syn::Expr) -> (PlaceholderId, syn::Ex
     (id, expr)
 }
 
-fn get_placeholder_id(path: &syn::Path) -> Option<PlaceholderId> {
+pub fn get_placeholder_id(path: &syn::Path) -> Option<PlaceholderId> {
     if path.leading_colon.is_none() && path.segments.len() == 1 {
         let segment = &path.segments[0];
 
@@ -62,16 +64,40 @@ fn get_placeholder_id(path: &syn::Path) -> Option<PlaceholderId> {
     None
 }
 
-fn replace_expr(
+/// Returns whether a placeholder occurs in `expr`.
+pub fn contains_placeholder_id(expr: &syn::Expr, placeholder_id: PlaceholderId) -> bool {
+    let mut count = CountVisitor {
+        placeholder_id,
+        count: 0,
+    };
+    count.visit_expr(expr);
+    count.count > 0
+}
+
+pub fn replace_expr(
     mut expr: syn::Expr,
     placeholder_id: PlaceholderId,
     target: syn::Expr,
 ) -> syn::Result<syn::Expr> {
+    // the target is only cloned for all but the last occurrence of the placeholder
+    let mut count = CountVisitor {
+        placeholder_id,
+        count: 0,
+    };
+    count.visit_expr(&expr);
+
+    let mut remaining = count.count;
+    let mut target = Some(target);
     let mut visitor = ReplaceVisitor::new(|id| {
-        if id == placeholder_id {
-            Some(target.clone())
+        if id != placeholder_id {
+            return None;
+        }
+
+        remaining = remaining.saturating_sub(1);
+        if remaining == 0 {
+            target.take()
         } else {
-            None
+            target.clone()
         }
     });
 
@@ -84,7 +110,8 @@ pub fn wrap_placeholder_expr_mut(
     placeholder_id: PlaceholderId,
     outer: syn::Expr,
 ) -> syn::Result<()> {
-    *expr = replace_expr(outer, placeholder_id, expr.clone())?;
+    let target = std::mem::replace(expr, syn::Expr::Verbatim(TokenStream::new()));
+    *expr = replace_expr(outer, placeholder_id, target)?;
     Ok(())
 }
 
@@ -96,7 +123,13 @@ pub fn wrap_placeholder_block_mut(
     let target = syn::Expr::Block(syn::ExprBlock {
         attrs: Vec::new(),
         label: None,
-        block: block.clone(),
+        block: std::mem::replace(
+            block,
+            syn::Block {
+                brace_token: Default::default(),
+                stmts: Vec::new(),
+            },
+        ),
     });
 
     let expr = replace_expr(outer, placeholder_id, target)?;
@@ -141,7 +174,7 @@ impl<F> ReplaceVisitor<F> {
 
 impl<F> VisitMut for ReplaceVisitor<F>
 where
-    F: Fn(PlaceholderId) -> Option<syn::Expr>,
+    F: FnMut(PlaceholderId) -> Option<syn::Expr>,
 {
     fn visit_expr_mut(&mut self, i: &mut syn::Expr) {
         match i {
@@ -170,4 +203,54 @@ where
             _ => syn::visit_mut::visit_expr_mut(self, i),
         }
     }
+
+    // placeholders end up in the arguments of transparent macros
+    fn visit_macro_mut(&mut self, i: &mut syn::Macro) {
+        if !contains_placeholder(i.tokens.clone()) {
+            return;
+        }
+
+        match parse_macro_args(i) {
+            Ok(mut args) => {
+                args.iter_mut().for_each(|arg| self.visit_expr_mut(arg));
+                i.tokens = args.into_token_stream();
+            }
+            Err(_) => self.fail(
+                &*i,
+                "internal macro error: cannot parse macro arguments with placeholders",
+            ),
+        }
+    }
+}
+
+/// Counts the occurrences of a placeholder that [`ReplaceVisitor`] replaces.
+struct CountVisitor {
+    placeholder_id: PlaceholderId,
+    count: usize,
+}
+
+impl<'ast> Visit<'ast> for CountVisitor {
+    fn visit_expr_path(&mut self, i: &'ast syn::ExprPath) {
+        if i.qself.is_none() && get_placeholder_id(&i.path) == Some(self.placeholder_id) {
+            self.count += 1;
+        }
+    }
+
+    fn visit_macro(&mut self, i: &'ast syn::Macro) {
+        if !contains_placeholder(i.tokens.clone()) {
+            return;
+        }
+
+        if let Ok(args) = parse_macro_args(i) {
+            args.iter().for_each(|arg| self.visit_expr(arg));
+        }
+    }
+}
+
+fn contains_placeholder(tokens: TokenStream) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(ident) => ident.to_string().starts_with("__cain_placeholder__"),
+        TokenTree::Group(group) => contains_placeholder(group.stream()),
+        _ => false,
+    })
 }
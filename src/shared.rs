@@ -0,0 +1,252 @@
+//! Support for sharing the continuation of branching `let` statements through a local
+//! generic function, with a `shared T: Trait -> Type;` header.
+//!
+//! The rest of the block is emitted once, as a function with a type parameter for the
+//! value of the `let` statement, and every arm calls it with its own value. The rest of
+//! the block is still compiled once for each type, but only written out once. A function
+//! cannot capture local variables, so the variables of the `cain!` block that the rest of
+//! the block uses are passed to it by move, with the types from their `let` statements.
+
+use std::collections::BTreeSet;
+
+use quote::quote;
+use syn::visit::Visit;
+use syn::visit_mut::VisitMut;
+
+use crate::dispatch;
+use crate::options::Shared;
+use crate::util::UsedIdents;
+
+/// A variable that is bound by a `let` statement in a `cain!` block.
+#[derive(Clone)]
+pub struct Binding {
+    pub ident: syn::Ident,
+    pub mutability: Option<syn::token::Mut>,
+
+    /// The declared type, if the statement binds a single identifier with a type annotation.
+    pub ty: Option<syn::Type>,
+}
+
+/// Returns the variables that a `let` statement binds.
+pub fn bindings(local: &syn::Local) -> Vec<Binding> {
+    if let syn::Pat::Type(pat_type) = &local.pat {
+        if let syn::Pat::Ident(pat_ident) = &*pat_type.pat {
+            let is_known = pat_ident.subpat.is_none()
+                && pat_ident.by_ref.is_none()
+                && !matches!(&*pat_type.ty, syn::Type::ImplTrait(_) | syn::Type::Infer(_));
+            if is_known {
+                return vec![Binding {
+                    ident: pat_ident.ident.clone(),
+                    mutability: pat_ident.mutability,
+                    ty: Some((*pat_type.ty).clone()),
+                }];
+            }
+        }
+    }
+
+    let mut visitor = BindingVisitor::default();
+    visitor.visit_pat(&local.pat);
+    visitor.bindings
+}
+
+/// Allow a `let mut` statement to leave its variable unmodified, since the variable may
+/// only be modified by a shared continuation that it is moved into.
+pub fn allow_unused_mut(local: &mut syn::Local) {
+    if let [Binding {
+        mutability: Some(_),
+        ty: Some(_),
+        ..
+    }] = &*bindings(local)
+    {
+        local.attrs.push(syn::parse_quote! { #[allow(unused_mut)] });
+    }
+}
+
+/// Returns the variables from `locals` that `rest` may use, except for `bound`, which is
+/// bound by the `let` statement itself.
+pub fn captures<'a>(
+    locals: &'a [Binding],
+    rest: &[syn::Stmt],
+    bound: &syn::Ident,
+) -> syn::Result<Vec<&'a Binding>> {
+    let mut used = UsedIdents::default();
+    rest.iter().for_each(|stmt| used.visit_stmt(stmt));
+
+    let mut seen = BTreeSet::new();
+    let mut captures = Vec::new();
+    for binding in locals.iter().rev() {
+        let name = binding.ident.to_string();
+        if binding.ident == *bound || !used.idents.contains(&name) || !seen.insert(name) {
+            continue;
+        }
+
+        if binding.ty.is_none() {
+            return Err(syn::Error::new_spanned(
+                &binding.ident,
+                format!(
+                    "cain! needs the type of `{}` to pass it to a shared continuation, add a type annotation to its `let` statement",
+                    binding.ident
+                ),
+            ));
+        }
+        captures.push(binding);
+    }
+
+    captures.reverse();
+    Ok(captures)
+}
+
+/// Returns an error if `rest` contains control flow that would leave the function that it
+/// is moved into, rather than the block.
+pub fn check_control_flow(rest: &[syn::Stmt]) -> syn::Result<()> {
+    let mut visitor = ControlFlowVisitor {
+        labels: Vec::new(),
+        loop_depth: 0,
+        error: None,
+    };
+    rest.iter().for_each(|stmt| visitor.visit_stmt(stmt));
+
+    match visitor.error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Returns the function that a shared continuation is moved into.
+pub fn function(
+    shared: &Shared,
+    name: &syn::Ident,
+    pat_ident: &syn::PatIdent,
+    captures: &[&Binding],
+    rest: Vec<syn::Stmt>,
+) -> syn::Item {
+    let Shared {
+        param,
+        bounds,
+        output,
+    } = shared;
+    let ident = &pat_ident.ident;
+    let mutability = &pat_ident.mutability;
+
+    // a variable that is shadowed before it is used is passed without being used
+    let captures = captures.iter().map(|binding| {
+        let ident = &binding.ident;
+        let mutability = &binding.mutability;
+        let ty = &binding.ty;
+        quote! {
+            #[allow(unused_mut, unused_variables)]
+            #mutability #ident: #ty
+        }
+    });
+
+    let mut function: syn::ItemFn = syn::parse_quote! {
+        fn #name<#param: #(#bounds)+*>(#mutability #ident: #param, #(#captures),*) #output {}
+    };
+    function.block.stmts = rest;
+    syn::Item::Fn(function)
+}
+
+/// Remove the braces around the values that are passed to the function `name`.
+pub fn unwrap_values(expr: &mut syn::Expr, name: &syn::Ident) {
+    UnwrapVisitor { name }.visit_expr_mut(expr);
+}
+
+struct UnwrapVisitor<'a> {
+    name: &'a syn::Ident,
+}
+
+impl<'a> VisitMut for UnwrapVisitor<'a> {
+    fn visit_expr_call_mut(&mut self, i: &mut syn::ExprCall) {
+        syn::visit_mut::visit_expr_call_mut(self, i);
+
+        let is_call =
+            matches!(&*i.func, syn::Expr::Path(expr_path) if expr_path.path.is_ident(self.name));
+        if let (true, Some(value)) = (is_call, i.args.first_mut()) {
+            dispatch::unwrap_block(value);
+        }
+    }
+}
+
+#[derive(Default)]
+struct BindingVisitor {
+    bindings: Vec<Binding>,
+}
+
+impl<'ast> Visit<'ast> for BindingVisitor {
+    fn visit_pat_ident(&mut self, i: &'ast syn::PatIdent) {
+        self.bindings.push(Binding {
+            ident: i.ident.clone(),
+            mutability: i.mutability,
+            ty: None,
+        });
+        syn::visit::visit_pat_ident(self, i);
+    }
+}
+
+struct ControlFlowVisitor {
+    /// The labels of the loops and blocks that enclose the visited expression.
+    labels: Vec<syn::Lifetime>,
+    loop_depth: usize,
+    error: Option<syn::Error>,
+}
+
+impl ControlFlowVisitor {
+    fn fail(&mut self, expr: &syn::Expr, what: &str) {
+        self.error.get_or_insert_with(|| {
+            syn::Error::new_spanned(
+                expr,
+                format!("cain! cannot share a continuation that contains {}", what),
+            )
+        });
+    }
+
+    fn leaves(&self, label: Option<&syn::Lifetime>) -> bool {
+        match label {
+            Some(label) => !self.labels.contains(label),
+            None => self.loop_depth == 0,
+        }
+    }
+
+    fn visit_target(&mut self, label: Option<&syn::Label>, is_loop: bool, i: &syn::Expr) {
+        self.labels.extend(label.map(|label| label.name.clone()));
+        self.loop_depth += is_loop as usize;
+        syn::visit::visit_expr(self, i);
+        self.loop_depth -= is_loop as usize;
+        if label.is_some() {
+            self.labels.pop();
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for ControlFlowVisitor {
+    fn visit_expr(&mut self, i: &'ast syn::Expr) {
+        match i {
+            syn::Expr::Return(_) => self.fail(i, "`return`"),
+            syn::Expr::Try(_) => self.fail(i, "`?`"),
+            syn::Expr::Await(_) => self.fail(i, "`.await`"),
+
+            syn::Expr::Break(syn::ExprBreak { label, .. })
+            | syn::Expr::Continue(syn::ExprContinue { label, .. })
+                if self.leaves(label.as_ref()) =>
+            {
+                self.fail(i, "`break` or `continue` out of it")
+            }
+
+            syn::Expr::Loop(syn::ExprLoop { label, .. })
+            | syn::Expr::While(syn::ExprWhile { label, .. })
+            | syn::Expr::ForLoop(syn::ExprForLoop { label, .. }) => {
+                self.visit_target(label.as_ref(), true, i)
+            }
+            syn::Expr::Block(syn::ExprBlock { label, .. }) => {
+                self.visit_target(label.as_ref(), false, i)
+            }
+
+            // control flow cannot leave closures and async blocks
+            syn::Expr::Closure(_) | syn::Expr::Async(_) => (),
+
+            _ => syn::visit::visit_expr(self, i),
+        }
+    }
+
+    fn visit_item(&mut self, _: &'ast syn::Item) {}
+}
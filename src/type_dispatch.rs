@@ -0,0 +1,103 @@
+//! Support for `cain::type_dispatch!`, which maps runtime tags to types.
+
+use proc_macro2::TokenStream;
+use quote::ToTokens;
+use syn::parse::ParseStream;
+
+use crate::dispatch_arms::{self, Dispatch, Kind, Values};
+use crate::options::Options;
+
+pub fn type_dispatch(input: TokenStream) -> syn::Result<TokenStream> {
+    let defaults = Options::load_defaults()?;
+    expand(input, &defaults)
+}
+
+/// Transform the input of the macro with the given default options.
+pub fn expand(input: TokenStream, defaults: &Options) -> syn::Result<TokenStream> {
+    let (options, dispatch) =
+        dispatch_arms::parse_with_options(input, defaults, |input, _| parse(input))?;
+    dispatch_arms::expand(&options, &dispatch)
+}
+
+/// Parse the input of the macro: `a, { "x" => X, _ => Y }, b, { 1 => Z }, |A, B| expr`.
+fn parse(input: ParseStream) -> syn::Result<Dispatch> {
+    let mut values = Vec::new();
+    while !input.peek(syn::Token![|]) {
+        values.push(parse_values(input)?);
+        input.parse::<syn::Token![,]>()?;
+    }
+
+    if values.is_empty() {
+        return Err(input.error("type_dispatch! expects at least one `value, { pattern => Type }`"));
+    }
+
+    let (names, body) = dispatch_arms::parse_closure(input, "type_dispatch", values.len())?;
+
+    Ok(Dispatch {
+        macro_name: "type_dispatch",
+        kind: Kind::Type,
+        values,
+        names,
+        body,
+    })
+}
+
+/// Parse `value, { pattern => Type, ... }`.
+fn parse_values(input: ParseStream) -> syn::Result<Values> {
+    let scrutinee = input.parse::<syn::Expr>()?;
+    input.parse::<syn::Token![,]>()?;
+
+    let content;
+    syn::braced!(content in input);
+
+    let mut arms = Vec::new();
+    let mut exhaustive = false;
+    while !content.is_empty() {
+        let pat = parse_pat(&content)?;
+        content.parse::<syn::Token![=>]>()?;
+        let ty = content.parse::<syn::Type>()?;
+
+        // arms after a wildcard are unreachable, just like in a `match`
+        exhaustive |= matches!(pat, syn::Pat::Wild(_));
+        arms.push((pat.into_token_stream(), ty.into_token_stream()));
+
+        if content.is_empty() {
+            break;
+        }
+        content.parse::<syn::Token![,]>()?;
+    }
+
+    if arms.is_empty() {
+        return Err(syn::Error::new_spanned(
+            scrutinee,
+            "type_dispatch! has no arms to dispatch on",
+        ));
+    }
+
+    Ok(Values {
+        scrutinee,
+        arms,
+        exhaustive,
+    })
+}
+
+/// Parse a pattern, which may have several alternatives.
+fn parse_pat(input: ParseStream) -> syn::Result<syn::Pat> {
+    let leading_vert = input.parse::<Option<syn::Token![|]>>()?;
+    let mut cases = syn::punctuated::Punctuated::new();
+    cases.push_value(input.parse::<syn::Pat>()?);
+    while input.peek(syn::Token![|]) && !input.peek(syn::Token![||]) {
+        cases.push_punct(input.parse::<syn::Token![|]>()?);
+        cases.push_value(input.parse::<syn::Pat>()?);
+    }
+
+    if leading_vert.is_none() && cases.len() == 1 {
+        return Ok(cases.pop().unwrap().into_value());
+    }
+
+    Ok(syn::Pat::Or(syn::PatOr {
+        attrs: Vec::new(),
+        leading_vert,
+        cases,
+    }))
+}
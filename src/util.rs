@@ -1,16 +1,170 @@
+use std::collections::BTreeSet;
+
+use proc_macro2::{TokenStream, TokenTree};
+use syn::visit::Visit;
+
 pub fn drain_filter<T, P: Fn(&T) -> bool>(vec: &mut Vec<T>, pred: P) -> Vec<T> {
-    let mut filtered = Vec::new();
-
-    let mut index = 0;
-    while index < vec.len() {
-        if pred(&vec[index]) {
-            filtered.push(vec.remove(index));
-        } else {
-            index += 1;
+    let (filtered, kept) = std::mem::take(vec).into_iter().partition(pred);
+    *vec = kept;
+
+    filtered
+}
+
+/// Returns whether `path` consists of exactly the segments `names`, as in
+/// `path_is(path, &["cain", "each"])`.
+pub fn path_is(path: &syn::Path, names: &[&str]) -> bool {
+    path.segments.len() == names.len()
+        && path
+            .segments
+            .iter()
+            .zip(names)
+            .all(|(segment, name)| segment.ident == *name)
+}
+
+/// Returns a block expression with `stmts`, without printing and parsing them again.
+pub fn block_expr(stmts: Vec<syn::Stmt>) -> syn::Expr {
+    syn::Expr::Block(syn::ExprBlock {
+        attrs: Vec::new(),
+        label: None,
+        block: syn::Block {
+            brace_token: Default::default(),
+            stmts,
+        },
+    })
+}
+
+/// Collects the identifiers that may refer to variables: single-segment paths, and all
+/// identifiers in macro arguments, including those that format strings capture.
+#[derive(Default)]
+pub struct UsedIdents {
+    pub idents: BTreeSet<String>,
+}
+
+impl<'ast> Visit<'ast> for UsedIdents {
+    fn visit_expr_path(&mut self, i: &'ast syn::ExprPath) {
+        if let Some(ident) = i.path.get_ident() {
+            self.idents.insert(ident.to_string());
         }
     }
 
-    filtered
+    fn visit_macro(&mut self, i: &'ast syn::Macro) {
+        fn visit_tokens(idents: &mut BTreeSet<String>, tokens: TokenStream) {
+            for token in tokens {
+                match token {
+                    TokenTree::Ident(ident) => {
+                        idents.insert(ident.to_string());
+                    }
+                    TokenTree::Literal(literal) => {
+                        // `{name}` in a format string, which may also be followed by a
+                        // format spec or be an escaped brace
+                        let literal = literal.to_string();
+                        for part in literal.split('{').skip(1) {
+                            let name = part
+                                .split(|c: char| !c.is_alphanumeric() && c != '_')
+                                .next()
+                                .unwrap_or("");
+                            if !name.is_empty() {
+                                idents.insert(name.to_string());
+                            }
+                        }
+                    }
+                    TokenTree::Group(group) => visit_tokens(idents, group.stream()),
+                    TokenTree::Punct(_) => (),
+                }
+            }
+        }
+
+        visit_tokens(&mut self.idents, i.tokens.clone());
+    }
+
+    fn visit_item(&mut self, _: &'ast syn::Item) {}
+}
+
+/// Returns the variables that are bound inside `init` and that shadow variables which are
+/// used by `rest`, if `rest` were moved into their scope.
+pub fn shadowing_bindings(init: &syn::Expr, rest: &[syn::Stmt]) -> Vec<syn::Ident> {
+    let mut bindings = BindingVisitor::default();
+    bindings.visit_expr(init);
+    if bindings.idents.is_empty() {
+        return Vec::new();
+    }
+
+    // stop looking for a variable once it is shadowed by the rest of the block
+    let mut used = BTreeSet::new();
+    let mut shadowed = BTreeSet::new();
+    for stmt in rest {
+        let mut stmt_idents = UsedIdents::default();
+        stmt_idents.visit_stmt(stmt);
+        used.extend(stmt_idents.idents.difference(&shadowed).cloned());
+
+        if let syn::Stmt::Local(local) = stmt {
+            let mut local_bindings = BindingVisitor::default();
+            local_bindings.visit_pat(&local.pat);
+            shadowed.extend(local_bindings.idents.iter().map(|ident| ident.to_string()));
+        }
+    }
+
+    bindings
+        .idents
+        .into_iter()
+        .filter(|ident| used.contains(&ident.to_string()))
+        .collect()
+}
+
+/// Collects the variables that are bound inside an expression.
+#[derive(Default)]
+struct BindingVisitor {
+    idents: Vec<syn::Ident>,
+}
+
+impl<'ast> Visit<'ast> for BindingVisitor {
+    fn visit_pat_ident(&mut self, i: &'ast syn::PatIdent) {
+        self.idents.push(i.ident.clone());
+        syn::visit::visit_pat_ident(self, i);
+    }
+
+    // the rest of the block is never moved into closures or items
+    fn visit_expr_closure(&mut self, _: &'ast syn::ExprClosure) {}
+    fn visit_item(&mut self, _: &'ast syn::Item) {}
+}
+
+/// Returns whether an expression never produces a value, because it is a `return`, `break`
+/// or `continue` expression or a `panic!`-like macro.
+pub fn is_diverging(expr: &syn::Expr) -> bool {
+    match expr {
+        syn::Expr::Return(_) | syn::Expr::Break(_) | syn::Expr::Continue(_) => true,
+        syn::Expr::Macro(expr_macro) => is_diverging_macro(&expr_macro.mac),
+        _ => false,
+    }
+}
+
+/// Returns whether an arm of a branch never produces a value, because it or the last
+/// statement of its block diverges.
+pub fn arm_diverges(expr: &syn::Expr) -> bool {
+    match expr {
+        syn::Expr::Block(expr_block) => block_diverges(&expr_block.block),
+        expr => is_diverging(expr),
+    }
+}
+
+pub fn block_diverges(block: &syn::Block) -> bool {
+    match block.stmts.last() {
+        Some(syn::Stmt::Expr(expr) | syn::Stmt::Semi(expr, _)) => arm_diverges(expr),
+        _ => false,
+    }
+}
+
+pub fn is_diverging_macro(mac: &syn::Macro) -> bool {
+    ["panic", "unreachable", "todo", "unimplemented"]
+        .iter()
+        .any(|name| mac.path.is_ident(name))
+}
+
+/// Parse the arguments of a macro invocation as a comma-separated list of expressions.
+pub fn parse_macro_args(
+    mac: &syn::Macro,
+) -> syn::Result<syn::punctuated::Punctuated<syn::Expr, syn::Token![,]>> {
+    mac.parse_body_with(syn::punctuated::Punctuated::parse_terminated)
 }
 
 #[cfg(not(test))]
@@ -19,6 +173,8 @@ static UNIQUE_IDENT_COUNTER: std::sync::atomic::AtomicUsize =
 
 #[cfg(test)]
 thread_local! {
+    // `const` initializers are not supported by the MSRV
+    #[allow(clippy::missing_const_for_thread_local)]
     static UNIQUE_IDENT_COUNTER: std::cell::Cell<usize> = std::cell::Cell::new(0);
 }
 
@@ -0,0 +1,250 @@
+//! Lint-style warnings for suspicious or wasteful uses of `cain!`.
+//!
+//! Stable Rust has no way for a procedural macro to emit a warning, so by default every
+//! warning is emitted as the use of a deprecated item, spanned at the offending code. With
+//! the `nightly` feature, warnings are emitted through `proc_macro::Diagnostic` instead.
+
+use std::cell::{Cell, RefCell};
+use std::iter::once;
+
+use proc_macro2::{Span, TokenStream, TokenTree};
+use syn::spanned::Spanned;
+use syn::visit::Visit;
+
+use crate::condition;
+use crate::placeholder::{get_placeholder_id, PlaceholderId};
+use crate::util::shadowing_bindings;
+
+/// The number of tokens in an expansion above which a warning is emitted.
+const SOFT_SIZE_LIMIT: usize = 20_000;
+
+/// The warning for a block without branch points, which does not apply to code that
+/// uses `cain!` for something else.
+pub const NO_BRANCH_POINTS: &str = "this `cain!` block has no branch points, and can be removed";
+
+#[derive(Clone, Debug)]
+pub struct Warning {
+    pub span: Span,
+    pub message: String,
+}
+
+/// The warnings collected during the transformation of a `cain!` block.
+#[derive(Default)]
+pub struct Warnings {
+    warnings: RefCell<Vec<Warning>>,
+    branch_points: Cell<usize>,
+}
+
+impl Warnings {
+    pub fn warn(&self, span: Span, message: impl Into<String>) {
+        self.warnings.borrow_mut().push(Warning {
+            span,
+            message: message.into(),
+        });
+    }
+
+    /// Record that the block has `count` more branch points.
+    pub fn add_branch_points(&self, count: usize) {
+        self.branch_points.set(self.branch_points.get() + count);
+    }
+
+    /// Add the warnings that need the whole expansion, and return all warnings.
+    pub fn finish(self, output: &TokenStream) -> Vec<Warning> {
+        if self.branch_points.get() == 0 {
+            self.warn(Span::call_site(), NO_BRANCH_POINTS);
+        }
+
+        let size = count_tokens(output.clone());
+        if size > SOFT_SIZE_LIMIT {
+            self.warn(
+                Span::call_site(),
+                format!(
+                    "this `cain!` block expands to {} tokens, consider setting `max_paths` or using dynamic dispatch",
+                    size
+                ),
+            );
+        }
+
+        self.warnings.into_inner()
+    }
+}
+
+fn count_tokens(tokens: TokenStream) -> usize {
+    tokens
+        .into_iter()
+        .map(|token| match token {
+            TokenTree::Group(group) => 1 + count_tokens(group.stream()),
+            _ => 1,
+        })
+        .sum()
+}
+
+/// Emit the warnings along with the output of the macro.
+#[cfg(not(feature = "nightly"))]
+pub fn emit(warnings: Vec<Warning>, output: TokenStream) -> TokenStream {
+    use quote::{quote, quote_spanned};
+
+    if warnings.is_empty() {
+        return output;
+    }
+
+    let warnings = warnings.into_iter().map(|Warning { span, message }| {
+        let name = quote_spanned! { span => __cain_warning };
+        quote! {
+            const _: () = {
+                #[deprecated(note = #message)]
+                #[allow(non_camel_case_types)]
+                struct __cain_warning;
+                let _ = #name;
+            };
+        }
+    });
+
+    quote! {
+        { #(#warnings)* #output }
+    }
+}
+
+/// Emit the warnings along with the output of the macro.
+#[cfg(feature = "nightly")]
+pub fn emit(warnings: Vec<Warning>, output: TokenStream) -> TokenStream {
+    for Warning { span, message } in warnings {
+        proc_macro::Diagnostic::spanned(span.unwrap(), proc_macro::Level::Warning, message).emit();
+    }
+
+    output
+}
+
+/// Warn about side effects that are evaluated after a branch point in the original code,
+/// but before it once the branch point has been hoisted out of `expr`.
+pub fn check_reordering(
+    warnings: &Warnings,
+    expr: &syn::Expr,
+    branches: &[(PlaceholderId, syn::Expr)],
+) {
+    for expr in Some(expr)
+        .into_iter()
+        .chain(branches.iter().flat_map(|(_, expr)| branch_parts(expr)))
+    {
+        let mut visitor = ReorderVisitor {
+            branches,
+            first_effect: None,
+            warned: false,
+        };
+        visitor.visit_expr(expr);
+
+        if let (true, Some(span)) = (visitor.warned, visitor.first_effect) {
+            warnings.warn(
+                span,
+                "this expression is now evaluated after a branch point that follows it, because `cain!` evaluates branch points first",
+            );
+        }
+    }
+}
+
+/// Returns the parts of a branch that are evaluated on their own: the scrutinee or
+/// condition, and the arms, whose branch points are only forked within the arm.
+fn branch_parts(branch: &syn::Expr) -> Vec<&syn::Expr> {
+    match branch {
+        syn::Expr::Match(match_expr) => once(&*match_expr.expr)
+            .chain(match_expr.arms.iter().map(|arm| &*arm.body))
+            .collect(),
+        syn::Expr::If(if_expr) => once(&*if_expr.cond)
+            .chain(if_expr.else_branch.iter().map(|(_, expr)| &**expr))
+            .collect(),
+        _ => vec![branch],
+    }
+}
+
+struct ReorderVisitor<'a> {
+    branches: &'a [(PlaceholderId, syn::Expr)],
+    first_effect: Option<Span>,
+    warned: bool,
+}
+
+impl<'a, 'ast> Visit<'ast> for ReorderVisitor<'a> {
+    fn visit_expr(&mut self, i: &'ast syn::Expr) {
+        // only branch points in the expression itself are hoisted
+        if let syn::Expr::Block(_) | syn::Expr::Closure(_) = i {
+            return;
+        }
+
+        if let syn::Expr::Path(expr_path) = i {
+            if let Some(id) = get_placeholder_id(&expr_path.path) {
+                let has_side_effects = self.branches.iter().any(|(branch_id, branch)| {
+                    *branch_id == id && branch_condition_has_side_effects(branch)
+                });
+
+                if has_side_effects && self.first_effect.is_some() {
+                    self.warned = true;
+                }
+                return;
+            }
+        }
+
+        syn::visit::visit_expr(self, i);
+
+        // the expression is completely evaluated at this point
+        if self.first_effect.is_none() && is_side_effect(i) {
+            self.first_effect = Some(i.span());
+        }
+    }
+
+    fn visit_block(&mut self, _: &'ast syn::Block) {}
+}
+
+fn branch_condition_has_side_effects(branch: &syn::Expr) -> bool {
+    match branch {
+        syn::Expr::Match(expr_match) => has_side_effects(&expr_match.expr),
+        syn::Expr::If(expr_if) => has_side_effects(&expr_if.cond),
+        _ => false,
+    }
+}
+
+fn is_side_effect(expr: &syn::Expr) -> bool {
+    match expr {
+        syn::Expr::Macro(expr_macro) => !condition::is_cfg_macro(&expr_macro.mac.path),
+        _ => matches!(
+            expr,
+            syn::Expr::Assign(_)
+                | syn::Expr::AssignOp(_)
+                | syn::Expr::Call(_)
+                | syn::Expr::MethodCall(_)
+        ),
+    }
+}
+
+fn has_side_effects(expr: &syn::Expr) -> bool {
+    struct SideEffectVisitor(bool);
+
+    impl<'ast> Visit<'ast> for SideEffectVisitor {
+        fn visit_expr(&mut self, i: &'ast syn::Expr) {
+            self.0 |= is_side_effect(i);
+            syn::visit::visit_expr(self, i);
+        }
+
+        fn visit_expr_closure(&mut self, _: &'ast syn::ExprClosure) {}
+    }
+
+    let mut visitor = SideEffectVisitor(false);
+    visitor.visit_expr(expr);
+    visitor.0
+}
+
+/// Warn about variables that are bound in a nested `cain!` block, and that shadow variables
+/// which are used by `rest`, since they keep the nested block from forking `rest`.
+///
+/// Returns whether any variable is shadowed.
+pub fn check_shadowing(warnings: &Warnings, init: &syn::Expr, rest: &[syn::Stmt]) -> bool {
+    let shadowing = shadowing_bindings(init, rest);
+    for ident in &shadowing {
+        warnings.warn(
+            ident.span(),
+            format!(
+                "this binding shadows `{}` in the rest of the `cain!` block, so the nested block cannot fork it",
+                ident
+            ),
+        );
+    }
+    !shadowing.is_empty()
+}
@@ -0,0 +1,131 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+#[cain::auto_enum]
+fn numbers(reverse: bool, limit: u32) -> impl DoubleEndedIterator<Item = u32> {
+    if limit == 0 {
+        return std::iter::empty();
+    }
+
+    let numbers = 0..limit;
+    if reverse {
+        numbers.rev()
+    } else {
+        numbers
+    }
+}
+
+#[test]
+fn auto_enum_iterator() {
+    assert_eq!(numbers(false, 3).collect::<Vec<_>>(), vec![0, 1, 2]);
+    assert_eq!(numbers(true, 3).collect::<Vec<_>>(), vec![2, 1, 0]);
+    assert_eq!(numbers(true, 3).rev().collect::<Vec<_>>(), vec![0, 1, 2]);
+    assert_eq!(numbers(true, 0).count(), 0);
+}
+
+#[cain::auto_enum]
+fn parse_list(input: &str) -> Result<impl Iterator<Item = u32> + '_, std::num::ParseIntError> {
+    let first = input.split(',').next().unwrap_or("").trim();
+    if first.is_empty() {
+        return Ok(None.into_iter());
+    }
+
+    let count: usize = first.parse()?;
+    let values = input
+        .split(',')
+        .skip(1)
+        .map(|value| value.trim().parse::<u32>().unwrap_or(0));
+    match count {
+        0 => Err("-".parse::<u32>().unwrap_err()),
+        _ => Ok(values.take(count)),
+    }
+}
+
+#[test]
+fn auto_enum_result() {
+    assert_eq!(
+        parse_list("2, 5, 6, 7").unwrap().collect::<Vec<_>>(),
+        vec![5, 6]
+    );
+    assert_eq!(parse_list("").unwrap().count(), 0);
+    assert!(parse_list("x, 1").is_err());
+    assert!(parse_list("0, 1").is_err());
+}
+
+#[cain::auto_enum]
+fn squares(limit: u32) -> impl Iterator<Item = u32> {
+    (0..limit).map(|n| n * n)
+}
+
+#[cain::auto_enum]
+fn evens(reverse: bool, limit: u32) -> impl Iterator<Item = u32> {
+    if reverse {
+        (0..limit).rev().filter(|n| n % 2 == 0)
+    } else {
+        (0..limit).filter(|n| n % 2 == 0)
+    }
+}
+
+#[test]
+fn auto_enum_single_value() {
+    assert_eq!(squares(4).collect::<Vec<_>>(), vec![0, 1, 4, 9]);
+}
+
+#[test]
+fn auto_enum_tail_if() {
+    assert_eq!(evens(false, 5).collect::<Vec<_>>(), vec![0, 2, 4]);
+    assert_eq!(evens(true, 5).collect::<Vec<_>>(), vec![4, 2, 0]);
+}
+
+#[cain::auto_enum]
+fn label(value: Option<u32>) -> impl Display {
+    let text = match value {
+        Some(n) => n,
+        None => "none",
+    };
+    text
+}
+
+#[test]
+fn auto_enum_display() {
+    assert_eq!(label(Some(4)).to_string(), "4");
+    assert_eq!(label(None).to_string(), "none");
+}
+
+#[cain::auto_enum]
+fn compute(fast: bool) -> impl Future<Output = u32> {
+    if fast {
+        std::future::ready(1)
+    } else {
+        async { 2 }
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = Pin::as_mut(&mut future).poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+#[test]
+fn auto_enum_future() {
+    assert_eq!(block_on(compute(true)), 1);
+    assert_eq!(block_on(compute(false)), 2);
+}
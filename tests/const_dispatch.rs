@@ -0,0 +1,44 @@
+use cain::const_dispatch;
+
+fn describe<const N: usize, const FAST: bool>(data: &[u8]) -> String {
+    format!("{} {} {}", N, FAST, data.len())
+}
+
+#[test]
+fn dispatch_range() {
+    let squares = (0..4)
+        .map(|n| const_dispatch!(n in 0..4, |N| [(); N].len() * N))
+        .collect::<Vec<_>>();
+
+    assert_eq!(squares, vec![0, 1, 4, 9]);
+}
+
+#[test]
+fn dispatch_multiple() {
+    let data = [1, 2, 3];
+
+    let descriptions = [(1, false), (4, true)]
+        .iter()
+        .map(|&(n, fast)| {
+            const_dispatch!(n in [1, 2, 4], fast in bool, |N, FAST| describe::<N, FAST>(&data))
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(descriptions, vec!["1 false 3", "4 true 3"]);
+}
+
+#[test]
+fn dispatch_negative() {
+    let offsets = (-2..=2)
+        .map(|d: i32| const_dispatch!(d in -2..=2, |D| D + 100))
+        .collect::<Vec<_>>();
+
+    assert_eq!(offsets, vec![98, 99, 100, 101, 102]);
+}
+
+#[test]
+#[should_panic(expected = "`n` is not one of the values of `const_dispatch!`")]
+fn dispatch_missing_value() {
+    let n = 5;
+    const_dispatch!(n in 0..4, |N| N);
+}
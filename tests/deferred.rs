@@ -0,0 +1,39 @@
+use std::fmt::Display;
+
+use cain::cain;
+
+fn describe(value: impl Display) -> String {
+    value.to_string()
+}
+
+fn connect(tls: bool, port: Option<u16>) -> Option<String> {
+    cain! {
+        let conn;
+        if tls {
+            conn = "tls";
+        } else {
+            conn = 80_u16;
+        }
+
+        let target;
+        if port == Some(0) {
+            return None;
+        }
+        match port {
+            Some(port) => target = port,
+            None => {
+                let fallback = "localhost";
+                target = fallback;
+            }
+        }
+
+        Some(format!("{} {}", describe(conn), describe(target)))
+    }
+}
+
+#[test]
+fn deferred_initialization() {
+    assert_eq!(connect(true, Some(8080)).unwrap(), "tls 8080");
+    assert_eq!(connect(false, None).unwrap(), "80 localhost");
+    assert_eq!(connect(false, Some(0)), None);
+}
@@ -0,0 +1,122 @@
+use cain::{cain, Dispatch};
+
+struct Circle(u32);
+struct Square(u32);
+
+impl Circle {
+    fn name(&self) -> &'static str {
+        "circle"
+    }
+
+    fn area(&self) -> u32 {
+        3 * self.0 * self.0
+    }
+}
+
+impl Square {
+    fn name(&self) -> &'static str {
+        "square"
+    }
+
+    fn area(&self) -> u32 {
+        self.0 * self.0
+    }
+}
+
+#[derive(Dispatch)]
+enum Shape {
+    Circle(Circle),
+    Square(Square),
+}
+
+#[test]
+fn dispatch_macro() {
+    let areas = [Shape::Circle(Circle(1)), Shape::Square(Square(2))]
+        .iter()
+        .map(|shape| shape_dispatch!(shape, |inner| inner.area()))
+        .collect::<Vec<_>>();
+
+    assert_eq!(areas, vec![3, 4]);
+}
+
+#[test]
+fn dispatch_in_cain() {
+    fn describe(shape: &Shape) -> String {
+        cain! {
+            #![cain(dispatch_macros(shape_dispatch))]
+            let inner = shape_dispatch!(shape);
+            let area = inner.area();
+            format!("{} {}", inner.name(), area)
+        }
+    }
+
+    let descriptions = [Shape::Circle(Circle(1)), Shape::Square(Square(2))]
+        .iter()
+        .map(describe)
+        .collect::<Vec<_>>();
+
+    assert_eq!(descriptions, vec!["circle 3", "square 4"]);
+}
+
+#[test]
+fn dispatch_declared_in_cain() {
+    fn area(size: u32, square: bool) -> u32 {
+        cain! {
+            #[derive(Dispatch)]
+            enum Owned {
+                Circle(Circle),
+                Square(Square),
+            }
+
+            let owned = match square {
+                true => Owned::Square(Square(size)),
+                false => Owned::Circle(Circle(size)),
+            };
+            let inner = owned_dispatch!(owned);
+            inner.area()
+        }
+    }
+
+    assert_eq!(area(2, false), 12);
+    assert_eq!(area(2, true), 4);
+}
+
+#[test]
+fn dispatch_let_else() {
+    fn area(shape: &Option<Shape>) -> Option<u32> {
+        cain! {
+            #![cain(dispatch_macros(shape_dispatch))]
+            let Some(shape) = shape else {
+                return None;
+            };
+            #[allow(unused_mut)]
+            let mut inner = shape_dispatch!(shape);
+            Some(inner.area())
+        }
+    }
+
+    assert_eq!(area(&Some(Shape::Square(Square(3)))), Some(9));
+    assert_eq!(area(&None), None);
+}
+
+#[test]
+fn unrelated_dispatch_macro() {
+    macro_rules! double_dispatch {
+        ($value:expr) => {
+            $value * 2
+        };
+    }
+
+    let values = [false, true]
+        .iter()
+        .map(|&large| {
+            cain! {
+                let base = if large { 20 } else { 1 };
+                let value = double_dispatch!(base);
+                value + 1
+            }
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(values, vec![3, 41]);
+}
@@ -1,3 +1,6 @@
+// the `_ => panic!()` arms are expanded into continuations that can never be reached
+#![allow(unreachable_code, unused_variables, clippy::diverging_sub_expression)]
+
 use cain::cain;
 
 macro_rules! test_cain {
@@ -61,4 +64,221 @@ test_cain! {
             })
             .collect::<Vec<_>>()
     } => { vec!["true0", "1!", "abc0", "true!", "10", "abc!"] },
+
+    loop_diverging_breaks: {
+        [0, 1, 2].into_iter()
+            .map(|n| {
+                cain!{
+                    let mut i = 0;
+                    let x = loop {
+                        if i == n {
+                            if n % 2 == 0 {
+                                break i;
+                            } else {
+                                break "odd";
+                            }
+                        }
+                        i += 1;
+                    };
+
+                    x.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+    } => { vec!["0", "odd", "2"] },
+
+    impl_trait_annotation: {
+        [0, 1].into_iter()
+            .map(|n| {
+                cain!{
+                    let x: impl std::fmt::Display = match n {
+                        0 => 1.5,
+                        _ => "abc",
+                    };
+
+                    x.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+    } => { vec!["1.5", "abc"] },
+
+    dyn_dispatch: {
+        [0, 1, 2].into_iter()
+            .map(|n| {
+                cain!{
+                    dyn std::fmt::Display;
+
+                    let x = match n {
+                        0 => true,
+                        1 => 1,
+                        _ => "abc",
+                    };
+
+                    let y = if n == 0 { 0.5 } else { 1 };
+
+                    format!("{}{}", x, y)
+                }
+            })
+            .collect::<Vec<_>>()
+    } => { vec!["true0.5", "11", "abc1"] },
+
+    hybrid_dispatch: {
+        [0, 1, 2, 3].into_iter()
+            .map(|n| {
+                cain!{
+                    dyn std::fmt::Display, max_paths = 2;
+
+                    let x = match n % 2 {
+                        0 => true,
+                        _ => "abc",
+                    };
+
+                    let y = match n / 2 {
+                        0 => 0.5,
+                        _ => 1,
+                    };
+
+                    format!("{}{}", x, y)
+                }
+            })
+            .collect::<Vec<_>>()
+    } => { vec!["true0.5", "abc0.5", "true1", "abc1"] },
+
+    hybrid_enum_fallback: {
+        [0, 1, 2, 3].into_iter()
+            .map(|n| {
+                cain!{
+                    #![cain(strategy = "hybrid", fallback = "enum")]
+                    #![cain(dispatch = "dyn std::fmt::Display", max_paths = 2)]
+
+                    let x = match n % 2 {
+                        0 => true,
+                        _ => "abc",
+                    };
+
+                    let y = match n / 2 {
+                        0 => 0.5,
+                        _ => 1,
+                    };
+
+                    format!("{}{}", x, y)
+                }
+            })
+            .collect::<Vec<_>>()
+    } => { vec!["true0.5", "abc0.5", "true1", "abc1"] },
+
+    inner_attribute_options: {
+        [0, 1, 2].into_iter()
+            .map(|n| {
+                cain!{
+                    #![cain(strategy = "hybrid", dispatch = "dyn std::fmt::Display", max_paths = 2)]
+                    #![cain(transparent_macros(format))]
+
+                    let x = format!("<{}>", match n {
+                        0 => true,
+                        1 => 1,
+                        _ => "abc",
+                    });
+
+                    let y = match n {
+                        0 => 0.5,
+                        _ => 1,
+                    };
+
+                    format!("{}{}", x, y)
+                }
+            })
+            .collect::<Vec<_>>()
+    } => { vec!["<true>0.5", "<1>1", "<abc>1"] },
+
+    branch_paths: {
+        [(0, 1), (1, 2), (2, 3)].into_iter()
+            .map(|(n, m)| {
+                cain!{
+                    let x = match n {
+                        0 => true,
+                        1 => 1,
+                        _ => "abc",
+                    };
+
+                    let y = if m > 2 { 0.5 } else { 1 };
+
+                    format!("{}{}: {}", x, y, cain::path!())
+                }
+            })
+            .collect::<Vec<_>>()
+    } => {
+        vec![
+            "true1: n => 0, m > 2 => false",
+            "11: n => 1, m > 2 => false",
+            "abc0.5: n => _, m > 2 => true",
+        ]
+    },
+
+    branch_path_hook: {
+        use std::cell::RefCell;
+
+        thread_local! {
+            // `const` initializers are not supported by the MSRV
+            #[allow(clippy::missing_const_for_thread_local)]
+            static PATHS: RefCell<Vec<&'static str>> = RefCell::new(Vec::new());
+        }
+
+        fn record(path: &'static str) {
+            PATHS.with(|paths| paths.borrow_mut().push(path));
+        }
+
+        for n in [1, 2] {
+            cain!{
+                #![cain(path_hook = "record")]
+
+                let x = match n {
+                    1 => "one",
+                    _ => "other",
+                };
+
+                let mut i = 0;
+                let y = loop {
+                    i += n;
+                    if i == 2 {
+                        break 1;
+                    }
+                    if i > 2 {
+                        break 2;
+                    }
+                };
+
+                assert!(!cain::path!().is_empty());
+            }
+        }
+
+        PATHS.with(|paths| paths.borrow().clone())
+    } => {
+        vec![
+            "n => 1",
+            "n => 1, loop => break 1",
+            "n => _",
+            "n => _, loop => break 1",
+        ]
+    },
+}
+
+fn describe(n: u32) -> String {
+    cain! {
+        dyn std::fmt::Display;
+        let x = match n {
+            0 => 1,
+            1 => "one",
+            _ => return String::from("ret"),
+        };
+
+        x.to_string()
+    }
+}
+
+#[test]
+fn dyn_diverging_branch() {
+    assert_eq!(describe(0), "1");
+    assert_eq!(describe(1), "one");
+    assert_eq!(describe(2), "ret");
 }
@@ -0,0 +1,78 @@
+use std::fmt::Debug;
+
+use cain::cain;
+
+fn describe<T: Debug>(value: T) -> String {
+    format!("{:?}", value)
+}
+
+#[test]
+fn each_value() {
+    let mut seen = Vec::new();
+
+    cain! {
+        for value in cain::each!(1_u8, "two", 3.5_f64, Some('4')) {
+            seen.push(describe(value));
+        }
+    }
+
+    assert_eq!(seen, vec!["1", "\"two\"", "3.5", "Some('4')"]);
+}
+
+#[test]
+fn each_continue_and_break() {
+    let mut seen = Vec::new();
+
+    cain! {
+        for value in cain::each!(1_u8, "skip", 2_i64, "stop", 3_u16) {
+            let text = describe(value);
+            if text == "\"skip\"" {
+                continue;
+            }
+            if text == "\"stop\"" {
+                break;
+            }
+            seen.push(text);
+        }
+    }
+
+    assert_eq!(seen, vec!["1", "2"]);
+}
+
+#[test]
+fn each_tuple_with_label() {
+    let mut seen = Vec::new();
+
+    cain! {
+        'values: for (count, value) in #[cain::each] ((1, vec![1_u8]), (3, ['a', 'b'])) {
+            for index in 0..count {
+                if index == 2 {
+                    continue 'values;
+                }
+
+                // breaks out of the inner loop only
+                if index == 5 {
+                    break;
+                }
+                seen.push(format!("{} {:?}", index, value));
+            }
+        }
+    }
+
+    assert_eq!(seen, vec!["0 [1]", "0 ['a', 'b']", "1 ['a', 'b']"]);
+}
+
+#[test]
+fn each_values_are_evaluated_lazily() {
+    let mut evaluated = Vec::new();
+
+    cain! {
+        for value in cain::each!({ evaluated.push(1); 1 }, { evaluated.push(2); "two" }) {
+            if describe(value) == "1" {
+                break;
+            }
+        }
+    }
+
+    assert_eq!(evaluated, vec![1]);
+}
@@ -0,0 +1,44 @@
+use std::fmt::Display;
+
+use cain::cain;
+
+fn describe(input: &str) -> Option<String> {
+    cain! {
+        #![cain(max_paths = 64, strategy = "enum", transparent_macros(log, trace))]
+        #![cain(dispatch = "dyn Display")]
+        let value = match (input.is_empty(), input.parse::<i64>()) {
+            (true, _) => return None,
+            (false, Ok(n)) => n,
+            (false, Err(_)) => input,
+        };
+        let shown: &dyn Display = &value;
+        Some(format!("<{}>", shown))
+    }
+}
+
+#[test]
+fn enum_value() {
+    assert_eq!(describe("12").unwrap(), "<12>");
+    assert_eq!(describe("abc").unwrap(), "<abc>");
+    assert_eq!(describe(""), None);
+}
+
+fn ends(limit: u32, reverse: bool) -> (Option<u32>, Option<u32>) {
+    cain! {
+        #![cain(strategy = "enum", dispatch = "&mut dyn DoubleEndedIterator<Item = u32>")]
+        let values = if reverse {
+            (0..limit).rev()
+        } else {
+            0..limit
+        };
+        let first = values.next();
+        (first, values.next_back())
+    }
+}
+
+#[test]
+fn enum_mut_value() {
+    assert_eq!(ends(5, false), (Some(0), Some(4)));
+    assert_eq!(ends(5, true), (Some(4), Some(0)));
+    assert_eq!(ends(1, true), (Some(0), None));
+}
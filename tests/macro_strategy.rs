@@ -0,0 +1,63 @@
+use std::fmt::Debug;
+
+use cain::cain;
+
+fn describe<T: Debug>(value: T) -> String {
+    format!("{:?}", value)
+}
+
+fn parse(input: &str) -> Result<String, String> {
+    cain! {
+        #![cain(strategy = "macro")]
+        let prefix = format!("{}:", input);
+        let number = match input.parse::<i64>() {
+            Ok(n) => n,
+            Err(_) => input.len(),
+        };
+        let sign = if input.starts_with('-') { '-' } else { 1_u8 };
+        if input.is_empty() {
+            return Err("empty".to_string());
+        }
+        Ok(format!("{} {} {}", prefix, describe(number), describe(sign)))
+    }
+}
+
+#[test]
+fn macro_continuation() {
+    assert_eq!(parse("12").unwrap(), "12: 12 1");
+    assert_eq!(parse("-x").unwrap(), "-x: 2 '-'");
+    assert_eq!(parse("").unwrap_err(), "empty");
+}
+
+struct Counter {
+    step: u32,
+}
+
+impl Counter {
+    fn count(&self, limit: u32) -> Vec<String> {
+        let mut seen = Vec::new();
+
+        'outer: for n in 0..limit {
+            cain! {
+                #![cain(strategy = "macro")]
+                let value = if n % 2 == 0 { n * self.step } else { 0 };
+                let text = match value {
+                    0 => describe(value),
+                    _ => describe(Some(value)),
+                };
+                if n == 3 {
+                    break 'outer;
+                }
+                seen.push(text);
+            }
+        }
+
+        seen
+    }
+}
+
+#[test]
+fn macro_continuation_control_flow() {
+    let counter = Counter { step: 5 };
+    assert_eq!(counter.count(10), vec!["0", "0", "Some(10)"]);
+}
@@ -0,0 +1,48 @@
+use std::fmt::Display;
+
+use cain::cain;
+
+fn describe(value: impl Display) -> String {
+    value.to_string()
+}
+
+fn label(x: Option<u32>, verbose: bool) -> String {
+    let mut calls = 0;
+    let text = cain! {
+        // the arms already have the same type
+        let count = #[cain::skip] match x {
+            Some(n) => n * 2,
+            None => 0,
+        };
+        let value = if verbose { describe(count) } else { count };
+        calls += 1;
+        format!("{}", value)
+    };
+    assert_eq!(calls, 1);
+    text
+}
+
+#[test]
+fn skip_marker() {
+    assert_eq!(label(Some(3), true), "6");
+    assert_eq!(label(None, false), "0");
+}
+
+fn explicit(x: Option<u32>, flag: bool) -> String {
+    cain! {
+        #![cain(explicit)]
+        let scale = if flag { 10 } else { 1 };
+        let value = #[cain::fork] match x {
+            Some(n) => n * scale,
+            None => "none",
+        };
+        describe(value)
+    }
+}
+
+#[test]
+fn explicit_mode() {
+    assert_eq!(explicit(Some(2), true), "20");
+    assert_eq!(explicit(Some(2), false), "2");
+    assert_eq!(explicit(None, true), "none");
+}
@@ -0,0 +1,111 @@
+use std::fmt::Debug;
+
+use cain::cain;
+
+fn describe<T: Debug>(value: T) -> String {
+    format!("{:?}", value)
+}
+
+fn parse(input: &str) -> String {
+    cain! {
+        let value = cain! {
+            let number = input.parse::<i64>();
+            match number {
+                Ok(n) => n,
+                Err(_) => input.len(),
+            }
+        };
+        describe(value)
+    }
+}
+
+#[test]
+fn nested_result_forks_continuation() {
+    assert_eq!(parse("12"), "12");
+    assert_eq!(parse("twelve"), "6");
+}
+
+// the shadowing binding is warned about through a deprecated item
+#[test]
+#[allow(deprecated)]
+fn nested_bindings_keep_their_scope() {
+    let number = 5;
+    let output = cain! {
+        let value = cain! {
+            let number = "shadow";
+            let size = if number.len() > 3 { 6 } else { 7 };
+            size * 2
+        };
+        format!("{} {}", value, number)
+    };
+
+    assert_eq!(output, "12 5");
+}
+
+macro_rules! with_flags {
+    ($flag:ident => $body:expr) => {
+        [true, false]
+            .iter()
+            .map(|&$flag| cain! { $body })
+            .collect::<Vec<_>>()
+    };
+}
+
+#[test]
+fn nested_in_macro_arguments() {
+    let outputs = with_flags!(flag => {
+        let value = cain! { if flag { 1_u8 } else { "two" } };
+        let other = cain! {
+            let other = if flag { 'x' } else { 'y' };
+            describe(other)
+        };
+        format!("{} {}", value, other)
+    });
+
+    assert_eq!(outputs, vec!["1 'x'", "two 'y'"]);
+}
+
+#[test]
+fn nested_paths() {
+    let paths = [1, 2]
+        .iter()
+        .map(|&n| {
+            cain! {
+                let a = match n {
+                    1 => 1_u8,
+                    _ => 2_u8,
+                };
+                let b = cain! {
+                    let b = if a > 1 { "big" } else { "small" };
+                    b
+                };
+                format!("{} {}", b, cain::path!())
+            }
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        paths,
+        vec!["small n => 1, a > 1 => false", "big n => _, a > 1 => true"]
+    );
+}
+
+#[test]
+fn nested_statement() {
+    let mut seen = Vec::new();
+
+    for n in 0..2 {
+        cain! {
+            let tag = if n == 0 { "zero" } else { "one" };
+            cain! {
+                let value = match tag.len() {
+                    4 => 4_u8,
+                    _ => 3_u8,
+                };
+                seen.push(format!("{} {}", tag, value));
+            }
+        }
+    }
+
+    assert_eq!(seen, vec!["zero 4", "one 3"]);
+}
@@ -1,3 +1,19 @@
+// the test inputs exercise patterns that `cain!` has to handle, not idiomatic code
+#![allow(
+    unused_imports,
+    unused_mut,
+    irrefutable_let_patterns,
+    clippy::let_and_return,
+    clippy::let_unit_value,
+    clippy::needless_borrow,
+    clippy::manual_unwrap_or,
+    clippy::manual_unwrap_or_default,
+    clippy::needless_else,
+    clippy::nonminimal_bool,
+    clippy::redundant_pattern_matching,
+    clippy::single_match
+)]
+
 /// A collection of tests that ensure that the `cain!` macro does not change the
 /// behavior of already valid Rust code.
 use cain::cain;
@@ -183,4 +199,196 @@ test_no_behavior_change! {cain!;
             }
         }
     },
+
+    repeated_match: {
+        (0..4)
+            .map(|n| {
+                cain! {
+                    let a = match n {
+                        0 => 1,
+                        1 | 2 => 2,
+                        _ => 3,
+                    };
+                    let b = match n {
+                        2 => "two",
+                        0 => "zero",
+                        _ => "other",
+                    };
+                    (a, b)
+                }
+            })
+            .collect::<Vec<_>>()
+    },
+    repeated_match_after_assignment: {
+        (0..3)
+            .map(|mut n| {
+                cain! {
+                    let a = match n {
+                        0 => 1,
+                        _ => 2,
+                    };
+                    n += 1;
+                    let b = match n {
+                        1 => "one",
+                        _ => "other",
+                    };
+                    (a, b)
+                }
+            })
+            .collect::<Vec<_>>()
+    },
+
+    constant_conditions: {
+        (0..3)
+            .map(|n| {
+                cain! {
+                    const DOUBLE: bool = true;
+                    let a = if DOUBLE && !false { n * 2 } else { n };
+                    let b = if cfg!(debug_assertions) || cfg!(test) { "checked" } else { "unchecked" };
+                    let c = if n > 0 { 1 } else { 0 };
+                    (a, b, c)
+                }
+            })
+            .collect::<Vec<_>>()
+    },
+
+    shadowed_binding: {
+        (0..3_u32)
+            .map(|n| {
+                let r = 100;
+                cain! {
+                    let a = match n.checked_sub(1) {
+                        Some(r) => r * 10,
+                        None => 0,
+                    };
+                    let b = if let Some(r) = n.checked_sub(2) { r } else { 7 };
+                    (a, b, r)
+                }
+            })
+            .collect::<Vec<_>>()
+    },
+
+    shadowed_binding_in_format_string: {
+        (0..2_u32)
+            .map(|n| {
+                let r = "outer";
+                cain! {
+                    let a = match n.checked_sub(1) {
+                        Some(r) => r,
+                        None => 5,
+                    };
+                    format!("{r} {}", a)
+                }
+            })
+            .collect::<Vec<_>>()
+    },
+
+    shadowed_binding_in_earlier_branch: {
+        (0..4_u32)
+            .map(|n| {
+                let r = 100;
+                cain! {
+                    let a = (
+                        match n % 2 {
+                            0 => r,
+                            _ => 1,
+                        },
+                        match n.checked_sub(2) {
+                            Some(r) => r,
+                            None => 0,
+                        },
+                    );
+                    a
+                }
+            })
+            .collect::<Vec<_>>()
+    },
+
+    constant_pattern: {
+        const LIMIT: u32 = 2;
+
+        (0..4_u32)
+            .map(|n| {
+                cain! {
+                    let a = match n {
+                        LIMIT => 0,
+                        other => other * 10,
+                    };
+                    a
+                }
+            })
+            .collect::<Vec<_>>()
+    },
+
+    deferred_initialization_drop_order: {
+        use std::cell::RefCell;
+
+        struct Guard<'a>(&'static str, &'a RefCell<Vec<&'static str>>);
+
+        impl Drop for Guard<'_> {
+            fn drop(&mut self) {
+                self.1.borrow_mut().push(self.0);
+            }
+        }
+
+        let dropped = RefCell::new(Vec::new());
+        for flag in [false, true] {
+            cain! {
+                let log;
+                let _guard = Guard("guard", &dropped);
+                log = Guard("log", &dropped);
+                let name = if flag { "yes" } else { "no" };
+                dropped.borrow_mut().push(name);
+                drop(log);
+            }
+        }
+        dropped.into_inner()
+    },
+
+    nested_branches_in_arms: {
+        let mut checked = Vec::new();
+        let mut check = |name: &'static str, value: u32| {
+            checked.push(name);
+            value
+        };
+        let mut values = Vec::new();
+        for (x, y, z) in [(0, 0, false), (1, 0, true), (1, 1, false)] {
+            cain! {
+                let a = match check("x", x) {
+                    0 => match check("y", y) {
+                        0 => 1,
+                        _ => 2,
+                    },
+                    _ => if z { 3 } else if check("z", 0) == 0 { 4 } else { 5 },
+                };
+                values.push(a);
+            }
+        }
+        (values, checked)
+    },
+
+    constants_with_the_same_value: {
+        struct Limits;
+
+        impl Limits {
+            const LOW: u32 = 0;
+            const ZERO: u32 = 0;
+        }
+
+        (0..2_u32)
+            .map(|x| {
+                cain! {
+                    let a = match x {
+                        Limits::LOW => 1,
+                        _ => 2,
+                    };
+                    let b = match x {
+                        Limits::ZERO => "zero",
+                        _ => "other",
+                    };
+                    (a, b)
+                }
+            })
+            .collect::<Vec<_>>()
+    },
 }
@@ -0,0 +1,47 @@
+use cain::cain;
+
+fn classify(x: Option<u32>, y: Result<u32, String>) -> (u32, u32) {
+    cain! {
+        #![cain(panic_free)]
+        let a = 1;
+        let b = match x {
+            Some(mut r) => {
+                r += a;
+                r
+            }
+            Option::None => a,
+        };
+        let c = if let Ok(s) = y { s } else { b };
+        let r = b * 10;
+        let s = c * 10;
+        (r, s)
+    }
+}
+
+#[test]
+fn panic_free_shadowing() {
+    assert_eq!(classify(Some(2), Ok(5)), (30, 50));
+    assert_eq!(classify(None, Ok(5)), (10, 50));
+    assert_eq!(classify(Some(4), Err("no".to_string())), (50, 50));
+    assert_eq!(classify(None, Err("no".to_string())), (10, 10));
+}
+
+fn narrowed(x: u8) -> &'static str {
+    cain! {
+        #![cain(panic_free)]
+        let kind = match x {
+            0 => "zero",
+            _ => "other",
+        };
+        match x {
+            0 => kind,
+            _ => "not zero",
+        }
+    }
+}
+
+#[test]
+fn panic_free_no_narrowing() {
+    assert_eq!(narrowed(0), "zero");
+    assert_eq!(narrowed(3), "not zero");
+}
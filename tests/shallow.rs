@@ -0,0 +1,51 @@
+use std::fmt::Display;
+
+use cain::cain;
+
+fn describe(value: impl Display) -> String {
+    value.to_string()
+}
+
+fn shallow(x: Option<u32>, flag: bool) -> String {
+    cain! {
+        #![cain(shallow)]
+        // only the whole initializer is a branch point, the argument is left as written
+        let count = describe(match x {
+            Some(n) => n * 2,
+            None => 0,
+        });
+        let value = if flag { describe(count) } else { count.len() };
+        describe(value)
+    }
+}
+
+#[test]
+fn shallow_mode() {
+    assert_eq!(shallow(Some(12), true), "24");
+    assert_eq!(shallow(Some(12), false), "2");
+    assert_eq!(shallow(None, true), "0");
+}
+
+fn classify(n: i32, wide: bool) -> String {
+    cain! {
+        #![cain(shallow)]
+        // the `else if` branch is part of the initializer, so it forks as well
+        let unit = if n < 0 { "negative" } else if wide { 'w' } else { 0 };
+        // the nested `match` is left alone, so its guard is fine
+        let size = match n {
+            0 => 0,
+            n => match n.abs() {
+                m if m > 9 => 2,
+                _ => 1,
+            },
+        };
+        format!("{}{}", unit, size)
+    }
+}
+
+#[test]
+fn shallow_else_if() {
+    assert_eq!(classify(-12, true), "negative2");
+    assert_eq!(classify(3, true), "w1");
+    assert_eq!(classify(0, false), "00");
+}
@@ -0,0 +1,67 @@
+use std::fmt::Debug;
+
+use cain::cain;
+
+fn describe(n: i32) -> String {
+    cain! {
+        shared T: Debug -> String;
+        let prefix: String = format!("{}:", n);
+        let value = match n {
+            0 => n as u8,
+            1 => "one",
+            _ => i64::from(n) * 2,
+        };
+        format!("{} {:?} {}", prefix, value, std::mem::size_of::<T>())
+    }
+}
+
+#[test]
+fn shared_continuation() {
+    assert_eq!(describe(0), "0: 0 1");
+    assert_eq!(describe(1), "1: \"one\" 16");
+    assert_eq!(describe(2), "2: 4 8");
+}
+
+#[test]
+fn shared_unit_continuation() {
+    let mut seen = Vec::new();
+
+    for n in 0..3 {
+        cain! {
+            shared T: Debug;
+            let mut lines: Vec<String> = Vec::new();
+            let value = if n % 2 == 0 { Some(n) } else { None::<char> };
+            lines.push(format!("{:?}", value));
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+        seen.push(n);
+    }
+
+    assert_eq!(seen, vec![0, 1, 2]);
+}
+
+#[test]
+fn shared_loop_exits() {
+    let results = (0..3)
+        .map(|n| {
+            cain! {
+                shared T: Debug -> String;
+                let mut i = 0;
+                let value = loop {
+                    i += 1;
+                    if i == 2 {
+                        break 'x';
+                    }
+                    if i > n {
+                        break "small";
+                    }
+                };
+                format!("{:?}", value)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(results, vec!["\"small\"", "'x'", "'x'"]);
+}
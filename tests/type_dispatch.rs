@@ -0,0 +1,66 @@
+use cain::type_dispatch;
+
+trait Codec {
+    const NAME: &'static str;
+}
+
+struct Json;
+struct Cbor;
+struct Raw;
+
+impl Codec for Json {
+    const NAME: &'static str = "json";
+}
+
+impl Codec for Cbor {
+    const NAME: &'static str = "cbor";
+}
+
+impl Codec for Raw {
+    const NAME: &'static str = "raw";
+}
+
+fn describe<C: Codec, W>() -> String {
+    format!("{} {}", C::NAME, std::mem::size_of::<W>())
+}
+
+#[test]
+fn dispatch_tags() {
+    let names = ["json", "cbor", "cb"]
+        .iter()
+        .map(|format| type_dispatch!(*format, { "json" => Json, "cbor" | "cb" => Cbor }, |C| C::NAME))
+        .collect::<Vec<_>>();
+
+    assert_eq!(names, vec!["json", "cbor", "cbor"]);
+}
+
+#[test]
+fn dispatch_multiple_with_fallback() {
+    let descriptions = [("json", 4), ("xml", 8), ("cbor", 2)]
+        .iter()
+        .map(|&(format, width)| {
+            type_dispatch!(
+                format, { "json" => Json, "cbor" => Cbor, _ => Raw },
+                width, { 4 => u32, 8 => u64, _ => [u8; 3] },
+                |C, W| describe::<C, W>()
+            )
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(descriptions, vec!["json 4", "raw 8", "cbor 3"]);
+}
+
+#[test]
+fn dispatch_qualified_type() {
+    let width = 1;
+    let value = type_dispatch!(width, { 1 => Vec<u8>, 2 => Vec<u16> }, |V| V::new().len());
+
+    assert_eq!(value, 0);
+}
+
+#[test]
+#[should_panic(expected = "`format` is not one of the values of `type_dispatch!`")]
+fn dispatch_missing_tag() {
+    let format = "xml";
+    type_dispatch!(format, { "json" => Json }, |C| C::NAME);
+}